@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+use crate::error::AppError;
+use crate::ripgrep::{RipgrepSearcher, SearchOptions};
+
+/// Markers searched for when a request names none
+pub const DEFAULT_MARKERS: &[&str] = &["TODO", "FIXME", "HACK", "XXX", "NOTE"];
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AnnotationOptions {
+    /// Markers to look for; defaults to `DEFAULT_MARKERS`
+    #[serde(default)]
+    pub markers: Vec<String>,
+    
+    /// Relative path within the root directory
+    #[serde(default)]
+    pub path: String,
+    
+    /// File types to include
+    #[serde(default)]
+    pub file_types: Vec<String>,
+}
+
+/// One parsed annotation comment, e.g. `TODO(alice): fix this`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Annotation {
+    pub marker: String,
+    
+    /// Name in parentheses right after the marker, if any
+    pub author: Option<String>,
+    
+    pub message: String,
+    pub path: String,
+    pub line: Option<u64>,
+}
+
+impl RipgrepSearcher {
+    /// Find marker comments and split each into marker, author and message
+    pub async fn find_annotations(&self, options: &AnnotationOptions) -> Result<Vec<Annotation>, AppError> {
+        let markers: Vec<String> = if options.markers.is_empty() {
+            DEFAULT_MARKERS.iter().map(|m| m.to_string()).collect()
+        } else {
+            options.markers.clone()
+        };
+        let alternatives: Vec<String> = markers.iter().map(|m| escape_regex(m)).collect();
+        
+        // Markers are conventionally upper case, so match them exactly
+        let search = SearchOptions {
+            path: options.path.clone(),
+            file_types: options.file_types.clone(),
+            case_sensitive: true,
+            highlight_matches: true,
+            ..SearchOptions::new(format!(r"\b(?:{})\b", alternatives.join("|")))
+        };
+        let result = self.search(&search).await?;
+        
+        let annotations = result
+            .structured_matches
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|m| {
+                let marker = m.matched_text?;
+                let rest = m.full_line.get(m.match_end?..)?;
+                let (author, message) = parse_annotation(rest);
+                Some(Annotation {
+                    marker,
+                    author,
+                    message,
+                    path: m.path,
+                    line: m.line_number,
+                })
+            })
+            .collect();
+        
+        Ok(annotations)
+    }
+}
+
+/// Split the text after a marker into an optional `(author)` and the
+/// message, dropping the `:` separator and trailing comment closers
+fn parse_annotation(rest: &str) -> (Option<String>, String) {
+    let mut rest = rest;
+    let mut author = None;
+    
+    if let Some(inner) = rest.strip_prefix('(') {
+        if let Some((name, after)) = inner.split_once(')') {
+            let name = name.trim();
+            author = (!name.is_empty()).then(|| name.to_string());
+            rest = after;
+        }
+    }
+    
+    let message = rest
+        .trim_start_matches([':', '-'])
+        .trim()
+        .trim_end_matches("*/")
+        .trim_end_matches("-->")
+        .trim();
+    (author, message.to_string())
+}
+
+/// Escape regex metacharacters so a marker matches literally
+pub(crate) fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$#&-~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    
+    #[tokio::test]
+    async fn test_find_annotations() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(
+            temp_dir.path().join("lib.rs"),
+            "// TODO(alice): fix this\nfn main() {}\n/* FIXME handle errors */\n// todo: lower case is not a marker\n",
+        ).unwrap();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = AnnotationOptions {
+            markers: Vec::new(),
+            path: String::new(),
+            file_types: Vec::new(),
+        };
+        let annotations = searcher.find_annotations(&options).await.unwrap();
+        assert_eq!(annotations.len(), 2);
+        
+        let todo = &annotations[0];
+        assert_eq!(todo.marker, "TODO");
+        assert_eq!(todo.author.as_deref(), Some("alice"));
+        assert_eq!(todo.message, "fix this");
+        assert_eq!(todo.line, Some(1));
+        assert!(todo.path.ends_with("lib.rs"));
+        
+        let fixme = &annotations[1];
+        assert_eq!(fixme.marker, "FIXME");
+        assert_eq!(fixme.author, None);
+        assert_eq!(fixme.message, "handle errors");
+        assert_eq!(fixme.line, Some(3));
+    }
+    
+    #[test]
+    fn test_parse_annotation() {
+        assert_eq!(parse_annotation("(alice): fix this"), (Some("alice".to_string()), "fix this".to_string()));
+        assert_eq!(parse_annotation(" handle errors */"), (None, "handle errors".to_string()));
+        assert_eq!(parse_annotation("(): empty author"), (None, "empty author".to_string()));
+        assert_eq!(escape_regex("C++"), r"C\+\+");
+    }
+}
\ No newline at end of file
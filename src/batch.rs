@@ -0,0 +1,189 @@
+use std::sync::Arc;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+use tracing::debug;
+use crate::ripgrep::{RipgrepSearcher, SearchOptions, SearchResult};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BatchRequest {
+    /// Searches to run concurrently
+    pub searches: Vec<SearchOptions>,
+    
+    /// Wall-clock limit for the whole batch; searches still running when it
+    /// passes are cancelled
+    #[serde(default)]
+    pub total_deadline_secs: Option<u64>,
+}
+
+/// Outcome of one search in a batch, in the same position as its request
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum BatchSlot {
+    Completed(Box<SearchResult>),
+    Failed { error: String },
+    TimedOut { timed_out: bool },
+}
+
+/// Most roots `search_all_roots` searches at the same time
+pub const MAX_CONCURRENT_ROOT_SEARCHES: usize = 4;
+
+/// Outcome of a `search_all_roots` search in one root, in root order
+#[derive(Debug, Clone, Serialize)]
+pub struct RootSlot {
+    /// Directory name of the root
+    pub root: String,
+    pub root_index: usize,
+    pub result: BatchSlot,
+}
+
+impl RipgrepSearcher {
+    /// Run several searches concurrently, bounding the batch by its deadline
+    pub async fn search_batch(self: &Arc<Self>, batch: &BatchRequest) -> Vec<BatchSlot> {
+        let deadline = batch
+            .total_deadline_secs
+            .map(|secs| Instant::now() + Duration::from_secs(secs));
+        
+        let handles: Vec<_> = batch
+            .searches
+            .iter()
+            .cloned()
+            .map(|options| {
+                let searcher = Arc::clone(self);
+                tokio::spawn(async move { searcher.search(&options).await })
+            })
+            .collect();
+        
+        let mut slots = Vec::with_capacity(handles.len());
+        for (index, mut handle) in handles.into_iter().enumerate() {
+            let outcome = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, &mut handle).await,
+                None => Ok((&mut handle).await),
+            };
+            
+            slots.push(match outcome {
+                Ok(Ok(Ok(result))) => BatchSlot::Completed(Box::new(result)),
+                Ok(Ok(Err(e))) => BatchSlot::Failed { error: e.to_string() },
+                Ok(Err(e)) => BatchSlot::Failed { error: format!("Search task failed: {}", e) },
+                Err(_) => {
+                    // Dropping the task kills its ripgrep process
+                    debug!(index, "Batch deadline passed; cancelling search");
+                    handle.abort();
+                    BatchSlot::TimedOut { timed_out: true }
+                }
+            });
+        }
+        
+        slots
+    }
+    
+    /// Run the same search in every configured root, a few roots at a time
+    pub async fn search_all_roots(self: &Arc<Self>, options: &SearchOptions) -> Vec<RootSlot> {
+        let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_ROOT_SEARCHES));
+        let roots: Vec<String> = self
+            .roots()
+            .iter()
+            .map(|root| match root.file_name() {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => root.display().to_string(),
+            })
+            .collect();
+        
+        let handles: Vec<_> = (0..roots.len())
+            .map(|root_index| {
+                let searcher = Arc::clone(self);
+                let semaphore = Arc::clone(&semaphore);
+                // One id cannot name several running searches
+                let options = SearchOptions {
+                    root_index: Some(root_index),
+                    search_id: None,
+                    ..options.clone()
+                };
+                tokio::spawn(async move {
+                    let _permit = semaphore.acquire_owned().await.expect("the semaphore is never closed");
+                    searcher.search(&options).await
+                })
+            })
+            .collect();
+        
+        let mut slots = Vec::with_capacity(handles.len());
+        for ((root_index, root), handle) in roots.into_iter().enumerate().zip(handles) {
+            let result = match handle.await {
+                Ok(Ok(result)) => BatchSlot::Completed(Box::new(result)),
+                Ok(Err(e)) => BatchSlot::Failed { error: e.to_string() },
+                Err(e) => BatchSlot::Failed { error: format!("Search task failed: {}", e) },
+            };
+            slots.push(RootSlot { root, root_index, result });
+        }
+        
+        slots
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    
+    #[tokio::test]
+    async fn test_total_deadline() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("fast.txt"), "needle\n").unwrap();
+        
+        // Reading a FIFO with no writer blocks, so this search never finishes
+        let status = std::process::Command::new("mkfifo")
+            .arg(temp_dir.path().join("slow.fifo"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        
+        let searcher = Arc::new(RipgrepSearcher::new(temp_dir.path().to_path_buf()));
+        let search = |path: &str| SearchOptions {
+            path: path.to_string(),
+            ..SearchOptions::new("needle")
+        };
+        let batch = BatchRequest {
+            searches: vec![search("fast.txt"), search("slow.fifo")],
+            total_deadline_secs: Some(2),
+        };
+        
+        let started = std::time::Instant::now();
+        let slots = searcher.search_batch(&batch).await;
+        assert!(started.elapsed() < Duration::from_secs(10));
+        
+        match &slots[0] {
+            BatchSlot::Completed(result) => assert_eq!(result.matches.len(), 1),
+            other => panic!("fast search should complete, got {:?}", other),
+        }
+        assert!(matches!(slots[1], BatchSlot::TimedOut { timed_out: true }));
+    }
+    
+    #[tokio::test]
+    async fn test_search_all_roots() {
+        let first = TempDir::new().unwrap();
+        let second = TempDir::new().unwrap();
+        std::fs::write(first.path().join("a.txt"), "needle in first\n").unwrap();
+        std::fs::write(second.path().join("b.txt"), "needle in second\nneedle again\n").unwrap();
+        
+        let searcher = Arc::new(
+            RipgrepSearcher::new(first.path().to_path_buf()).with_extra_roots(vec![second.path().to_path_buf()]),
+        );
+        let slots = searcher.search_all_roots(&SearchOptions::new("needle")).await;
+        
+        // One slot per root, each holding only that root's matches
+        assert_eq!(slots.len(), 2);
+        let name = |dir: &TempDir| dir.path().file_name().unwrap().to_string_lossy().into_owned();
+        assert_eq!((slots[0].root.clone(), slots[0].root_index), (name(&first), 0));
+        assert_eq!((slots[1].root.clone(), slots[1].root_index), (name(&second), 1));
+        match (&slots[0].result, &slots[1].result) {
+            (BatchSlot::Completed(first_result), BatchSlot::Completed(second_result)) => {
+                assert_eq!(first_result.matches.len(), 1);
+                assert!(first_result.matches[0].contains("in first"));
+                assert_eq!(second_result.matches.len(), 2);
+                assert!(second_result.matches.iter().all(|m| m.contains("b.txt")));
+            }
+            other => panic!("both searches should complete, got {:?}", other),
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,70 @@
+use std::io::Write;
+use base64::Engine;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use crate::error::AppError;
+
+/// A serialized result, gzipped and base64-encoded for clients that opt in
+/// with `compress_response`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CompressedResponse {
+    /// Always `gzip`
+    pub content_encoding: String,
+    
+    /// Base64 of the gzipped compact JSON
+    pub data: String,
+}
+
+/// Serialize `value` as compact JSON and gzip it
+pub fn gzip_json<T: Serialize>(value: &T) -> Result<CompressedResponse, AppError> {
+    let json = serde_json::to_vec(value)?;
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&json)?;
+    let gzipped = encoder.finish()?;
+    
+    Ok(CompressedResponse {
+        content_encoding: "gzip".to_string(),
+        data: base64::engine::general_purpose::STANDARD.encode(gzipped),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use flate2::read::GzDecoder;
+    use crate::ripgrep::{SearchResult, SearchStats};
+    
+    #[test]
+    fn test_gzip_json_round_trip() {
+        let result = SearchResult {
+            search_id: "search-1".to_string(),
+            matches: (0..5000).map(|i| format!("/root/src/file{}.rs:{}:let value = compute({});", i % 50, i, i)).collect(),
+            stats: SearchStats {
+                matched_lines: 5000,
+                ..SearchStats::default()
+            },
+            ..SearchResult::default()
+        };
+        
+        let compressed = gzip_json(&result).unwrap();
+        assert_eq!(compressed.content_encoding, "gzip");
+        
+        let gzipped = base64::engine::general_purpose::STANDARD.decode(&compressed.data).unwrap();
+        let mut json = String::new();
+        GzDecoder::new(gzipped.as_slice()).read_to_string(&mut json).unwrap();
+        
+        // Repetitive match lines shrink well below their JSON size
+        assert!(compressed.data.len() < json.len() / 4);
+        let round_tripped: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(round_tripped, serde_json::to_value(&result).unwrap());
+    }
+    
+    #[test]
+    fn test_gzip_json_unserializable() {
+        // JSON object keys must be strings
+        let value = std::collections::HashMap::from([(vec![1u8], 1)]);
+        assert!(matches!(gzip_json(&value), Err(AppError::SerializationError(_))));
+    }
+}
\ No newline at end of file
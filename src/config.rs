@@ -1,10 +1,84 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use anyhow::Result;
+use serde::Deserialize;
+use crate::ripgrep::{
+    SearchProfile,
+    SearchSettings,
+    DEFAULT_MAX_PATHS_PER_REQUEST,
+    DEFAULT_SYMLINK_MAX_DEPTH,
+};
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub files_root: PathBuf,
+    
+    /// Roots after the first when `FILES_ROOTS` lists several
+    pub extra_roots: Vec<PathBuf>,
+    
     pub log_level: String,
+    pub max_paths_per_request: usize,
+    pub profiles: HashMap<String, SearchProfile>,
+    pub symlink_max_depth: usize,
+    pub max_threads_global: Option<usize>,
+    pub require_explicit_path: bool,
+    pub reject_degenerate_patterns: bool,
+    pub result_cache_ttl_secs: u64,
+    pub session_time_budget_secs: Option<u64>,
+    pub search_timeout_ms: Option<u64>,
+    
+    /// The only file extensions searches may read (`ALLOWED_EXTENSIONS`)
+    pub allowed_extensions: Option<Vec<String>>,
+    
+    /// Result cap for searches that set no `max_results` (`MAX_RESULTS`)
+    pub max_results: Option<usize>,
+    
+    /// Globs every search excludes (`EXCLUDE_GLOBS`)
+    pub exclude_globs: Vec<String>,
+    
+    /// The ripgrep binary: `RG_PATH`, or the `rg` found on `PATH`
+    pub rg_path: PathBuf,
+    
+    /// Custom file types (`name:glob` specs for `--type-add`), checked
+    /// against ripgrep at startup
+    pub type_add: Vec<String>,
+    
+    /// Where to serve HTTP when `TRANSPORT=http`; `None` serves stdio
+    pub http_addr: Option<SocketAddr>,
+    
+    /// Browser origins besides the loopback ones that may use the HTTP
+    /// transport
+    pub http_allowed_origins: Vec<String>,
+    
+    /// Overrides the `instructions` sent to clients at initialization
+    pub server_instructions: Option<String>,
+    
+    /// Overrides the description of the `search` tool
+    pub search_tool_description: Option<String>,
+}
+
+/// Settings read from the optional `CONFIG_FILE`; environment variables
+/// take precedence over the same setting in the file
+#[derive(Debug, Default, Deserialize)]
+struct FileConfig {
+    log_level: Option<String>,
+    max_paths_per_request: Option<usize>,
+    symlink_max_depth: Option<usize>,
+    max_threads_global: Option<usize>,
+    require_explicit_path: Option<bool>,
+    reject_degenerate_patterns: Option<bool>,
+    result_cache_ttl_secs: Option<u64>,
+    session_time_budget_secs: Option<u64>,
+    search_timeout_ms: Option<u64>,
+    allowed_extensions: Option<Vec<String>>,
+    max_results: Option<usize>,
+    exclude_globs: Option<Vec<String>>,
+    type_add: Option<Vec<String>>,
+    server_instructions: Option<String>,
+    search_tool_description: Option<String>,
+    #[serde(default)]
+    profiles: HashMap<String, SearchProfile>,
 }
 
 impl Config {
@@ -12,26 +86,336 @@ impl Config {
         // Load .env file if present (for development)
         dotenvy::dotenv().ok();
         
-        // Get FILES_ROOT from environment or use default
-        let files_root = match std::env::var("FILES_ROOT") {
-            Ok(path) => PathBuf::from(path),
-            Err(_) => {
+        // Get FILES_ROOT, or several roots from FILES_ROOTS, or use the default
+        let mut roots: Vec<PathBuf> = match (std::env::var_os("FILES_ROOTS"), std::env::var("FILES_ROOT")) {
+            (Some(_), Ok(_)) => anyhow::bail!("Set either FILES_ROOT or FILES_ROOTS, not both"),
+            (Some(paths), _) => std::env::split_paths(&paths)
+                .filter(|path| !path.as_os_str().is_empty())
+                .collect(),
+            (None, Ok(path)) => vec![PathBuf::from(path)],
+            (None, Err(_)) => {
                 eprintln!("FILES_ROOT not set, using current directory");
-                std::env::current_dir()?
+                vec![std::env::current_dir()?]
             }
         };
+        if roots.is_empty() {
+            anyhow::bail!("FILES_ROOTS lists no directories");
+        }
         
-        // Verify the path exists
-        if !files_root.exists() {
-            anyhow::bail!("FILES_ROOT directory does not exist: {:?}", files_root);
+        // Refuse to expose the whole disk or system directories by accident
+        let forbidden_roots = match std::env::var_os("FORBIDDEN_ROOTS") {
+            Some(paths) => std::env::split_paths(&paths).collect(),
+            None => default_forbidden_roots(),
+        };
+        for root in &roots {
+            // Verify the path exists
+            if !root.exists() {
+                anyhow::bail!("FILES_ROOT directory does not exist: {:?}", root);
+            }
+            check_root_safety(root, &forbidden_roots, env_flag("ALLOW_UNSAFE_ROOT"))?;
         }
+        let files_root = roots.remove(0);
+        let extra_roots = roots;
             
+        let file_config = match std::env::var("CONFIG_FILE") {
+            Ok(path) => load_file_config(Path::new(&path))?,
+            Err(_) => FileConfig::default(),
+        };
+        
         let log_level = std::env::var("LOG_LEVEL")
-            .unwrap_or_else(|_| "info".to_string());
+            .ok()
+            .or(file_config.log_level)
+            .unwrap_or_else(|| "info".to_string());
+        
+        let max_paths_per_request = parse_env("MAX_PATHS_PER_REQUEST")?
+            .or(file_config.max_paths_per_request)
+            .unwrap_or(DEFAULT_MAX_PATHS_PER_REQUEST);
+        
+        let symlink_max_depth = parse_env("SYMLINK_MAX_DEPTH")?
+            .or(file_config.symlink_max_depth)
+            .unwrap_or(DEFAULT_SYMLINK_MAX_DEPTH);
+        
+        let max_threads_global = parse_env("RIPGREP_MAX_THREADS_GLOBAL")?
+            .or(file_config.max_threads_global)
+            .filter(|&threads| threads > 0);
+        
+        let require_explicit_path = match std::env::var("REQUIRE_EXPLICIT_PATH") {
+            Ok(_) => env_flag("REQUIRE_EXPLICIT_PATH"),
+            Err(_) => file_config.require_explicit_path.unwrap_or(false),
+        };
+        
+        let reject_degenerate_patterns = match std::env::var("REJECT_DEGENERATE_PATTERNS") {
+            Ok(_) => env_flag("REJECT_DEGENERATE_PATTERNS"),
+            Err(_) => file_config.reject_degenerate_patterns.unwrap_or(false),
+        };
+        
+        let result_cache_ttl_secs = parse_env("RESULT_CACHE_TTL_SECS")?
+            .or(file_config.result_cache_ttl_secs)
+            .unwrap_or(0);
+        
+        let session_time_budget_secs = parse_env("SESSION_TIME_BUDGET_SECS")?
+            .or(file_config.session_time_budget_secs)
+            .filter(|&secs| secs > 0);
+        
+        let search_timeout_ms = parse_env("SEARCH_TIMEOUT_MS")?
+            .or(file_config.search_timeout_ms)
+            .filter(|&ms| ms > 0);
+        
+        // Accept `rs`, `.rs` and `*.rs` alike
+        let allowed_extensions = match std::env::var("ALLOWED_EXTENSIONS") {
+            Ok(list) => Some(list.split(',').map(String::from).collect()),
+            Err(_) => file_config.allowed_extensions,
+        };
+        let allowed_extensions: Option<Vec<String>> = allowed_extensions.map(|list| {
+            list.iter()
+                .map(|ext| ext.trim().trim_start_matches('*').trim_start_matches('.').to_lowercase())
+                .filter(|ext| !ext.is_empty())
+                .collect()
+        });
+        if allowed_extensions.as_ref().is_some_and(Vec::is_empty) {
+            anyhow::bail!("ALLOWED_EXTENSIONS lists no extensions");
+        }
+        
+        let max_results = parse_env("MAX_RESULTS")?
+            .or(file_config.max_results)
+            .filter(|&max| max > 0);
+        
+        // Globs may contain commas, so they are separated by semicolons
+        let exclude_globs: Vec<String> = match std::env::var("EXCLUDE_GLOBS") {
+            Ok(globs) => globs.split(';').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect(),
+            Err(_) => file_config.exclude_globs.unwrap_or_default(),
+        };
+        
+        // The startup check reports a missing binary with a clear message
+        let rg_path = match std::env::var_os("RG_PATH") {
+            Some(path) => PathBuf::from(path),
+            None => which::which("rg").unwrap_or_else(|_| PathBuf::from("rg")),
+        };
+        
+        // Globs may contain commas, so specs are separated by semicolons
+        let type_add: Vec<String> = match std::env::var("TYPE_ADD") {
+            Ok(specs) => specs.split(';').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect(),
+            Err(_) => file_config.type_add.unwrap_or_default(),
+        };
+        check_type_add(&rg_path, &type_add)?;
+        
+        let http_addr = match std::env::var("TRANSPORT").ok().as_deref().map(str::trim) {
+            None | Some("stdio") => None,
+            Some("http") => Some(parse_env("HTTP_ADDR")?.unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 8080)))),
+            Some(other) => anyhow::bail!("Unknown TRANSPORT {:?}; expected stdio or http", other),
+        };
+        let http_allowed_origins: Vec<String> = std::env::var("HTTP_ALLOWED_ORIGINS")
+            .map(|list| list.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect())
+            .unwrap_or_default();
+        
+        let server_instructions = std::env::var("SERVER_INSTRUCTIONS")
+            .ok()
+            .or(file_config.server_instructions);
+        
+        let search_tool_description = std::env::var("SEARCH_TOOL_DESCRIPTION")
+            .ok()
+            .or(file_config.search_tool_description);
             
         Ok(Config {
             files_root,
+            extra_roots,
             log_level,
+            max_paths_per_request,
+            profiles: file_config.profiles,
+            symlink_max_depth,
+            max_threads_global,
+            require_explicit_path,
+            reject_degenerate_patterns,
+            result_cache_ttl_secs,
+            session_time_budget_secs,
+            search_timeout_ms,
+            allowed_extensions,
+            max_results,
+            exclude_globs,
+            rg_path,
+            type_add,
+            http_addr,
+            http_allowed_origins,
+            server_instructions,
+            search_tool_description,
         })
     }
+    
+    /// Re-read the configuration for a running server. The root directory
+    /// cannot change this way, since open clients already rely on it.
+    /// Environment variables are read again too, but a running process's
+    /// environment does not change, so in practice only edits to the
+    /// `CONFIG_FILE` take effect, and only for settings the environment
+    /// does not set.
+    pub fn reload(&self) -> Result<Self> {
+        let fresh = Config::new()?;
+        if fresh.files_root != self.files_root || fresh.extra_roots != self.extra_roots {
+            anyhow::bail!(
+                "FILES_ROOT cannot change on reload ({:?} -> {:?})",
+                std::iter::once(&self.files_root).chain(&self.extra_roots).collect::<Vec<_>>(),
+                std::iter::once(&fresh.files_root).chain(&fresh.extra_roots).collect::<Vec<_>>()
+            );
+        }
+        Ok(fresh)
+    }
+    
+    /// The subset of the configuration the searcher can swap at runtime
+    pub fn search_settings(&self) -> SearchSettings {
+        SearchSettings {
+            max_paths_per_request: self.max_paths_per_request,
+            profiles: self.profiles.clone(),
+            symlink_max_depth: self.symlink_max_depth,
+            max_threads_global: self.max_threads_global,
+            require_explicit_path: self.require_explicit_path,
+            reject_degenerate_patterns: self.reject_degenerate_patterns,
+            result_cache_ttl: std::time::Duration::from_secs(self.result_cache_ttl_secs),
+            session_time_budget: self.session_time_budget_secs.map(std::time::Duration::from_secs),
+            search_timeout_ms: self.search_timeout_ms,
+            allowed_extensions: self.allowed_extensions.clone(),
+            max_results: self.max_results,
+            exclude_globs: self.exclude_globs.clone(),
+        }
+    }
+}
+
+/// Directories that are never a sensible search root unless explicitly allowed
+fn default_forbidden_roots() -> Vec<PathBuf> {
+    ["/", "/etc", "/home"].iter().map(PathBuf::from).collect()
+}
+
+/// Reject a root that is the filesystem root or one of the forbidden roots.
+/// Path traversal checks are meaningless when the root itself is too broad.
+fn check_root_safety(root: &Path, forbidden: &[PathBuf], allow_unsafe: bool) -> Result<()> {
+    if allow_unsafe {
+        return Ok(());
+    }
+    
+    let root = std::fs::canonicalize(root)?;
+    let is_forbidden = root.parent().is_none()
+        || forbidden
+            .iter()
+            .filter_map(|path| std::fs::canonicalize(path).ok())
+            .any(|path| path == root);
+    
+    if is_forbidden {
+        anyhow::bail!(
+            "Refusing to serve {:?} as FILES_ROOT; set ALLOW_UNSAFE_ROOT=1 to override",
+            root
+        );
+    }
+    
+    Ok(())
+}
+
+/// Fail fast on a custom type spec ripgrep rejects, rather than on every
+/// search that uses it
+fn check_type_add(rg_path: &Path, specs: &[String]) -> Result<()> {
+    for spec in specs {
+        let output = std::process::Command::new(rg_path)
+            .arg("--no-config")
+            .arg("--type-add")
+            .arg(spec)
+            .arg("--type-list")
+            .output()
+            .map_err(|e| anyhow::anyhow!("Could not run ripgrep at {:?} to check TYPE_ADD: {}", rg_path, e))?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Invalid TYPE_ADD spec {:?}: {}",
+                spec,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Whether a boolean environment variable is set to a truthy value
+fn env_flag(key: &str) -> bool {
+    std::env::var(key)
+        .map(|value| matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+/// Load the config file; its format is inferred from the extension (e.g. TOML)
+fn load_file_config(path: &Path) -> Result<FileConfig> {
+    let settings = ::config::Config::builder()
+        .add_source(::config::File::from(path))
+        .build()
+        .map_err(|e| anyhow::anyhow!("Failed to read config file {:?}: {}", path, e))?;
+    
+    settings
+        .try_deserialize()
+        .map_err(|e| anyhow::anyhow!("Invalid config file {:?}: {}", path, e))
+}
+
+/// Read and parse an optional environment variable
+fn parse_env<T>(key: &str) -> Result<Option<T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match std::env::var(key) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("Invalid value for {}: {:?} ({})", key, value, e)),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    
+    #[test]
+    fn test_filesystem_root_rejected() {
+        let forbidden = default_forbidden_roots();
+        assert!(check_root_safety(Path::new("/"), &forbidden, false).is_err());
+        assert!(check_root_safety(Path::new("/etc"), &forbidden, false).is_err());
+        
+        // The filesystem root is refused even with an empty forbidden list
+        assert!(check_root_safety(Path::new("/"), &[], false).is_err());
+        
+        // The explicit override allows it
+        assert!(check_root_safety(Path::new("/"), &forbidden, true).is_ok());
+        
+        let temp_dir = TempDir::new().unwrap();
+        assert!(check_root_safety(temp_dir.path(), &forbidden, false).is_ok());
+    }
+    
+    #[test]
+    fn test_load_profiles() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("mcp-rg.toml");
+        std::fs::write(&path, r#"
+[profiles.docs]
+file_types = ["markdown"]
+max_depth = 2
+
+[profiles.code]
+file_types = ["rust", "js"]
+exclude_globs = ["vendor/**"]
+"#).unwrap();
+        
+        let config = load_file_config(&path).unwrap();
+        assert_eq!(config.profiles.len(), 2);
+        assert_eq!(config.profiles["docs"].file_types, vec!["markdown"]);
+        assert_eq!(config.profiles["docs"].max_depth, Some(2));
+        assert_eq!(config.profiles["code"].file_types, vec!["rust", "js"]);
+        assert_eq!(config.profiles["code"].max_depth, None);
+        assert!(config.profiles["docs"].exclude_globs.is_empty());
+        assert_eq!(config.profiles["code"].exclude_globs, vec!["vendor/**"]);
+    }
+    
+    #[test]
+    fn test_invalid_type_add_rejected() {
+        let rg = PathBuf::from("rg");
+        assert!(check_type_add(&rg, &["proto:*.proto".to_string(), "web:*.{html,css}".to_string()]).is_ok());
+        
+        // A spec without a glob fails startup, naming the spec
+        let error = check_type_add(&rg, &["proto:*.proto".to_string(), "protobuf".to_string()]).unwrap_err();
+        assert!(error.to_string().contains("Invalid TYPE_ADD spec \"protobuf\""), "{}", error);
+    }
 }
\ No newline at end of file
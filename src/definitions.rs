@@ -0,0 +1,63 @@
+use crate::annotations::escape_regex;
+
+/// Keywords that introduce a definition, per ripgrep file type. Only the
+/// forms `<keyword> <name>` are covered, so this is a heuristic: C-style
+/// `int name(` declarations and assignments are not found
+const DEFINITION_KEYWORDS: &[(&str, &[&str])] = &[
+    ("rust", &["fn", "struct", "enum", "trait", "type", "mod", "const", "static", "let", "let mut", "macro_rules!"]),
+    ("js", &["function", "class", "const", "let", "var"]),
+    ("ts", &["function", "class", "interface", "type", "enum", "const", "let", "var"]),
+    ("py", &["def", "class"]),
+    ("go", &["func", "type", "var", "const"]),
+    ("java", &["class", "interface", "enum", "record"]),
+    ("kotlin", &["fun", "class", "interface", "object", "val", "var"]),
+    ("ruby", &["def", "class", "module"]),
+    ("php", &["function", "class", "interface", "trait"]),
+    ("c", &["struct", "enum", "union"]),
+    ("cpp", &["struct", "enum", "union", "class", "namespace"]),
+];
+
+/// A pattern matching `name` right after a definition keyword of the given
+/// file types, or of every known language when none of them is known
+pub fn definition_pattern(name: &str, fixed_strings: bool, file_types: &[String]) -> String {
+    let selected: Vec<&[&str]> = DEFINITION_KEYWORDS
+        .iter()
+        .filter(|(file_type, _)| file_types.iter().any(|t| t.eq_ignore_ascii_case(file_type)))
+        .map(|(_, keywords)| *keywords)
+        .collect();
+    let tables = if selected.is_empty() {
+        DEFINITION_KEYWORDS.iter().map(|(_, keywords)| *keywords).collect()
+    } else {
+        selected
+    };
+    
+    let mut keywords: Vec<&str> = tables.into_iter().flatten().copied().collect();
+    keywords.sort_unstable();
+    keywords.dedup();
+    let keywords: Vec<String> = keywords.iter().map(|k| escape_regex(k)).collect();
+    
+    let name = if fixed_strings { escape_regex(name) } else { name.to_string() };
+    format!(r"\b(?:{})\s+(?:{})", keywords.join("|"), name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_definition_pattern() {
+        // Keywords of the requested languages only, each listed once
+        let pattern = definition_pattern("hello_world", false, &["py".to_string(), "ruby".to_string()]);
+        assert_eq!(pattern, r"\b(?:class|def|module)\s+(?:hello_world)");
+        
+        // Fixed strings are escaped, since the result is a regex
+        let pattern = definition_pattern("a.b", true, &["py".to_string()]);
+        assert_eq!(pattern, r"\b(?:class|def)\s+(?:a\.b)");
+        
+        // Without a known type every language's keywords apply
+        let pattern = definition_pattern("x", false, &["markdown".to_string()]);
+        assert!(pattern.contains("fn|"));
+        assert!(pattern.contains("|function|"));
+        assert!(pattern.contains(r"macro_rules!"));
+    }
+}
\ No newline at end of file
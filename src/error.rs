@@ -5,6 +5,9 @@ pub enum AppError {
     #[error("I/O error: {0}")]
     IoError(#[from] std::io::Error),
     
+    #[error("Serialization error: {0}")]
+    SerializationError(#[from] serde_json::Error),
+    
     #[error("Ripgrep error: {0}")]
     RipgrepError(String),
     
@@ -14,6 +17,30 @@ pub enum AppError {
     #[error("Invalid path: {0}")]
     InvalidPath(String),
     
+    #[error("Permission denied: the server cannot read {0}; grant it read access or search a different path")]
+    PermissionDenied(String),
+    
+    #[error("Too many paths in request: {0} exceeds the limit of {1}")]
+    TooManyPaths(usize, usize),
+    
+    #[error("A path is required: searching the whole root is disabled on this server")]
+    PathRequired,
+    
+    #[error("Pattern `{0}` matches every line; narrow the search with a path or file type")]
+    DegeneratePattern(String),
+    
+    #[error("Session time budget of {0}s is used up; further searches are rejected until the session restarts")]
+    SessionBudgetExhausted(f64),
+    
+    #[error("Search timed out after {0} ms; narrow the path or simplify the pattern")]
+    Timeout(u64),
+    
+    #[error("Search {0} was cancelled")]
+    Cancelled(String),
+    
+    #[error("Unknown or expired search id: {0}")]
+    UnknownSearchId(String),
+    
     #[error("Configuration error: {0}")]
     ConfigError(String),
     
@@ -0,0 +1,320 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use async_trait::async_trait;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::sse::{Event, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use futures_util::{stream, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::task::AbortHandle;
+use tracing::{debug, info};
+use crate::error::AppError;
+
+/// Answers MCP's JSON-RPC methods, whatever transport carried them
+#[async_trait]
+pub trait JsonRpcHandler: Send + Sync + 'static {
+    /// The `result` of a request, or an error message; output lines produced
+    /// while it runs go to `lines` so they can be sent ahead of the result
+    async fn handle(&self, method: &str, params: Value, lines: UnboundedSender<String>) -> Result<Value, String>;
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonRpcRequest {
+    /// Absent for notifications, which get no response
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Routes JSON-RPC requests POSTed to `/mcp`; each response is an SSE
+/// stream of `match` events, one per output line as it is produced, ending
+/// in a `message` event with the JSON-RPC response. Browser requests are
+/// served only from loopback origins and `allowed_origins`
+pub fn router<H: JsonRpcHandler>(handler: Arc<H>, allowed_origins: Vec<String>) -> Router {
+    Router::new()
+        .route("/mcp", post(handle_request::<H>))
+        .with_state((handler, Arc::new(allowed_origins)))
+}
+
+/// Serve the tools over HTTP until the listener fails
+pub async fn serve<H: JsonRpcHandler>(
+    handler: Arc<H>,
+    addr: SocketAddr,
+    allowed_origins: Vec<String>,
+) -> Result<(), AppError> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!(%addr, "Serving MCP over HTTP");
+    axum::serve(listener, router(handler, allowed_origins)).await?;
+    Ok(())
+}
+
+async fn handle_request<H: JsonRpcHandler>(
+    State((handler, allowed_origins)): State<(Arc<H>, Arc<Vec<String>>)>,
+    headers: HeaderMap,
+    Json(request): Json<JsonRpcRequest>,
+) -> Response {
+    // Browsers send `Origin` with every POST, so a page that reached the
+    // server by rebinding its own host name to a local address is refused
+    let origin = headers.get(header::ORIGIN).map(|value| value.to_str().unwrap_or_default());
+    if !origin_allowed(origin, &allowed_origins) {
+        debug!(?origin, "Rejected request from a disallowed origin");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    
+    debug!(method = %request.method, "Received HTTP request");
+    let (lines, received) = mpsc::unbounded_channel();
+    let Some(id) = request.id else {
+        let _ = handler.handle(&request.method, request.params, lines).await;
+        return StatusCode::ACCEPTED.into_response();
+    };
+    
+    // The handler runs alongside the response so lines reach the client
+    // while it works; its sender is dropped when it finishes, which ends
+    // the `match` events
+    let task = tokio::spawn(async move { handler.handle(&request.method, request.params, lines).await });
+    let abort = AbortOnDrop(task.abort_handle());
+    let matches = stream::unfold(received, |mut received| async move {
+        let line = received.recv().await?;
+        Some((Event::default().event("match").data(line), received))
+    });
+    let message = stream::once(async move {
+        let _abort = abort;
+        let outcome = task.await.unwrap_or_else(|e| Err(format!("Request handler failed: {}", e)));
+        let message = match outcome {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(message) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32603, "message": message } }),
+        };
+        Event::default().event("message").data(message.to_string())
+    });
+    
+    Sse::new(matches.chain(message).map(Ok::<_, Infallible>)).into_response()
+}
+
+/// Whether a request may use the server: ones without an `Origin` come from
+/// non-browser clients; browsers must come from a loopback origin or one
+/// listed in `allowed`
+fn origin_allowed(origin: Option<&str>, allowed: &[String]) -> bool {
+    let Some(origin) = origin else {
+        return true;
+    };
+    if allowed.iter().any(|allowed| allowed == origin) {
+        return true;
+    }
+    let Some(authority) = origin.strip_prefix("http://").or_else(|| origin.strip_prefix("https://")) else {
+        return false;
+    };
+    let host = match authority.strip_prefix('[') {
+        Some(v6) => v6.split(']').next().unwrap_or_default(),
+        None => authority.split(':').next().unwrap_or_default(),
+    };
+    matches!(host, "localhost" | "127.0.0.1" | "::1")
+}
+
+/// Stops the handler's task when the response is dropped before it is done,
+/// e.g. because the client went away, so its search stops with it
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::ripgrep::{RipgrepSearcher, SearchOptions, SearchSettings};
+    
+    /// Wraps search results the way the MCP handler does, as text content
+    struct SearchOnly(RipgrepSearcher);
+    
+    #[async_trait]
+    impl JsonRpcHandler for SearchOnly {
+        async fn handle(&self, method: &str, params: Value, lines: UnboundedSender<String>) -> Result<Value, String> {
+            if method != "tools/call" {
+                return Err(format!("Method not found: {}", method));
+            }
+            let mut options: SearchOptions = serde_json::from_value(params["arguments"].clone()).map_err(|e| e.to_string())?;
+            options.line_sink = Some(lines);
+            let result = self.0.search(&options).await.map_err(|e| e.to_string())?;
+            let text = serde_json::to_string(&result).map_err(|e| e.to_string())?;
+            Ok(json!({ "content": [{ "type": "text", "text": text }] }))
+        }
+    }
+    
+    async fn serve_searcher(searcher: RipgrepSearcher, allowed_origins: Vec<String>) -> SocketAddr {
+        let handler = Arc::new(SearchOnly(searcher));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(handler, allowed_origins)).await });
+        addr
+    }
+    
+    async fn serve_search(root: &std::path::Path) -> SocketAddr {
+        serve_searcher(RipgrepSearcher::new(root.to_path_buf()), Vec::new()).await
+    }
+    
+    /// Send a request with extra header lines, each ending in `\r\n`
+    async fn send_with(addr: SocketAddr, headers: &str, body: &str) -> tokio::net::TcpStream {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "POST /mcp HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}",
+            addr,
+            body.len(),
+            headers,
+            body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        stream
+    }
+    
+    async fn send(addr: SocketAddr, body: &str) -> tokio::net::TcpStream {
+        send_with(addr, "", body).await
+    }
+    
+    async fn post_with(addr: SocketAddr, headers: &str, body: &str) -> String {
+        let mut stream = send_with(addr, headers, body).await;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+    
+    async fn post(addr: SocketAddr, body: &str) -> String {
+        post_with(addr, "", body).await
+    }
+    
+    #[tokio::test]
+    async fn test_search_over_http() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("a.txt"), "needle one\nhay\nneedle two\n").unwrap();
+        let addr = serve_search(temp_dir.path()).await;
+        
+        let body = r#"{"jsonrpc":"2.0","id":7,"method":"tools/call","params":{"name":"search","arguments":{"pattern":"needle"}}}"#;
+        let response = post(addr, body).await;
+        assert!(response.contains("text/event-stream"), "{}", response);
+        
+        // Each match line arrives as its own event before the full response
+        let match_events: Vec<&str> = response.lines().filter(|l| l.starts_with("data:") && l.contains("needle ")).collect();
+        assert!(response.contains("event: match"));
+        assert!(match_events.iter().any(|l| l.contains("needle one")));
+        assert!(match_events.iter().any(|l| l.contains("needle two")));
+        let message = response
+            .lines()
+            .skip_while(|l| *l != "event: message")
+            .find_map(|l| l.strip_prefix("data: "))
+            .unwrap();
+        let message: Value = serde_json::from_str(message).unwrap();
+        assert_eq!(message["id"], 7);
+        assert!(message["result"]["content"][0]["text"].as_str().unwrap().contains("needle two"));
+        
+        // Notifications are acknowledged without a body
+        let response = post(addr, r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#).await;
+        assert!(response.starts_with("HTTP/1.1 202"), "{}", response);
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_matches_stream_before_search_ends() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("a.txt"), "needle one\n").unwrap();
+        let fifo = temp_dir.path().join("pipe.fifo");
+        let status = std::process::Command::new("mkfifo").arg(&fifo).status().unwrap();
+        assert!(status.success());
+        let addr = serve_search(temp_dir.path()).await;
+        
+        // Ripgrep blocks reading the FIFO, so the search cannot finish yet
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"tools/call","params":{"name":"search","arguments":{"pattern":"needle","paths":["a.txt","pipe.fifo"]}}}"#;
+        let mut stream = send(addr, body).await;
+        let mut response = String::new();
+        let mut buf = [0; 4096];
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !response.contains("needle one") {
+                let n = stream.read(&mut buf).await.unwrap();
+                assert!(n > 0, "{}", response);
+                response.push_str(&String::from_utf8_lossy(&buf[..n]));
+            }
+        })
+        .await
+        .expect("the first match should arrive while the search runs");
+        assert!(response.contains("event: match"), "{}", response);
+        assert!(!response.contains("event: message"), "{}", response);
+        
+        // Feeding the FIFO lets the search, and the response, finish
+        std::thread::spawn(move || std::fs::write(fifo, "needle two\n").unwrap());
+        let mut rest = String::new();
+        tokio::time::timeout(Duration::from_secs(10), stream.read_to_string(&mut rest))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(rest.contains("event: message"), "{}", rest);
+        assert!(rest.contains("needle two"), "{}", rest);
+    }
+    
+    #[test]
+    fn test_origin_allowed() {
+        // Non-browser clients send no origin
+        assert!(origin_allowed(None, &[]));
+        
+        // Loopback origins on any port are local pages
+        assert!(origin_allowed(Some("http://localhost:3000"), &[]));
+        assert!(origin_allowed(Some("http://127.0.0.1"), &[]));
+        assert!(origin_allowed(Some("https://[::1]:8443"), &[]));
+        
+        // Everything else needs listing, exactly
+        let allowed = vec!["https://app.example.com".to_string()];
+        assert!(origin_allowed(Some("https://app.example.com"), &allowed));
+        assert!(!origin_allowed(Some("https://app.example.com:8443"), &allowed));
+        assert!(!origin_allowed(Some("http://localhost.evil.example"), &allowed));
+        assert!(!origin_allowed(Some("http://evil.example"), &[]));
+        assert!(!origin_allowed(Some("null"), &[]));
+    }
+    
+    #[tokio::test]
+    async fn test_foreign_origin_rejected() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("a.txt"), "needle\n").unwrap();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        let addr = serve_searcher(searcher, vec!["https://app.example.com".to_string()]).await;
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"tools/call","params":{"name":"search","arguments":{"pattern":"needle"}}}"#;
+        
+        // A page on a rebound host name is refused before anything runs
+        let response = post_with(addr, "Origin: http://evil.example:8080\r\n", body).await;
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+        
+        // Local pages and listed origins are served
+        let response = post_with(addr, "Origin: http://localhost:8080\r\n", body).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let response = post_with(addr, "Origin: https://app.example.com\r\n", body).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    }
+    
+    #[tokio::test]
+    async fn test_clients_share_session_budget() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("a.txt"), "needle\n").unwrap();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf()).with_settings(SearchSettings {
+            session_time_budget: Some(Duration::from_nanos(1)),
+            ..SearchSettings::default()
+        });
+        let addr = serve_searcher(searcher, Vec::new()).await;
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"tools/call","params":{"name":"search","arguments":{"pattern":"needle"}}}"#;
+        
+        // The first client's search uses up the budget, and a second client,
+        // on its own connection, is rejected by it all the same
+        let first = post_with(addr, "Origin: http://localhost:3000\r\n", body).await;
+        assert!(!first.contains("Session time budget"), "{}", first);
+        let second = post_with(addr, "Origin: http://127.0.0.1:4000\r\n", body).await;
+        assert!(second.contains("Session time budget"), "{}", second);
+    }
+}
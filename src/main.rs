@@ -1,11 +1,20 @@
+mod annotations;
+mod batch;
+mod compress;
 mod config;
+mod definitions;
 mod error;
+mod http;
 mod mcp;
+#[cfg(unix)]
+mod reload;
+mod resources;
 mod ripgrep;
+mod session;
 
 use anyhow::Result;
 use tracing::Level;
-use tracing_subscriber::FmtSubscriber;
+use tracing_subscriber::{fmt, prelude::*, reload as log_reload, Registry};
 use tracing_subscriber::filter::EnvFilter;
 
 #[tokio::main]
@@ -14,23 +23,36 @@ async fn main() -> Result<()> {
     let config = config::Config::new()?;
     
     // Set up logging to stderr only, no color codes
-    setup_logging(&config.log_level);
+    let log_handle = setup_logging(&config.log_level);
     
     // Stderr messages are fine as they won't interfere with JSON-RPC over stdout
     eprintln!("Starting ripgrep MCP server");
     eprintln!("Files root directory: {:?}", config.files_root);
+    for root in &config.extra_roots {
+        eprintln!("Additional root directory: {:?}", root);
+    }
     
-    // Check if ripgrep is installed
-    match which::which("rg") {
+    // Check if ripgrep is installed, at RG_PATH when set
+    match which::which(&config.rg_path) {
         Ok(path) => eprintln!("Found ripgrep at {:?}", path),
         Err(_) => {
-            eprintln!("Error: ripgrep (rg) is not installed or not in PATH");
+            eprintln!("Error: ripgrep not found at {:?}; install it or set RG_PATH to its location", config.rg_path);
             std::process::exit(1);
         }
     }
     
     // Create and run the MCP server (will communicate over stdin/stdout)
-    let server = mcp::MCPServer::new(config);
+    let server = mcp::MCPServer::new(config.clone());
+    
+    // Re-read the configuration on SIGHUP without dropping the client
+    #[cfg(unix)]
+    reload::reload_on_sighup(server.searcher(), move || {
+        let fresh = config.reload()?;
+        log_handle.reload(env_filter(&fresh.log_level))?;
+        Ok(fresh.search_settings())
+    })?;
+    #[cfg(not(unix))]
+    drop(log_handle);
     
     // Run the server and ensure all errors go to stderr, not stdout
     if let Err(e) = server.run().await {
@@ -43,7 +65,28 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-fn setup_logging(log_level: &str) {
+/// Install the stderr-only subscriber, returning a handle to swap its filter
+fn setup_logging(log_level: &str) -> log_reload::Handle<EnvFilter, Registry> {
+    let (filter, handle) = log_reload::Layer::new(env_filter(log_level));
+    
+    // Create a custom subscriber that only logs to stderr
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            fmt::layer()
+                .with_target(false)
+                .with_ansi(false) // Disable ANSI color codes
+                .with_writer(std::io::stderr), // Force all logging to stderr only
+        );
+        
+    tracing::subscriber::set_global_default(subscriber)
+        .expect("Failed to set tracing subscriber");
+    
+    handle
+}
+
+/// Build the log filter, letting RUST_LOG override the configured level
+fn env_filter(log_level: &str) -> EnvFilter {
     let level = match log_level.to_lowercase().as_str() {
         "trace" => Level::TRACE,
         "debug" => Level::DEBUG,
@@ -53,17 +96,6 @@ fn setup_logging(log_level: &str) {
         _ => Level::INFO,
     };
     
-    // Create a custom subscriber that only logs to stderr
-    let env_filter = EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| EnvFilter::new(format!("mcp_rg={}", level)));
-        
-    let subscriber = FmtSubscriber::builder()
-        .with_env_filter(env_filter)
-        .with_target(false)
-        .with_ansi(false) // Disable ANSI color codes
-        .with_writer(std::io::stderr) // Force all logging to stderr only
-        .finish();
-        
-    tracing::subscriber::set_global_default(subscriber)
-        .expect("Failed to set tracing subscriber");
+    EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("mcp_rg={}", level)))
 }
\ No newline at end of file
@@ -26,28 +26,63 @@ use rust_mcp_transport::{
     StdioTransport,
 };
 use serde_json::{json, Map, Value};
+use tokio::sync::mpsc::UnboundedSender;
 use tracing::{debug, info};
 use crate::{
+    annotations::{AnnotationOptions, DEFAULT_MARKERS},
+    batch::BatchRequest,
+    compress,
     config::Config,
     error::AppError,
-    ripgrep::{RipgrepSearcher, SearchOptions},
+    http::{self, JsonRpcHandler},
+    ripgrep::{CancelRequest, CancelResult, ListFilesOptions, RipgrepSearcher, SearchOptions},
 };
 use std::sync::Arc;
 use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// Instructions sent to clients when none are configured
+const DEFAULT_INSTRUCTIONS: &str = "Ripgrep MCP server for code search";
+
+/// Description of the `search` tool when none is configured
+const DEFAULT_SEARCH_DESCRIPTION: &str = "Search code using ripgrep";
 
 pub struct MCPServer {
     searcher: Arc<RipgrepSearcher>,
+    instructions: String,
+    search_description: String,
+    http_addr: Option<SocketAddr>,
+    http_allowed_origins: Vec<String>,
 }
 
 impl MCPServer {
     pub fn new(config: Config) -> Self {
-        let searcher = Arc::new(RipgrepSearcher::new(config.files_root.clone()));
-        Self { searcher }
+        let searcher = Arc::new(
+            RipgrepSearcher::new(config.files_root.clone())
+                .with_extra_roots(config.extra_roots.clone())
+                .with_rg_path(config.rg_path.clone())
+                .with_type_add(config.type_add.clone())
+                .with_settings(config.search_settings()),
+        );
+        Self {
+            searcher,
+            instructions: config.server_instructions
+                .unwrap_or_else(|| DEFAULT_INSTRUCTIONS.to_string()),
+            search_description: config.search_tool_description
+                .unwrap_or_else(|| DEFAULT_SEARCH_DESCRIPTION.to_string()),
+            http_addr: config.http_addr,
+            http_allowed_origins: config.http_allowed_origins,
+        }
     }
     
-    pub async fn run(&self) -> Result<(), AppError> {
-        // Create server details with the MCP protocol version
-        let server_details = InitializeResult {
+    /// Shared handle to the searcher, e.g. for reloading its settings
+    pub fn searcher(&self) -> Arc<RipgrepSearcher> {
+        self.searcher.clone()
+    }
+    
+    /// Server details sent to clients during initialization
+    fn server_details(&self) -> InitializeResult {
+        InitializeResult {
             server_info: Implementation {
                 name: "ripgrep-mcp".to_string(),
                 version: env!("CARGO_PKG_VERSION").to_string(),
@@ -57,15 +92,27 @@ impl MCPServer {
                 ..Default::default()
             },
             meta: None,
-            instructions: Some("Ripgrep MCP server for code search".to_string()),
+            instructions: Some(self.instructions.clone()),
             protocol_version: LATEST_PROTOCOL_VERSION.to_string(),
-        };
+        }
+    }
+    
+    pub async fn run(&self) -> Result<(), AppError> {
+        // Create server details with the MCP protocol version
+        let server_details = self.server_details();
         
         // Create a server handler with our implementation
         let handler = RipgrepServerHandler {
             searcher: self.searcher.clone(),
+            search_description: self.search_description.clone(),
         };
         
+        // Browser-based clients connect over HTTP and read results as SSE
+        if let Some(addr) = self.http_addr {
+            let handler = HttpHandler { handler, details: server_details };
+            return http::serve(Arc::new(handler), addr, self.http_allowed_origins.clone()).await;
+        }
+        
         // Create a transport with default options
         let transport_opt = TransportOptions::default();
         let transport = StdioTransport::new(transport_opt)
@@ -82,111 +129,702 @@ impl MCPServer {
     }
 }
 
+/// Build a JSON schema property with the given type and description
+fn schema_property(kind: &str, description: &str) -> Map<String, Value> {
+    let mut property = Map::new();
+    property.insert("type".to_string(), json!(kind));
+    property.insert("description".to_string(), json!(description));
+    property
+}
+
 // Server handler implementation
 #[derive(Debug)]
 struct RipgrepServerHandler {
     searcher: Arc<RipgrepSearcher>,
+    search_description: String,
 }
 
-#[async_trait]
-impl ServerHandler for RipgrepServerHandler {
-    // Handle tool listing
-    async fn handle_list_tools_request(
-        &self,
-        _request: ListToolsRequest,
-        _runtime: &dyn rust_mcp_sdk::McpServer,
-    ) -> Result<ListToolsResult, rust_mcp_schema::RpcError> {
+impl RipgrepServerHandler {
+    /// The tools this server offers
+    fn list_tools(&self) -> ListToolsResult {
         let mut properties = HashMap::new();
+        properties.insert("pattern".to_string(), schema_property("string", "Search pattern"));
+        let mut fallbacks_prop = schema_property(
+            "array",
+            "Patterns tried in order when pattern matches nothing (e.g. exact, then fuzzy); matched_pattern_index names the one that matched",
+        );
+        fallbacks_prop.insert("items".to_string(), json!({ "type": "string" }));
+        properties.insert("pattern_fallbacks".to_string(), fallbacks_prop);
+        properties.insert("path".to_string(), schema_property("string", "Relative path within root directory"));
+        properties.insert(
+            "root_index".to_string(),
+            schema_property("integer", "Which configured root paths are relative to; 0, the first, by default"),
+        );
+        let mut paths_prop = schema_property("array", "Additional relative paths (files or directories) to search");
+        paths_prop.insert("items".to_string(), json!({ "type": "string" }));
+        properties.insert("paths".to_string(), paths_prop);
+        properties.insert("fixed_strings".to_string(), schema_property("boolean", "Use fixed strings instead of regex"));
+        let mut case_mode_prop = schema_property(
+            "string",
+            "Case handling; smart ignores case unless the pattern has an uppercase letter. Overrides case_sensitive",
+        );
+        case_mode_prop.insert("enum".to_string(), json!(["sensitive", "insensitive", "smart"]));
+        properties.insert("case_mode".to_string(), case_mode_prop);
+        let mut sort_prop = schema_property(
+            "string",
+            "Return results in this file order for stable output across identical searches; makes ripgrep single-threaded, so slower on large trees",
+        );
+        sort_prop.insert("enum".to_string(), json!(["path", "modified", "accessed", "created"]));
+        properties.insert("sort".to_string(), sort_prop);
+        properties.insert("word_boundary".to_string(), schema_property("boolean", "Only match whole words"));
+        properties.insert("invert_match".to_string(), schema_property("boolean", "Return the lines that do not match"));
+        properties.insert(
+            "multiline".to_string(),
+            schema_property("boolean", "Let matches span lines, so the pattern may contain \\n"),
+        );
+        properties.insert(
+            "multiline_dotall".to_string(),
+            schema_property("boolean", "Let . match newlines too; implies multiline"),
+        );
+        properties.insert(
+            "definitions_only".to_string(),
+            schema_property("boolean", "Heuristic: only match where the pattern is defined (fn X, class X, def X, ...), using keywords of the requested file_types"),
+        );
+        properties.insert(
+            "pcre2".to_string(),
+            schema_property("boolean", "Use the PCRE2 regex engine, which supports lookahead, lookbehind and backreferences"),
+        );
+        properties.insert(
+            "encoding".to_string(),
+            schema_property("string", "Text encoding of the searched files, e.g. latin1 or utf-16; transcoded to UTF-8 before matching"),
+        );
+        properties.insert(
+            "lossy_decode".to_string(),
+            schema_property("boolean", "Replace invalid UTF-8 in the output with U+FFFD instead of failing the search"),
+        );
+        let mut globs_prop = schema_property("array", "Only search files matching these globs (e.g. *.toml)");
+        globs_prop.insert("items".to_string(), json!({ "type": "string" }));
+        properties.insert("globs".to_string(), globs_prop);
+        let mut exclude_globs_prop = schema_property("array", "Skip files and directories matching these globs (e.g. target/**)");
+        exclude_globs_prop.insert("items".to_string(), json!({ "type": "string" }));
+        properties.insert("exclude_globs".to_string(), exclude_globs_prop);
+        properties.insert(
+            "match_window_chars".to_string(),
+            schema_property("integer", "Return only the match plus this many characters of the line on each side"),
+        );
+        
+        properties.insert(
+            "within_results_of".to_string(),
+            schema_property("string", "search_id of an earlier search; only the files it matched are searched"),
+        );
+        properties.insert(
+            "search_id".to_string(),
+            schema_property("string", "Name this search so cancel_search can stop it while it runs; the result's search_id takes this value"),
+        );
+        
+        properties.insert(
+            "report_total".to_string(),
+            schema_property("boolean", "Also report the total number of individual matches in stats.total_matches"),
+        );
+        
+        properties.insert(
+            "profile".to_string(),
+            schema_property("string", "Named search profile whose defaults apply to options left unset"),
+        );
+        
+        properties.insert(
+            "summary_per_file".to_string(),
+            schema_property("boolean", "Return one summary per matched file (path, match_count, first_line, first_match_text) instead of every match"),
+        );
+        
+        properties.insert(
+            "columns".to_string(),
+            schema_property("boolean", "Add the 1-based byte column of the match (path:line:column:text), and a column field to structured_matches"),
+        );
+        
+        properties.insert(
+            "byte_offset".to_string(),
+            schema_property("boolean", "Add each line's 0-based byte offset in its file (path:line:offset:text), and a byte_offset field to structured_matches"),
+        );
+        
+        properties.insert(
+            "byte_ranges".to_string(),
+            schema_property("boolean", "Return structured_matches with each match's absolute start_byte/end_byte in its file"),
+        );
+        
+        properties.insert(
+            "only_in_files_matching".to_string(),
+            schema_property("string", "Only search files that also contain this secondary pattern"),
+        );
+        
+        properties.insert(
+            "follow_symlinks".to_string(),
+            schema_property("boolean", "Follow symbolic links while searching"),
+        );
+        properties.insert(
+            "follow_internal_symlinks_only".to_string(),
+            schema_property("boolean", "Follow symbolic links, dropping matches from files whose target lies outside the root"),
+        );
+        
+        properties.insert(
+            "rank_relevance".to_string(),
+            schema_property("boolean", "Return structured matches scored and sorted by relevance"),
+        );
+        
+        properties.insert(
+            "newer_than".to_string(),
+            schema_property("string", "Only search files modified after this reference file (relative to root)"),
+        );
+        
+        properties.insert(
+            "older_than".to_string(),
+            schema_property("string", "Only search files modified before this reference file (relative to root)"),
+        );
         
-        // Create pattern property
-        let mut pattern_prop = Map::new();
-        pattern_prop.insert("type".to_string(), json!("string"));
-        pattern_prop.insert("description".to_string(), json!("Search pattern"));
+        properties.insert(
+            "first_result_fast".to_string(),
+            schema_property("boolean", "Return only the first matching file's matches, stopping the search early"),
+        );
         
-        // Create path property
-        let mut path_prop = Map::new();
-        path_prop.insert("type".to_string(), json!("string"));
-        path_prop.insert("description".to_string(), json!("Relative path within root directory"));
+        properties.insert(
+            "normalize_pattern".to_string(),
+            schema_property("boolean", "Collapse accidental double escapes like \\\\d into \\d before searching"),
+        );
         
-        // Create fixed_strings property
-        let mut fixed_strings_prop = Map::new();
-        fixed_strings_prop.insert("type".to_string(), json!("boolean"));
-        fixed_strings_prop.insert("description".to_string(), json!("Use fixed strings instead of regex"));
+        properties.insert(
+            "highlight_matches".to_string(),
+            schema_property("boolean", "Return structured matches with the matched text and its span within the line"),
+        );
         
-        // Add to properties map
-        properties.insert("pattern".to_string(), pattern_prop);
-        properties.insert("path".to_string(), path_prop);
-        properties.insert("fixed_strings".to_string(), fixed_strings_prop);
+        properties.insert(
+            "include_last_commit".to_string(),
+            schema_property("boolean", "Attach each matched file's most recent git commit hash and date"),
+        );
+        
+        properties.insert(
+            "diagnose".to_string(),
+            schema_property("boolean", "Explain which files were skipped (ignore rules, hidden files, type filters) and why"),
+        );
+        
+        properties.insert(
+            "token_budget".to_string(),
+            schema_property("integer", "Truncate match lines to fit roughly this many tokens, keeping one line per file first"),
+        );
+        
+        properties.insert(
+            "skip_invalid_paths".to_string(),
+            schema_property("boolean", "Search the valid paths and report invalid ones in invalid_paths instead of failing"),
+        );
+        
+        properties.insert(
+            "smart_truncate".to_string(),
+            schema_property("boolean", "Cut match windows at word or token boundaries and mark cuts with an ellipsis; requires match_window_chars"),
+        );
+        
+        properties.insert(
+            "relative_to_line".to_string(),
+            schema_property("integer", "Reference line; structured matches report delta_lines from it"),
+        );
+        
+        properties.insert(
+            "merge_context".to_string(),
+            schema_property("boolean", "Return contiguous per-file line blocks merging nearby matches and their context"),
+        );
+        
+        properties.insert(
+            "only_matching".to_string(),
+            schema_property("boolean", "Return only the matched part of each line, one entry per match"),
+        );
+        
+        properties.insert(
+            "histogram".to_string(),
+            schema_property("boolean", "Return a frequency count of distinct matched strings instead of matches"),
+        );
+        
+        properties.insert(
+            "color".to_string(),
+            schema_property("boolean", "Keep ANSI color codes around matches in plain text output"),
+        );
+        
+        properties.insert(
+            "path_components".to_string(),
+            schema_property("boolean", "List matched files with their root-relative path split into components"),
+        );
+        
+        properties.insert(
+            "max_submatches_per_line".to_string(),
+            schema_property("integer", "Keep only the first N matches on each line"),
+        );
+        
+        properties.insert(
+            "max_results".to_string(),
+            schema_property("integer", "Return at most this many matches; stats.truncated is set when more were found"),
+        );
+        properties.insert(
+            "sample".to_string(),
+            schema_property("integer", "Return a reproducible sample of this many matches drawn from the whole result"),
+        );
+        properties.insert(
+            "max_count_per_file".to_string(),
+            schema_property("integer", "Stop reading each file after this many matching lines"),
+        );
+        
+        properties.insert(
+            "null_output".to_string(),
+            schema_property("boolean", "End each path with a NUL byte instead of ':' in plain text output"),
+        );
+        
+        properties.insert(
+            "density_report".to_string(),
+            schema_property("boolean", "Return matched files ranked by matches per line instead of matches"),
+        );
+        
+        properties.insert(
+            "hard_limit".to_string(),
+            schema_property("integer", "Stop ripgrep once this many matching lines have been read"),
+        );
+        
+        properties.insert(
+            "reconcile_counts".to_string(),
+            schema_property("boolean", "After an early stop, count every match so total_matches stays accurate"),
+        );
+        
+        properties.insert(
+            "include_mime".to_string(),
+            schema_property("boolean", "List matched files with a MIME type guessed from their extension"),
+        );
+        
+        properties.insert(
+            "partition_comments".to_string(),
+            schema_property("boolean", "Split matched lines into matches inside comments and matches in code (heuristic)"),
+        );
+        
+        properties.insert(
+            "type_breakdown".to_string(),
+            schema_property("boolean", "Also return the number of matches per ripgrep file type"),
+        );
+        
+        properties.insert(
+            "raw_json".to_string(),
+            schema_property("boolean", "Return ripgrep's --json event lines unmodified in matches"),
+        );
+        
+        properties.insert(
+            "smart_path_pattern".to_string(),
+            schema_property("boolean", "When the pattern is the path of a file under the root, return that file instead"),
+        );
+        
+        properties.insert(
+            "search_upward".to_string(),
+            schema_property(
+                "boolean",
+                "Search the files in `path`, then in each parent directory up to the root, stopping at the first level with matches",
+            ),
+        );
+        
+        properties.insert(
+            "include_manifest".to_string(),
+            schema_property("boolean", "Attach the ripgrep version, arguments, root and time needed to reproduce the search"),
+        );
+        properties.insert(
+            "resource_report".to_string(),
+            schema_property("boolean", "Report ripgrep's peak memory (stats.peak_rss_kb) and CPU time (stats.user_cpu_ms, stats.system_cpu_ms), best effort; Linux only"),
+        );
+        properties.insert(
+            "include_scope".to_string(),
+            schema_property("boolean", "Attach a scope object showing how the request was interpreted: resolved root and paths, file_types, globs, max_depth, honored ignore sources"),
+        );
+        
+        properties.insert(
+            "timeout_ms".to_string(),
+            schema_property("integer", "Fail the search if it takes longer than this many milliseconds in total, including every ripgrep pass it runs"),
+        );
+        
+        properties.insert(
+            "include_indent".to_string(),
+            schema_property("boolean", "Attach each match line's leading whitespace width (indent) to the structured matches, as a hint of nesting depth"),
+        );
+        properties.insert(
+            "tab_width".to_string(),
+            schema_property("integer", "Columns a tab counts for in indent (default 4)"),
+        );
+        
+        properties.insert(
+            "include_prev_line".to_string(),
+            schema_property("boolean", "Attach the line before each match, such as a key or section header, to the structured matches"),
+        );
+        
+        properties.insert(
+            "compress_response".to_string(),
+            schema_property("boolean", "Return the result as base64 gzipped JSON in `data`, marked content_encoding gzip"),
+        );
+        
+        properties.insert(
+            "match_ratio".to_string(),
+            schema_property("boolean", "Report stats.match_ratio, the share of searched files that matched: low for a rare term, high for a broad one"),
+        );
+        properties.insert(
+            "replace".to_string(),
+            schema_property("string", "Preview a find-and-replace: return matched lines with each match replaced by this text ($1 etc. for capture groups). Preview only; files are never modified"),
+        );
+        properties.insert(
+            "patch_hunks".to_string(),
+            schema_property("boolean", "With replace, also return patch_hunks: unified-diff hunks (@@ headers, context, -/+ lines) ready for patch or git apply. context_lines sets the hunk context (default 3)"),
+        );
+        
+        let mut snippet_prop = schema_property(
+            "object",
+            "Return one snippet per match with this many characters around it, marking cut sides with the ellipsis",
+        );
+        snippet_prop.insert(
+            "properties".to_string(),
+            json!({
+                "before_chars": { "type": "integer" },
+                "after_chars": { "type": "integer" },
+                "ellipsis": { "type": "string" }
+            }),
+        );
+        snippet_prop.insert("required".to_string(), json!(["before_chars", "after_chars"]));
+        properties.insert("snippet".to_string(), snippet_prop);
+        
+        let mut output_format_prop = schema_property("string", "Shape of the returned match lines");
+        output_format_prop.insert("enum".to_string(), json!(["text", "git_grep", "json", "grouped"]));
+        properties.insert("output_format".to_string(), output_format_prop);
+        
+        let mut ignore_prop = schema_property(
+            "array",
+            "Ignore sources to honor (vcs, dot, parent, global, exclude); unlisted sources are disabled",
+        );
+        ignore_prop.insert(
+            "items".to_string(),
+            json!({ "type": "string", "enum": ["vcs", "dot", "parent", "global", "exclude"] }),
+        );
+        properties.insert("use_ignore_files".to_string(), ignore_prop);
+        properties.insert(
+            "max_filesize".to_string(),
+            schema_property("string", "Skip files larger than this, e.g. \"500K\" or \"1M\" (byte count with optional K, M or G suffix); useful against large generated or minified files"),
+        );
+        properties.insert(
+            "search_hidden".to_string(),
+            schema_property("boolean", "Also search hidden files and directories (names starting with .)"),
+        );
+        properties.insert(
+            "no_ignore".to_string(),
+            schema_property("boolean", "Also search files excluded by .gitignore, .ignore and other ignore files"),
+        );
         
         // Create the tool with input schema
         let search_tool = Tool {
             name: "search".to_string(),
-            description: Some("Search code using ripgrep".to_string()),
+            description: Some(self.search_description.clone()),
             input_schema: ToolInputSchema::new(
                 vec!["pattern".to_string()], 
-                Some(properties)
+                Some(properties.clone())
             ),
         };
         
-        Ok(ListToolsResult {
-            tools: vec![search_tool],
+        let mut annotation_properties = HashMap::new();
+        let mut markers_prop = schema_property(
+            "array",
+            &format!("Markers to look for (default: {})", DEFAULT_MARKERS.join(", ")),
+        );
+        markers_prop.insert("items".to_string(), json!({ "type": "string" }));
+        annotation_properties.insert("markers".to_string(), markers_prop);
+        annotation_properties.insert(
+            "path".to_string(),
+            schema_property("string", "Relative path within the root directory"),
+        );
+        let mut file_types_prop = schema_property("array", "File types to include (e.g. rust, js)");
+        file_types_prop.insert("items".to_string(), json!({ "type": "string" }));
+        annotation_properties.insert("file_types".to_string(), file_types_prop);
+        
+        let annotations_tool = Tool {
+            name: "find_annotations".to_string(),
+            description: Some("Find TODO/FIXME-style comments, parsed into marker, author and message".to_string()),
+            input_schema: ToolInputSchema::new(Vec::new(), Some(annotation_properties)),
+        };
+        
+        let mut batch_properties = HashMap::new();
+        let mut searches_prop = schema_property("array", "Searches to run concurrently; each takes the search tool's parameters");
+        searches_prop.insert("items".to_string(), json!({ "type": "object" }));
+        batch_properties.insert("searches".to_string(), searches_prop);
+        batch_properties.insert(
+            "total_deadline_secs".to_string(),
+            schema_property("integer", "Wall-clock limit for the whole batch; unfinished searches report timed_out"),
+        );
+        
+        let batch_tool = Tool {
+            name: "search_batch".to_string(),
+            description: Some("Run several ripgrep searches concurrently, one result slot per search".to_string()),
+            input_schema: ToolInputSchema::new(vec!["searches".to_string()], Some(batch_properties)),
+        };
+        
+        let health_tool = Tool {
+            name: "health".to_string(),
+            description: Some("Check readiness without searching: the ripgrep version and whether each configured root exists".to_string()),
+            input_schema: ToolInputSchema::new(Vec::new(), Some(HashMap::new())),
+        };
+        
+        let mut cancel_properties = HashMap::new();
+        cancel_properties.insert(
+            "search_id".to_string(),
+            schema_property("string", "The search_id a running search was started with"),
+        );
+        
+        let cancel_tool = Tool {
+            name: "cancel_search".to_string(),
+            description: Some("Stop a running search started with a search_id, killing its ripgrep process; reports whether one was found".to_string()),
+            input_schema: ToolInputSchema::new(vec!["search_id".to_string()], Some(cancel_properties)),
+        };
+        
+        let mut list_properties = HashMap::new();
+        list_properties.insert(
+            "path".to_string(),
+            schema_property("string", "Relative path within the root directory"),
+        );
+        let mut list_types_prop = schema_property("array", "File types to include (e.g. rust, js)");
+        list_types_prop.insert("items".to_string(), json!({ "type": "string" }));
+        list_properties.insert("file_types".to_string(), list_types_prop);
+        let mut list_globs_prop = schema_property("array", "Glob patterns a file must match");
+        list_globs_prop.insert("items".to_string(), json!({ "type": "string" }));
+        list_properties.insert("globs".to_string(), list_globs_prop);
+        list_properties.insert(
+            "max_depth".to_string(),
+            schema_property("integer", "Maximum directory depth to descend"),
+        );
+        list_properties.insert(
+            "root_index".to_string(),
+            schema_property("integer", "Which configured root to list; 0, the first, by default"),
+        );
+        
+        let list_files_tool = Tool {
+            name: "list_files".to_string(),
+            description: Some("List the files ripgrep would search, relative to the root, honoring ignore files".to_string()),
+            input_schema: ToolInputSchema::new(Vec::new(), Some(list_properties)),
+        };
+        
+        // Counting takes the search parameters; output-shaping ones are ignored
+        let count_tool = Tool {
+            name: "count".to_string(),
+            description: Some("Count matches per file with ripgrep, without returning the matched lines".to_string()),
+            input_schema: ToolInputSchema::new(vec!["pattern".to_string()], Some(properties.clone())),
+        };
+        
+        let all_roots_tool = Tool {
+            name: "search_all_roots".to_string(),
+            description: Some("Run one search in every configured root concurrently, with results grouped by root".to_string()),
+            input_schema: ToolInputSchema::new(vec!["pattern".to_string()], Some(properties)),
+        };
+        
+        ListToolsResult {
+            tools: vec![search_tool, health_tool, count_tool, list_files_tool, annotations_tool, batch_tool, all_roots_tool, cancel_tool],
             meta: None,
             next_cursor: None,
-        })
+        }
     }
     
-    // Handle tool calls
-    async fn handle_call_tool_request(
+    /// Run one tool call, whichever transport it came over; a transport that
+    /// streams passes `lines` to get each search output line as it arrives
+    async fn call_tool(
         &self,
-        request: CallToolRequest,
-        _runtime: &dyn rust_mcp_sdk::McpServer,
+        name: &str,
+        arguments: Option<Map<String, Value>>,
+        lines: Option<UnboundedSender<String>>,
     ) -> Result<CallToolResult, CallToolError> {
-        debug!(?request, "Received search request");
-        
-        match request.params.name.as_str() {
+        match name {
             "search" => {
-                // Parse the search options from the parameters
-                let options: SearchOptions = match request.params.arguments {
-                    Some(args) => serde_json::from_value(Value::Object(args))
-                        .map_err(|e| {
-                            let err_msg = format!("Invalid parameters: {}", e);
-                            CallToolError::new(std::io::Error::new(std::io::ErrorKind::InvalidInput, err_msg))
-                        })?,
-                    None => {
-                        let err_msg = "Missing required arguments for search".to_string();
-                        return Err(CallToolError::new(std::io::Error::new(std::io::ErrorKind::InvalidInput, err_msg)));
-                    }
-                };
+                let mut options: SearchOptions = parse_arguments(arguments, "search")?;
+                options.line_sink = lines;
                 
                 // Execute the search
                 let result = self.searcher.search(&options).await
-                    .map_err(|e| {
-                        let err_msg = format!("Search failed: {}", e);
-                        CallToolError::new(std::io::Error::new(std::io::ErrorKind::Other, err_msg))
-                    })?;
+                    .map_err(|e| tool_error(std::io::ErrorKind::Other, format!("Search failed: {}", e)))?;
                 
-                // Convert the result to JSON
-                let result_json = serde_json::to_string_pretty(&result).map_err(|e| {
-                    let err_msg = format!("JSON serialization error: {}", e);
-                    CallToolError::new(std::io::Error::new(std::io::ErrorKind::Other, err_msg))
-                })?;
+                if options.compress_response {
+                    let compressed = compress::gzip_json(&result)
+                        .map_err(|e| tool_error(std::io::ErrorKind::Other, format!("Compression failed: {}", e)))?;
+                    return json_result(&compressed);
+                }
+                json_result(&result)
+            },
+            "health" => json_result(&self.searcher.health().await),
+            "count" => {
+                let options: SearchOptions = parse_arguments(arguments, "count")?;
+                
+                let counts = self.searcher.count(&options).await
+                    .map_err(|e| tool_error(std::io::ErrorKind::Other, format!("Count failed: {}", e)))?;
                 
-                // Create text content
-                let text_content = TextContent::new(result_json, None);
+                json_result(&counts)
+            },
+            "list_files" => {
+                // Every field is optional, so a call without arguments is fine
+                let arguments = Some(arguments.unwrap_or_default());
+                let options: ListFilesOptions = parse_arguments(arguments, "list_files")?;
                 
-                // Create call tool result with content
-                let mut content = Vec::new();
-                content.push(text_content.into());
+                let files = self.searcher.list_files(&options).await
+                    .map_err(|e| tool_error(std::io::ErrorKind::Other, format!("Listing files failed: {}", e)))?;
+                
+                json_result(&files)
+            },
+            "find_annotations" => {
+                // Every field is optional, so a call without arguments is fine
+                let arguments = Some(arguments.unwrap_or_default());
+                let options: AnnotationOptions = parse_arguments(arguments, "find_annotations")?;
                 
-                Ok(CallToolResult {
-                    content,
-                    is_error: None,
-                    meta: None,
-                })
+                let annotations = self.searcher.find_annotations(&options).await
+                    .map_err(|e| tool_error(std::io::ErrorKind::Other, format!("Annotation search failed: {}", e)))?;
+                
+                json_result(&annotations)
+            },
+            "search_batch" => {
+                let batch: BatchRequest = parse_arguments(arguments, "search_batch")?;
+                json_result(&self.searcher.search_batch(&batch).await)
+            },
+            "search_all_roots" => {
+                let options: SearchOptions = parse_arguments(arguments, "search_all_roots")?;
+                json_result(&self.searcher.search_all_roots(&options).await)
+            },
+            "cancel_search" => {
+                let request: CancelRequest = parse_arguments(arguments, "cancel_search")?;
+                let cancelled = self.searcher.cancel_search(&request.search_id);
+                json_result(&CancelResult { search_id: request.search_id, cancelled })
             },
             _ => {
-                Err(CallToolError::unknown_tool(format!("Unknown tool: {}", request.params.name)))
+                Err(CallToolError::unknown_tool(format!("Unknown tool: {}", name)))
             },
         }
     }
+}
+
+#[async_trait]
+impl ServerHandler for RipgrepServerHandler {
+    // Handle tool listing
+    async fn handle_list_tools_request(
+        &self,
+        _request: ListToolsRequest,
+        _runtime: &dyn rust_mcp_sdk::McpServer,
+    ) -> Result<ListToolsResult, rust_mcp_schema::RpcError> {
+        Ok(self.list_tools())
+    }
+    
+    // Handle tool calls
+    async fn handle_call_tool_request(
+        &self,
+        request: CallToolRequest,
+        _runtime: &dyn rust_mcp_sdk::McpServer,
+    ) -> Result<CallToolResult, CallToolError> {
+        debug!(?request, "Received tool call");
+        self.call_tool(&request.params.name, request.params.arguments, None).await
+    }
+}
+
+/// The same handler behind the HTTP transport, which also answers
+/// `initialize` itself since there is no SDK runtime to do it
+struct HttpHandler {
+    handler: RipgrepServerHandler,
+    details: InitializeResult,
+}
+
+#[async_trait]
+impl JsonRpcHandler for HttpHandler {
+    async fn handle(&self, method: &str, params: Value, lines: UnboundedSender<String>) -> Result<Value, String> {
+        let result = match method {
+            "initialize" => serde_json::to_value(&self.details),
+            "ping" => Ok(json!({})),
+            "tools/list" => serde_json::to_value(self.handler.list_tools()),
+            "tools/call" => {
+                let name = params.get("name").and_then(Value::as_str).ok_or("Missing tool name")?;
+                let arguments = params.get("arguments").and_then(Value::as_object).cloned();
+                let result = self.handler.call_tool(name, arguments, Some(lines)).await.map_err(|e| e.to_string())?;
+                serde_json::to_value(result)
+            }
+            // Notifications such as `notifications/initialized` need no answer
+            _ if method.starts_with("notifications/") => Ok(Value::Null),
+            _ => return Err(format!("Method not found: {}", method)),
+        };
+        result.map_err(|e| format!("JSON serialization error: {}", e))
+    }
+}
+
+
+fn tool_error(kind: std::io::ErrorKind, message: String) -> CallToolError {
+    CallToolError::new(std::io::Error::new(kind, message))
+}
+
+/// Deserialize a tool's arguments, reporting bad or missing ones as invalid input
+fn parse_arguments<T: serde::de::DeserializeOwned>(arguments: Option<Map<String, Value>>, tool: &str) -> Result<T, CallToolError> {
+    match arguments {
+        Some(args) => serde_json::from_value(Value::Object(args))
+            .map_err(|e| tool_error(std::io::ErrorKind::InvalidInput, format!("Invalid parameters: {}", e))),
+        None => Err(tool_error(
+            std::io::ErrorKind::InvalidInput,
+            format!("Missing required arguments for {}", tool),
+        )),
+    }
+}
+
+/// Wrap a serializable result as pretty-printed JSON text content
+fn json_result<T: serde::Serialize>(value: &T) -> Result<CallToolResult, CallToolError> {
+    // Convert the result to JSON
+    let result_json = serde_json::to_string_pretty(value)
+        .map_err(|e| tool_error(std::io::ErrorKind::Other, format!("JSON serialization error: {}", e)))?;
+    
+    // Create call tool result with text content
+    Ok(CallToolResult {
+        content: vec![TextContent::new(result_json, None).into()],
+        is_error: None,
+        meta: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    
+    fn test_config(root: &std::path::Path) -> Config {
+        Config {
+            files_root: root.to_path_buf(),
+            extra_roots: Vec::new(),
+            log_level: "info".to_string(),
+            max_paths_per_request: crate::ripgrep::DEFAULT_MAX_PATHS_PER_REQUEST,
+            profiles: HashMap::new(),
+            symlink_max_depth: crate::ripgrep::DEFAULT_SYMLINK_MAX_DEPTH,
+            max_threads_global: None,
+            require_explicit_path: false,
+            reject_degenerate_patterns: false,
+            result_cache_ttl_secs: 0,
+            session_time_budget_secs: None,
+            search_timeout_ms: None,
+            rg_path: std::path::PathBuf::from("rg"),
+            type_add: Vec::new(),
+            allowed_extensions: None,
+            max_results: None,
+            exclude_globs: Vec::new(),
+            http_addr: None,
+            http_allowed_origins: Vec::new(),
+            server_instructions: None,
+            search_tool_description: None,
+        }
+    }
+    
+    #[test]
+    fn test_custom_instructions() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        let config = Config {
+            server_instructions: Some("Searches the ACME codebase; prefer file_types=rust".to_string()),
+            ..test_config(temp_dir.path())
+        };
+        let details = MCPServer::new(config).server_details();
+        assert_eq!(
+            details.instructions.as_deref(),
+            Some("Searches the ACME codebase; prefer file_types=rust")
+        );
+        
+        // Unset values fall back to the defaults
+        let server = MCPServer::new(test_config(temp_dir.path()));
+        assert_eq!(server.server_details().instructions.as_deref(), Some(DEFAULT_INSTRUCTIONS));
+        assert_eq!(server.search_description, DEFAULT_SEARCH_DESCRIPTION);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use crate::ripgrep::{RipgrepSearcher, SearchSettings};
+
+/// Reload the searcher's settings every time the process receives SIGHUP.
+///
+/// `load` re-reads the configuration; if it fails (for example because the
+/// root directory changed) the current settings stay in place.
+pub fn reload_on_sighup<F>(searcher: Arc<RipgrepSearcher>, load: F) -> std::io::Result<JoinHandle<()>>
+where
+    F: Fn() -> anyhow::Result<SearchSettings> + Send + 'static,
+{
+    // Register the handler before returning so no SIGHUP falls back to the
+    // default action (terminating the process)
+    let mut hangups = signal(SignalKind::hangup())?;
+    
+    Ok(tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            match load() {
+                Ok(settings) => {
+                    searcher.reload_settings(settings);
+                    info!("Reloaded configuration after SIGHUP");
+                }
+                Err(e) => warn!("Keeping current configuration, reload failed: {}", e),
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AppError;
+    use crate::ripgrep::SearchOptions;
+    use std::time::Duration;
+    use tempfile::TempDir;
+    
+    #[tokio::test]
+    async fn test_sighup_reloads_settings() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("a.txt"), "needle\nneedle\n").unwrap();
+        std::fs::write(temp_dir.path().join("b.txt"), "needle\n").unwrap();
+        let searcher = Arc::new(RipgrepSearcher::new(temp_dir.path().to_path_buf()));
+        
+        reload_on_sighup(searcher.clone(), || {
+            Ok(SearchSettings {
+                max_paths_per_request: 1,
+                max_results: Some(1),
+                exclude_globs: vec!["b.txt".to_string()],
+                ..SearchSettings::default()
+            })
+        })
+        .unwrap();
+        
+        let status = std::process::Command::new("kill")
+            .args(["-HUP", &std::process::id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        
+        // The handler runs on another task; wait for it to swap the settings
+        for _ in 0..100 {
+            if searcher.settings().max_paths_per_request == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        
+        let options: SearchOptions = serde_json::from_value(serde_json::json!({
+            "pattern": "needle",
+            "paths": ["a.txt", "b.txt"],
+        }))
+        .unwrap();
+        match searcher.search(&options).await {
+            Err(AppError::TooManyPaths(2, 1)) => {}
+            other => panic!("Expected the reloaded path limit to apply, got {:?}", other),
+        }
+        
+        // The reloaded result cap and excludes apply to a whole-root search
+        let options = SearchOptions::new("needle");
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 1);
+        assert!(result.matches[0].contains("a.txt"));
+        assert!(result.stats.truncated);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,130 @@
+/// Resource use of one ripgrep process, best effort; each field is `None`
+/// where the platform does not expose it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    pub peak_rss_kb: Option<u64>,
+    pub user_cpu_ms: Option<u64>,
+    pub system_cpu_ms: Option<u64>,
+}
+
+/// Linux reports CPU time in `/proc` in units of USER_HZ, fixed at 100
+#[cfg(target_os = "linux")]
+const CLOCK_TICKS_PER_SEC: u64 = 100;
+
+/// Samples a child process while it runs. The peak resident size is only
+/// readable while the process is alive, so it keeps the largest seen;
+/// CPU times stay readable until the exited child is reaped
+#[derive(Debug)]
+pub struct ResourceMonitor {
+    pid: Option<u32>,
+    usage: ResourceUsage,
+}
+
+impl ResourceMonitor {
+    pub fn new(pid: Option<u32>) -> Self {
+        Self {
+            pid,
+            usage: ResourceUsage::default(),
+        }
+    }
+    
+    /// Take one sample; returns whether the process has exited and awaits
+    /// reaping, after which its CPU times no longer change
+    #[cfg(target_os = "linux")]
+    pub fn sample(&mut self) -> bool {
+        let Some(pid) = self.pid else {
+            return true;
+        };
+        
+        if let Ok(status) = std::fs::read_to_string(format!("/proc/{}/status", pid)) {
+            if let Some(kb) = parse_peak_rss_kb(&status) {
+                self.usage.peak_rss_kb = Some(self.usage.peak_rss_kb.map_or(kb, |peak| peak.max(kb)));
+            }
+        }
+        match std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok().as_deref().and_then(parse_stat) {
+            Some(stat) => {
+                self.usage.user_cpu_ms = Some(stat.user_ticks * 1000 / CLOCK_TICKS_PER_SEC);
+                self.usage.system_cpu_ms = Some(stat.system_ticks * 1000 / CLOCK_TICKS_PER_SEC);
+                stat.state == 'Z'
+            }
+            None => true,
+        }
+    }
+    
+    #[cfg(not(target_os = "linux"))]
+    pub fn sample(&mut self) -> bool {
+        true
+    }
+    
+    pub fn usage(&self) -> ResourceUsage {
+        self.usage
+    }
+}
+
+/// `VmHWM`, the peak resident set size, from `/proc/<pid>/status`
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_peak_rss_kb(status: &str) -> Option<u64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+}
+
+/// The fields of `/proc/<pid>/stat` a sample needs
+#[derive(Debug, PartialEq, Eq)]
+struct ProcStat {
+    state: char,
+    user_ticks: u64,
+    system_ticks: u64,
+}
+
+/// Parse `pid (comm) state ppid ...`; the command name may hold spaces and
+/// parentheses, so fields are counted from the last `)`
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_stat(stat: &str) -> Option<ProcStat> {
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    Some(ProcStat {
+        state: fields.first()?.chars().next()?,
+        user_ticks: fields.get(11)?.parse().ok()?,
+        system_ticks: fields.get(12)?.parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_proc_files() {
+        let status = "Name:\trg\nVmPeak:\t  20000 kB\nVmHWM:\t    5120 kB\nVmRSS:\t    4096 kB\n";
+        assert_eq!(parse_peak_rss_kb(status), Some(5120));
+        assert_eq!(parse_peak_rss_kb("Name:\trg\n"), None);
+        
+        let stat = "4242 (rg (x) y) Z 1 4242 4242 0 -1 4194560 100 0 0 0 37 5 0 0 20 0 1 0";
+        assert_eq!(
+            parse_stat(stat),
+            Some(ProcStat {
+                state: 'Z',
+                user_ticks: 37,
+                system_ticks: 5,
+            })
+        );
+        assert_eq!(parse_stat("4242 (rg) R 1"), None);
+    }
+    
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sample_running_process() {
+        let mut monitor = ResourceMonitor::new(Some(std::process::id()));
+        assert!(!monitor.sample());
+        let usage = monitor.usage();
+        assert!(usage.peak_rss_kb.is_some_and(|kb| kb > 0));
+        assert!(usage.user_cpu_ms.is_some());
+        assert!(usage.system_cpu_ms.is_some());
+        
+        // A process that is gone reports nothing new
+        let mut gone = ResourceMonitor::new(Some(u32::MAX));
+        assert!(gone.sample());
+        assert_eq!(gone.usage(), ResourceUsage::default());
+    }
+}
\ No newline at end of file
@@ -1,18 +1,44 @@
-use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
 use tokio::process::Command as TokioCommand;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{Notify, OnceCell};
 use serde::{Deserialize, Serialize};
-use tracing::{debug, error, instrument};
+use tracing::{debug, error, instrument, trace, warn};
 use crate::error::AppError;
+use crate::definitions::definition_pattern;
+use crate::resources::{ResourceMonitor, ResourceUsage};
+use crate::session::SessionBudget;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SearchOptions {
     /// Search pattern
     pub pattern: String,
     
+    /// Patterns to try in order when `pattern` matches nothing; the first
+    /// one that matches is returned, named by `matched_pattern_index`
+    #[serde(default)]
+    pub pattern_fallbacks: Vec<String>,
+    
     /// Relative path within the root directory
     #[serde(default)]
     pub path: String,
     
+    /// Which configured root `path` is relative to (0 is the first root,
+    /// the default)
+    #[serde(default)]
+    pub root_index: Option<usize>,
+    
+    /// Additional relative paths (files or directories) to search
+    #[serde(default)]
+    pub paths: Vec<String>,
+    
     /// Use fixed strings instead of regex (literal search)
     #[serde(default)]
     pub fixed_strings: bool,
@@ -21,6 +47,25 @@ pub struct SearchOptions {
     #[serde(default)]
     pub case_sensitive: bool,
     
+    /// How case affects matching; takes precedence over `case_sensitive`
+    #[serde(default)]
+    pub case_mode: Option<CaseMode>,
+    
+    /// Return results in this order (`--sort`) so identical searches come
+    /// back identically. Sorting makes ripgrep search on a single thread
+    #[serde(default)]
+    pub sort: Option<SortMode>,
+    
+    /// Add the 1-based byte column of the first match to each line
+    /// (`--column`), and the column of each match to `structured_matches`
+    #[serde(default)]
+    pub columns: bool,
+    
+    /// Prefix each line with its 0-based byte offset in the file (`-b`),
+    /// and add a `byte_offset` to each entry of `structured_matches`
+    #[serde(default)]
+    pub byte_offset: bool,
+    
     /// Include line numbers in output
     #[serde(default = "default_true")]
     pub line_numbers: bool,
@@ -36,238 +81,8161 @@ pub struct SearchOptions {
     /// Maximum depth to search
     #[serde(default)]
     pub max_depth: Option<usize>,
+    
+    /// Skip files larger than this (`--max-filesize`): a byte count with an
+    /// optional `K`, `M` or `G` suffix, like `"500K"` or `"1M"`
+    #[serde(default)]
+    pub max_filesize: Option<String>,
+    
+    /// Return only the matched text plus this many characters of the
+    /// surrounding line on each side, instead of the whole line
+    #[serde(default)]
+    pub match_window_chars: Option<usize>,
+    
+    /// Ignore sources to honor; when set, every source not listed is disabled
+    #[serde(default)]
+    pub use_ignore_files: Option<Vec<IgnoreSource>>,
+    
+    /// Search hidden files and directories too (`--hidden`)
+    #[serde(default)]
+    pub search_hidden: bool,
+    
+    /// Search files that ignore files would skip (`--no-ignore`)
+    #[serde(default)]
+    pub no_ignore: bool,
+    
+    /// Restrict the search to the files matched by an earlier search,
+    /// identified by the `search_id` it returned
+    #[serde(default)]
+    pub within_results_of: Option<String>,
+    
+    /// Name this search so `cancel_search` can stop it while it runs; the
+    /// result's `search_id` then takes this value. Not part of what
+    /// matches, so it stays out of the result cache key
+    #[serde(default, skip_serializing)]
+    pub search_id: Option<String>,
+    
+    /// Also report the total number of individual matches, computed with a
+    /// separate count-only pass
+    #[serde(default)]
+    pub report_total: bool,
+    
+    /// Named search profile whose defaults apply to unset options
+    #[serde(default)]
+    pub profile: Option<String>,
+    
+    /// Return one summary per matched file instead of every match
+    #[serde(default)]
+    pub summary_per_file: bool,
+    
+    /// Return structured matches carrying each match's absolute byte range
+    /// within its file
+    #[serde(default)]
+    pub byte_ranges: bool,
+    
+    /// Shape of the lines returned in `matches`
+    #[serde(default)]
+    pub output_format: OutputFormat,
+    
+    /// Only search files that also contain this secondary pattern
+    #[serde(default)]
+    pub only_in_files_matching: Option<String>,
+    
+    /// Follow symbolic links while walking directories (`-L`). Requested
+    /// paths are still resolved and must lie inside a root
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    
+    /// Follow symbolic links, but only return matches from files whose
+    /// resolved target stays inside the root
+    #[serde(default)]
+    pub follow_internal_symlinks_only: bool,
+    
+    /// Score matches with a relevance heuristic and sort by descending score
+    #[serde(default)]
+    pub rank_relevance: bool,
+    
+    /// Only search files modified after this reference file
+    #[serde(default)]
+    pub newer_than: Option<String>,
+    
+    /// Only search files modified before this reference file
+    #[serde(default)]
+    pub older_than: Option<String>,
+    
+    /// Stop after the first matching file and return only its matches
+    #[serde(default)]
+    pub first_result_fast: bool,
+    
+    /// Stop ripgrep once this many matching lines have been read
+    #[serde(default)]
+    pub hard_limit: Option<usize>,
+    
+    /// After an early-stopped search, run a count-only pass so
+    /// `total_matches` still reports the true total
+    #[serde(default)]
+    pub reconcile_counts: bool,
+    
+    /// Collapse accidental double escapes such as `\\d` before searching
+    #[serde(default)]
+    pub normalize_pattern: bool,
+    
+    /// Return each match's text and its byte span within the line
+    #[serde(default)]
+    pub highlight_matches: bool,
+    
+    /// Attach each matched file's most recent git commit
+    #[serde(default)]
+    pub include_last_commit: bool,
+    
+    /// Explain which files ripgrep skipped and why
+    #[serde(default)]
+    pub diagnose: bool,
+    
+    /// Truncate `matches` to fit this many estimated tokens
+    #[serde(default)]
+    pub token_budget: Option<usize>,
+    
+    /// Search the valid entries of `path`/`paths` and report the invalid
+    /// ones instead of failing the request
+    #[serde(default)]
+    pub skip_invalid_paths: bool,
+    
+    /// Cut `match_window_chars` windows at whitespace or token boundaries
+    /// and mark the cuts with `…`
+    #[serde(default)]
+    pub smart_truncate: bool,
+    
+    /// Annotate structured matches with their line distance from this line
+    #[serde(default)]
+    pub relative_to_line: Option<u64>,
+    
+    /// Merge overlapping or adjacent match and context lines into
+    /// contiguous per-file blocks
+    #[serde(default)]
+    pub merge_context: bool,
+    
+    /// Print only the matched parts of each line, one entry per match;
+    /// structured matches then always carry `matched_text`
+    #[serde(default)]
+    pub only_matching: bool,
+    
+    /// Return a frequency count of the distinct matched strings
+    #[serde(default)]
+    pub histogram: bool,
+    
+    /// Keep ripgrep's ANSI color codes in plain text output
+    #[serde(default)]
+    pub color: bool,
+    
+    /// List each matched file with its path split into components
+    #[serde(default)]
+    pub path_components: bool,
+    
+    /// Keep only the first N matches on each line
+    #[serde(default)]
+    pub max_submatches_per_line: Option<usize>,
+    
+    /// Terminate each path with NUL (`--null`) in plain text output
+    #[serde(default)]
+    pub null_output: bool,
+    
+    /// Rank matched files by matches per line of the file
+    #[serde(default)]
+    pub density_report: bool,
+    
+    /// List each matched file with a MIME type guessed from its extension
+    #[serde(default)]
+    pub include_mime: bool,
+    
+    /// Split matches into those inside comments and those in code, by a
+    /// per-language comment marker heuristic
+    #[serde(default)]
+    pub partition_comments: bool,
+    
+    /// Also count matches per ripgrep file type
+    #[serde(default)]
+    pub type_breakdown: bool,
+    
+    /// Only search files matching these globs (`-g`)
+    #[serde(default)]
+    pub globs: Vec<String>,
+    
+    /// Skip files and directories matching these globs (`-g !glob`)
+    #[serde(default)]
+    pub exclude_globs: Vec<String>,
+    
+    /// Return ripgrep's `--json` event lines in `matches` unmodified
+    #[serde(default)]
+    pub raw_json: bool,
+    
+    /// Only match whole words (`-w`)
+    #[serde(default)]
+    pub word_boundary: bool,
+    
+    /// Return the file itself when the pattern is the path of a file
+    /// under the root, instead of searching for that text
+    #[serde(default)]
+    pub smart_path_pattern: bool,
+    
+    /// Let matches span lines (`-U`), so the pattern may contain `\n`
+    #[serde(default)]
+    pub multiline: bool,
+    
+    /// Let `.` match newlines too (`--multiline-dotall`); implies `multiline`
+    #[serde(default)]
+    pub multiline_dotall: bool,
+    
+    /// Use the PCRE2 engine (`-P`) for lookaround and backreferences
+    #[serde(default)]
+    pub pcre2: bool,
+    
+    /// Text encoding of the searched files (`--encoding`, e.g. `latin1`,
+    /// `utf-16`); ripgrep transcodes them to UTF-8
+    #[serde(default)]
+    pub encoding: Option<String>,
+    
+    /// Replace invalid UTF-8 in ripgrep's output with U+FFFD instead of
+    /// failing the search
+    #[serde(default)]
+    pub lossy_decode: bool,
+    
+    /// Only match where the pattern is being defined, e.g. `fn name` or
+    /// `class name`, using declaration keywords of the requested file types
+    #[serde(default)]
+    pub definitions_only: bool,
+    
+    /// Return the lines that do not match the pattern (`-v`)
+    #[serde(default)]
+    pub invert_match: bool,
+    
+    /// Search `path` and then each parent directory up to the root, one
+    /// level at a time, stopping at the first level with matches
+    #[serde(default)]
+    pub search_upward: bool,
+    
+    /// Return at most this many entries in `matches` (or `structured_matches`)
+    #[serde(default)]
+    pub max_results: Option<usize>,
+    
+    /// Stop reading each file after this many matching lines (`-m`)
+    #[serde(default)]
+    pub max_count_per_file: Option<usize>,
+    
+    /// Return a reproducible pseudo-random sample of this many entries
+    /// drawn from all of `matches`
+    #[serde(default)]
+    pub sample: Option<usize>,
+    
+    /// Attach a `manifest` recording how to reproduce the search
+    #[serde(default)]
+    pub include_manifest: bool,
+    
+    /// Report the ripgrep process's peak memory and CPU time in `stats`,
+    /// best effort. Measured searches run ripgrep to completion
+    #[serde(default)]
+    pub resource_report: bool,
+    
+    /// Attach a `scope` describing the effective search: resolved root and
+    /// paths, filters and the ignore rules that applied
+    #[serde(default)]
+    pub include_scope: bool,
+    
+    /// Stop ripgrep and fail the search once the whole search, every ripgrep
+    /// pass included, has taken this many milliseconds; defaults to the
+    /// server's `SEARCH_TIMEOUT_MS`
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    
+    /// Attach the line before each match as `prev_line` in `structured_matches`
+    #[serde(default)]
+    pub include_prev_line: bool,
+    
+    /// Attach each match line's leading whitespace width as `indent` in
+    /// `structured_matches`, a hint of its nesting depth
+    #[serde(default)]
+    pub include_indent: bool,
+    
+    /// Columns a tab counts for in `indent`; 4 by default
+    #[serde(default)]
+    pub tab_width: Option<usize>,
+    
+    /// Return the result gzipped and base64-encoded instead of as plain JSON
+    #[serde(default)]
+    pub compress_response: bool,
+    
+    /// Show matched lines with each match replaced by this text (`-r`,
+    /// capture groups like `$1` allowed). A preview only: files are never
+    /// modified
+    #[serde(default)]
+    pub replace: Option<String>,
+    
+    /// With `replace`, also return unified-diff hunks for each changed
+    /// region, ready for `patch` or `git apply`. `context_lines` sets the
+    /// hunk context (default 3)
+    #[serde(default)]
+    pub patch_hunks: bool,
+    
+    /// Report `stats.match_ratio`, the share of searched files that matched
+    #[serde(default)]
+    pub match_ratio: bool,
+    
+    /// Return one single-line snippet per match, with ellipsis markers
+    /// where the line was cut
+    #[serde(default)]
+    pub snippet: Option<SnippetOptions>,
+    
+    /// Ripgrep worker threads (`-j`), assigned by the server from the global
+    /// thread budget rather than by clients
+    #[serde(skip)]
+    pub threads: Option<usize>,
+    
+    /// Custom file type definitions (`--type-add`), set by the server from
+    /// its configuration rather than by clients
+    #[serde(skip)]
+    pub type_add: Vec<String>,
+    
+    /// File extensions searches are limited to, set by the server from its
+    /// configuration rather than by clients
+    #[serde(skip)]
+    pub allowed_extensions: Option<Vec<String>>,
+    
+    /// Where each output line goes as ripgrep produces it, for transports
+    /// that stream results; set by the server rather than by clients
+    #[serde(skip)]
+    pub line_sink: Option<UnboundedSender<String>>,
+}
+
+/// How letter case affects matching
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaseMode {
+    /// Case must match exactly
+    Sensitive,
+    /// Case is ignored (`-i`)
+    #[default]
+    Insensitive,
+    /// Case is ignored unless the pattern has an uppercase letter (`-S`)
+    Smart,
+}
+
+/// Order of files in a sorted search, ascending
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortMode {
+    Path,
+    Modified,
+    Accessed,
+    Created,
+}
+
+impl SortMode {
+    /// The value ripgrep's `--sort` takes
+    fn flag_value(self) -> &'static str {
+        match self {
+            SortMode::Path => "path",
+            SortMode::Modified => "modified",
+            SortMode::Accessed => "accessed",
+            SortMode::Created => "created",
+        }
+    }
+}
+
+/// How match lines are rendered into `SearchResult::matches`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// ripgrep's own output, passed through unchanged
+    #[default]
+    Text,
+    /// `git grep` conventions: `path:line:text` with paths relative to the root
+    GitGrep,
+    /// Parsed matches in `structured_matches` instead of lines in `matches`
+    Json,
+    /// Matching lines grouped by file in `grouped_matches` instead of
+    /// lines in `matches`
+    Grouped,
+}
+
+/// A source of ignore rules that ripgrep honors by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IgnoreSource {
+    /// `.gitignore` and other version control ignore files
+    Vcs,
+    /// `.ignore` and `.rgignore` files
+    Dot,
+    /// Ignore files found in parent directories of the search path
+    Parent,
+    /// The global gitignore configured through git
+    Global,
+    /// Repository-local `.git/info/exclude` rules
+    Exclude,
+}
+
+impl IgnoreSource {
+    const ALL: [IgnoreSource; 5] = [
+        IgnoreSource::Vcs,
+        IgnoreSource::Dot,
+        IgnoreSource::Parent,
+        IgnoreSource::Global,
+        IgnoreSource::Exclude,
+    ];
+    
+    /// The ripgrep flag that turns this source off
+    fn disable_flag(self) -> &'static str {
+        match self {
+            IgnoreSource::Vcs => "--no-ignore-vcs",
+            IgnoreSource::Dot => "--no-ignore-dot",
+            IgnoreSource::Parent => "--no-ignore-parent",
+            IgnoreSource::Global => "--no-ignore-global",
+            IgnoreSource::Exclude => "--no-ignore-exclude",
+        }
+    }
+}
+
+impl SearchOptions {
+    /// Options for `pattern` with every other field at its request default
+    pub fn new(pattern: impl Into<String>) -> Self {
+        // Deserializing keeps this in step with the serde defaults above
+        serde_json::from_value(serde_json::json!({ "pattern": pattern.into() }))
+            .expect("a lone pattern is always valid search options")
+    }
+    
+    /// The case mode in effect, falling back to the `case_sensitive` flag
+    fn case(&self) -> CaseMode {
+        match self.case_mode {
+            Some(mode) => mode,
+            None if self.case_sensitive => CaseMode::Sensitive,
+            None => CaseMode::default(),
+        }
+    }
+    
+    /// Whether the requested output needs ripgrep's `--json` event stream
+    fn uses_json_output(&self) -> bool {
+        self.match_window_chars.is_some()
+            || self.summary_per_file
+            || self.wants_structured()
+            || self.output_format != OutputFormat::Text
+            || self.first_result_fast
+            || self.hard_limit.is_some()
+            || self.merge_context
+            || self.histogram
+            || self.density_report
+            || self.partition_comments
+            || self.type_breakdown
+            || self.raw_json
+            || self.snippet.is_some()
+    }
+    
+    /// Whether the result should carry `structured_matches`
+    fn wants_structured(&self) -> bool {
+        self.byte_ranges
+            || self.output_format == OutputFormat::Json
+            || self.include_prev_line
+            || self.include_indent
+            || self.rank_relevance
+            || self.highlight_matches
+            || self.relative_to_line.is_some()
+    }
+    
+    /// Whether ripgrep can be stopped once `max_results` is exceeded: only
+    /// when the cap keeps the first lines, not when every match feeds an
+    /// aggregate, a sample or the token budget
+    fn stops_at_max_results(&self) -> bool {
+        self.max_results.is_some()
+            && self.sample.is_none()
+            && self.token_budget.is_none()
+            && !self.summary_per_file
+            && !self.merge_context
+            && !self.histogram
+            && !self.density_report
+            && !self.partition_comments
+            && !self.type_breakdown
+            && !self.resource_report
+    }
+    
+    /// Whether a result can be replayed while the searched files are
+    /// unchanged; early-stopped searches and git history can differ
+    fn cacheable(&self) -> bool {
+        !self.first_result_fast
+            && self.hard_limit.is_none()
+            && !self.include_last_commit
+            && !self.include_manifest
+            && !self.resource_report
+    }
+    
+    /// Whether the pattern matches every line (empty, `.*`, `^`, `$`) while
+    /// no path, file type, glob or earlier result narrows the search
+    fn is_degenerate(&self) -> bool {
+        let narrowed = self.requested_paths().iter().any(|p| Path::new(p) != Path::new("."))
+            || !self.file_types.is_empty()
+            || !self.globs.is_empty()
+            || self.within_results_of.is_some();
+        let pattern = self.pattern.as_str();
+        let degenerate = if self.fixed_strings {
+            pattern.is_empty()
+        } else {
+            let core = pattern.strip_prefix('^').unwrap_or(pattern);
+            let core = core.strip_suffix('$').unwrap_or(core);
+            matches!(pattern, "" | "^" | "$") || matches!(core, ".*" | ".*?")
+        };
+        degenerate && !narrowed
+    }
+    
+    /// Every path the client asked for, in order; empty means the whole root
+    fn requested_paths(&self) -> Vec<&str> {
+        std::iter::once(self.path.as_str())
+            .filter(|p| !p.is_empty())
+            .chain(self.paths.iter().map(String::as_str))
+            .collect()
+    }
 }
 
 fn default_true() -> bool {
     true
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// Shape of the per-match snippets
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SnippetOptions {
+    /// Characters kept before the match
+    pub before_chars: usize,
+    
+    /// Characters kept after the match
+    pub after_chars: usize,
+    
+    /// Marker for a side that was cut short of the line boundary
+    #[serde(default = "default_ellipsis")]
+    pub ellipsis: String,
+}
+
+fn default_ellipsis() -> String {
+    "…".to_string()
+}
+
+/// Canned defaults for a class of searches (e.g. "docs" or "code"),
+/// applied wherever the request leaves the option unset; excludes are added
+/// to the request's own
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SearchProfile {
+    /// File types to include when the request names none
+    #[serde(default)]
+    pub file_types: Vec<String>,
+    
+    /// Maximum depth when the request sets none
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+    
+    /// Glob patterns to exclude, added to the request's own
+    #[serde(default)]
+    pub exclude_globs: Vec<String>,
+}
+
+impl SearchProfile {
+    /// Fill in the options this profile covers that the request left unset
+    fn apply(&self, options: &mut SearchOptions) {
+        if options.file_types.is_empty() {
+            options.file_types = self.file_types.clone();
+        }
+        if options.max_depth.is_none() {
+            options.max_depth = self.max_depth;
+        }
+        for glob in &self.exclude_globs {
+            if !options.exclude_globs.contains(glob) {
+                options.exclude_globs.push(glob.clone());
+            }
+        }
+    }
+}
+
+/// Default cap on the number of paths a single search may name
+pub const DEFAULT_MAX_PATHS_PER_REQUEST: usize = 100;
+
+/// Default depth limit applied when following symlinks without a `max_depth`
+pub const DEFAULT_SYMLINK_MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct SearchResult {
+    /// Identifies this search for later refinement via `within_results_of`
+    pub search_id: String,
+    
+    /// The effective search, when `include_scope` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<SearchScope>,
+    
     pub matches: Vec<String>,
+    
+    /// Unified-diff hunks of the `replace` preview, when `patch_hunks` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub patch_hunks: Option<Vec<PatchHunk>>,
+    
+    /// Matching lines per file, returned instead of `matches` with the
+    /// `grouped` output format
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grouped_matches: Option<Vec<FileMatches>>,
+    
+    /// Per-file overview, returned instead of `matches` when requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_summaries: Option<Vec<FileSummary>>,
+    
+    /// One entry per individual match, returned instead of `matches` when
+    /// a structured option is requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub structured_matches: Option<Vec<StructuredMatch>>,
+    
+    /// Most recent commit per matched file, when requested inside a git
+    /// work tree
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_commits: Option<Vec<FileCommit>>,
+    
+    /// Contiguous line ranges, returned instead of `matches` when
+    /// `merge_context` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_blocks: Option<Vec<ContextBlock>>,
+    
+    /// Distinct matched strings by descending count, returned instead of
+    /// `matches` when `histogram` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub histogram: Option<Vec<HistogramEntry>>,
+    
+    /// Matched files by descending match density, returned instead of
+    /// `matches` when `density_report` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub density: Option<Vec<FileDensity>>,
+    
+    /// Matched lines split by whether the match sits in a comment, returned
+    /// instead of `matches` when `partition_comments` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment_partition: Option<CommentPartition>,
+    
+    /// Individual matches per ripgrep file type, when `type_breakdown` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub type_breakdown: Option<Vec<TypeCount>>,
+    
+    /// Matched files with their root-relative path components and/or MIME
+    /// type, when `path_components` or `include_mime` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matched_files: Option<Vec<MatchedFile>>,
+    
+    /// Root-relative directory whose files matched, when `search_upward` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upward_level: Option<String>,
+    
+    /// Which pattern matched when `pattern_fallbacks` is set: 0 for
+    /// `pattern`, then 1 for the first fallback and so on; absent when
+    /// none did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matched_pattern_index: Option<usize>,
+    
+    /// What was run, when `include_manifest` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<SearchManifest>,
+    
+    /// Requested paths left out by `skip_invalid_paths`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub invalid_paths: Vec<String>,
+    
+    /// Files ripgrep skipped and the reason, when `diagnose` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostics: Option<Vec<String>>,
+    
+    /// Notes about adjustments made to the request
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    
     pub stats: SearchStats,
 }
 
+/// A single match; a line with several matches yields several entries
 #[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct SearchStats {
-    pub matched_lines: usize,
-    pub elapsed_ms: u64,
-}
-
-#[derive(Debug)]
-pub struct RipgrepSearcher {
-    root_dir: PathBuf,
+pub struct StructuredMatch {
+    /// Hash of path, line number and matched text, so the same match keeps
+    /// its id across searches regardless of ordering
+    pub match_id: String,
+    
+    pub path: String,
+    pub line_number: Option<u64>,
+    
+    /// 1-based byte column of the match start, with the `json` output
+    /// format or `columns`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    
+    /// The full matched line, without its line terminator
+    pub full_line: String,
+    
+    /// The line before the match, when `include_prev_line` is set; absent
+    /// for a match on the first line of its file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev_line: Option<String>,
+    
+    /// Width of the line's leading whitespace, when `include_indent` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub indent: Option<usize>,
+    
+    /// The exact text of this match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matched_text: Option<String>,
+    
+    /// Byte offset of the match start within `full_line`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_start: Option<usize>,
+    
+    /// Byte offset just past the match end within `full_line`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_end: Option<usize>,
+    
+    /// Absolute byte offset of the match start within the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_byte: Option<u64>,
+    
+    /// Absolute byte offset just past the match end within the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_byte: Option<u64>,
+    
+    /// With `byte_offset`, the 0-based offset within the file of the
+    /// matching line, or of the match itself with `only_matching`, as
+    /// ripgrep's `-b` reports it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub byte_offset: Option<u64>,
+    
+    /// Lines from `relative_to_line` to this match; negative when the match
+    /// comes before the reference line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta_lines: Option<i64>,
+    
+    /// Relevance score when `rank_relevance` is set; higher ranks first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<u32>,
+}
+
+/// Everything needed to rerun a search by hand
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SearchManifest {
+    /// First line of `rg --version`
+    pub ripgrep_version: String,
+    
+    /// Arguments ripgrep was run with, after every option was resolved
+    pub args: Vec<String>,
+    
+    /// Canonical files root
+    pub root: String,
+    
+    /// Seconds since the Unix epoch when the search ran
+    pub timestamp: u64,
+}
+
+/// Readiness of the server, as the `health` tool reports it
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HealthReport {
+    /// Ripgrep runs and every root exists
+    pub ready: bool,
+    
+    /// The ripgrep binary searches run
+    pub rg_path: String,
+    
+    /// First line of `rg --version`, when ripgrep could be run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rg_version: Option<String>,
+    
+    /// Why ripgrep could not be run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rg_error: Option<String>,
+    
+    /// Configured roots in `root_index` order
+    pub roots: Vec<RootHealth>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RootHealth {
+    pub path: String,
+    
+    /// Whether the root is currently an existing directory
+    pub exists: bool,
+}
+
+/// A run of consecutive lines from one file, mixing matches and context
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ContextBlock {
+    pub path: String,
+    pub start_line: u64,
+    pub end_line: u64,
+    
+    /// Line texts from `start_line` to `end_line`, without terminators
+    pub lines: Vec<String>,
+}
+
+/// How the server interpreted a search request
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SearchScope {
+    /// Canonical root the paths are relative to
+    pub root: String,
+    
+    /// Searched paths after resolution, relative to `root` (`.` for the
+    /// root itself)
+    pub paths: Vec<String>,
+    pub file_types: Vec<String>,
+    pub globs: Vec<String>,
+    pub exclude_globs: Vec<String>,
+    pub max_depth: Option<usize>,
+    
+    /// Ignore sources that were honored; empty with `no_ignore`
+    pub ignore_sources: Vec<IgnoreSource>,
+    pub search_hidden: bool,
+}
+
+/// Arguments of the `cancel_search` tool
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CancelRequest {
+    /// The `search_id` the running search was started with
+    pub search_id: String,
+}
+
+/// Whether `cancel_search` found and stopped a running search
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CancelResult {
+    pub search_id: String,
+    pub cancelled: bool,
+}
+
+/// One changed region of a `replace` preview as a unified diff
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PatchHunk {
+    /// Path relative to the root, as used in the diff headers
+    pub path: String,
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    
+    /// File headers, `@@` line and the ` `/`-`/`+` prefixed lines
+    pub diff: String,
+}
+
+/// A matched file with the details the request asked for
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MatchedFile {
+    pub path: String,
+    
+    /// Path relative to the root, split into components
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub components: Vec<String>,
+    
+    /// Best-effort MIME type
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>,
+}
+
+/// How often one distinct matched string occurred
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HistogramEntry {
+    pub value: String,
+    pub count: usize,
+}
+
+/// How concentrated a file's matches are
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FileDensity {
+    pub path: String,
+    pub match_count: usize,
+    pub line_count: usize,
+    
+    /// Matches per line of the file
+    pub density: f64,
+}
+
+/// Matched lines split by a comment heuristic; a line with matches in both
+/// places appears in both lists
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CommentPartition {
+    pub in_comments: Vec<String>,
+    pub in_code: Vec<String>,
+}
+
+/// How many matches fell in files of one ripgrep type (`other` when no
+/// type claims the file)
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TypeCount {
+    pub file_type: String,
+    pub count: usize,
+}
+
+/// Input of the `list_files` tool
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ListFilesOptions {
+    /// Relative path within the root directory
+    #[serde(default)]
+    pub path: String,
+    
+    /// Which configured root `path` is relative to (0 is the first root,
+    /// the default)
+    #[serde(default)]
+    pub root_index: Option<usize>,
+    
+    /// File types to include
+    #[serde(default)]
+    pub file_types: Vec<String>,
+    
+    /// Glob patterns a file must match (`-g`)
+    #[serde(default)]
+    pub globs: Vec<String>,
+    
+    /// Maximum directory depth to descend
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+}
+
+/// Result of the `count` tool: match totals without the matched lines
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CountResult {
+    pub total: usize,
+    
+    /// Matches per file that has any, ordered by path
+    pub per_file: Vec<FileCount>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FileCount {
+    pub path: String,
+    pub count: usize,
+}
+
+/// The last commit that touched a matched file
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FileCommit {
+    pub path: String,
+    
+    /// Full commit hash
+    pub commit: String,
+    
+    /// Committer date in ISO 8601 format
+    pub date: String,
+}
+
+/// The matching lines of one file, in file order
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FileMatches {
+    pub path: String,
+    pub matches: Vec<LineMatch>,
+}
+
+/// One matching line within a `FileMatches` group
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LineMatch {
+    pub line_number: Option<u64>,
+    
+    /// The line without its terminator
+    pub text: String,
+}
+
+/// Overview of the matches within one file
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FileSummary {
+    pub path: String,
+    
+    /// Individual matches in the file (a line can hold several)
+    pub match_count: usize,
+    
+    /// Line number of the first match
+    pub first_line: Option<u64>,
+    
+    /// Full text of the first matching line
+    pub first_match_text: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SearchStats {
+    /// Lines returned; with `invert_match` these are the lines that did not
+    /// match the pattern
+    pub matched_lines: usize,
+    pub elapsed_ms: u64,
+    
+    /// Total individual matches (a line can hold several), when requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_matches: Option<usize>,
+    
+    /// Set when ripgrep was stopped early, so more matches may exist
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+    
+    /// Estimated tokens in the returned `matches`, when a budget was given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_estimate: Option<usize>,
+    
+    /// Set when lines were dropped to fit the token budget
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    
+    /// Set when the result was served from the result cache
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+    
+    /// Lines whose matches were cut to `max_submatches_per_line`
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub capped_lines: usize,
+    
+    /// Files ripgrep read, from its own stats; unknown when it was stopped
+    /// early or a file filter dropped part of its output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files_searched: Option<usize>,
+    
+    /// Files with at least one match, from ripgrep's own stats, or counted
+    /// from the returned output when those are unknown
+    #[serde(default)]
+    pub files_with_matches: usize,
+    
+    /// `files_with_matches / files_searched` when requested and both are
+    /// known: low for a rare term, high for one that may be too broad
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_ratio: Option<f64>,
+    
+    /// Peak resident memory of ripgrep, when `resource_report` is set and
+    /// the platform exposes it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_rss_kb: Option<u64>,
+    
+    /// User CPU time of ripgrep, when `resource_report` is set and the
+    /// platform exposes it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_cpu_ms: Option<u64>,
+    
+    /// System CPU time of ripgrep, likewise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_cpu_ms: Option<u64>,
+}
+
+/// How long the matched files of a search stay available for refinement
+const RESULT_FILES_TTL: Duration = Duration::from_secs(600);
+
+/// How many searches' matched files are remembered at once
+const RESULT_FILES_CAPACITY: usize = 64;
+
+/// How many distinct requests the result cache holds at once
+const RESULT_CACHE_CAPACITY: usize = 64;
+
+/// Cheap summary of the files a search could read: a change to any of
+/// them (edit, addition or removal) changes the signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DirSignature {
+    file_count: usize,
+    latest_mtime: Option<SystemTime>,
+}
+
+#[derive(Debug)]
+struct CachedResult {
+    created: Instant,
+    signature: DirSignature,
+    result: SearchResult,
+    files: Vec<PathBuf>,
+}
+
+/// Bounded, expiring cache of search results keyed by request. Entries are
+/// only served while the searched files' signature is unchanged
+#[derive(Debug)]
+struct ResultCache {
+    entries: Mutex<HashMap<String, CachedResult>>,
+}
+
+impl ResultCache {
+    fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+    
+    fn insert(&self, key: String, entry: CachedResult, ttl: Duration) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, cached| cached.created.elapsed() < ttl);
+        
+        // Evict the oldest entry once full
+        if entries.len() >= RESULT_CACHE_CAPACITY {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, cached)| cached.created)
+                .map(|(key, _)| key.clone());
+            if let Some(key) = oldest {
+                entries.remove(&key);
+            }
+        }
+        
+        entries.insert(key, entry);
+    }
+    
+    /// A fresh entry whose signature still matches; stale ones are dropped
+    fn get(&self, key: &str, signature: DirSignature, ttl: Duration) -> Option<(SearchResult, Vec<PathBuf>)> {
+        let mut entries = self.entries.lock().unwrap();
+        let cached = entries.get(key)?;
+        if cached.created.elapsed() < ttl && cached.signature == signature {
+            return Some((cached.result.clone(), cached.files.clone()));
+        }
+        
+        entries.remove(key);
+        None
+    }
+    
+    fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Bounded, expiring map of search ID to the files that search matched
+#[derive(Debug)]
+struct ResultFiles {
+    entries: Mutex<HashMap<String, (Instant, Vec<PathBuf>)>>,
+    ttl: Duration,
+    capacity: usize,
+}
+
+impl ResultFiles {
+    fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            ttl,
+            capacity,
+        }
+    }
+    
+    fn insert(&self, search_id: String, files: Vec<PathBuf>) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (created, _)| created.elapsed() < self.ttl);
+        
+        // Evict the oldest entry once full
+        if entries.len() >= self.capacity {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (created, _))| *created)
+                .map(|(id, _)| id.clone());
+            if let Some(id) = oldest {
+                entries.remove(&id);
+            }
+        }
+        
+        entries.insert(search_id, (Instant::now(), files));
+    }
+    
+    fn get(&self, search_id: &str) -> Option<Vec<PathBuf>> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(search_id)
+            .filter(|(created, _)| created.elapsed() < self.ttl)
+            .map(|(_, files)| files.clone())
+    }
+}
+
+/// Searches started with a client-chosen `search_id`, so `cancel_search`
+/// can stop them while they run
+#[derive(Debug, Default)]
+struct ActiveSearches {
+    entries: Mutex<HashMap<String, Arc<Notify>>>,
+}
+
+impl ActiveSearches {
+    /// Register a running search; it stays cancellable until the guard drops
+    fn register(&self, search_id: &str) -> Result<ActiveSearch<'_>, AppError> {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.contains_key(search_id) {
+            return Err(AppError::ConfigError(format!("A search with id {:?} is already running", search_id)));
+        }
+        let notify = Arc::new(Notify::new());
+        entries.insert(search_id.to_string(), Arc::clone(&notify));
+        Ok(ActiveSearch {
+            searches: self,
+            search_id: search_id.to_string(),
+            notify,
+        })
+    }
+    
+    /// Signal the running search with this id; false when there is none
+    fn cancel(&self, search_id: &str) -> bool {
+        match self.entries.lock().unwrap().remove(search_id) {
+            Some(notify) => {
+                // A stored permit reaches the search even before it waits
+                notify.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Registration of a cancellable search; dropping it unregisters the id
+struct ActiveSearch<'a> {
+    searches: &'a ActiveSearches,
+    search_id: String,
+    notify: Arc<Notify>,
+}
+
+impl ActiveSearch<'_> {
+    async fn cancelled(&self) {
+        self.notify.notified().await
+    }
+}
+
+impl Drop for ActiveSearch<'_> {
+    fn drop(&mut self) {
+        // Leave a later search that reused the id alone
+        let mut entries = self.searches.entries.lock().unwrap();
+        if entries.get(&self.search_id).is_some_and(|notify| Arc::ptr_eq(notify, &self.notify)) {
+            entries.remove(&self.search_id);
+        }
+    }
+}
+
+/// Searcher settings that can be swapped while the server runs
+#[derive(Debug, Clone)]
+pub struct SearchSettings {
+    pub max_paths_per_request: usize,
+    pub profiles: HashMap<String, SearchProfile>,
+    
+    /// Depth cap for symlink-following searches that set no `max_depth`,
+    /// bounding traversal of symlink cycles
+    pub symlink_max_depth: usize,
+    
+    /// Total ripgrep threads shared by all concurrent searches; `None` lets
+    /// each search use ripgrep's default
+    pub max_threads_global: Option<usize>,
+    
+    /// Reject searches that name no path instead of searching the whole root
+    pub require_explicit_path: bool,
+    
+    /// Reject unfiltered searches whose pattern matches every line, rather
+    /// than only warning about them
+    pub reject_degenerate_patterns: bool,
+    
+    /// How long identical searches are answered from the result cache;
+    /// zero disables the cache
+    pub result_cache_ttl: Duration,
+    
+    /// Total search wall time allowed per client session; `None` is unlimited
+    pub session_time_budget: Option<Duration>,
+    
+    /// Timeout for searches that set no `timeout_ms`; `None` waits forever
+    pub search_timeout_ms: Option<u64>,
+    
+    /// The only file extensions (lowercase, without the dot) any search may
+    /// read, whatever the request asks for; `None` allows every file
+    pub allowed_extensions: Option<Vec<String>>,
+    
+    /// Result cap for searches that set no `max_results`
+    pub max_results: Option<usize>,
+    
+    /// Globs every search excludes, on top of the request's own
+    pub exclude_globs: Vec<String>,
+}
+
+impl Default for SearchSettings {
+    fn default() -> Self {
+        Self {
+            max_paths_per_request: DEFAULT_MAX_PATHS_PER_REQUEST,
+            profiles: HashMap::new(),
+            symlink_max_depth: DEFAULT_SYMLINK_MAX_DEPTH,
+            max_threads_global: None,
+            require_explicit_path: false,
+            reject_degenerate_patterns: false,
+            result_cache_ttl: Duration::ZERO,
+            session_time_budget: None,
+            search_timeout_ms: None,
+            allowed_extensions: None,
+            max_results: None,
+            exclude_globs: Vec::new(),
+        }
+    }
+}
+
+/// Search options validated once, to be run against any number of searchers
+/// with `run_compiled`. Each run resolves them against its searcher's own
+/// settings, types and profiles like any search; only options served by a
+/// single ripgrep pass are supported
+#[derive(Debug, Clone)]
+pub struct CompiledSearch {
+    options: SearchOptions,
+    warnings: Vec<String>,
+}
+
+#[allow(dead_code)] // Library API; the server builds a fresh command per request
+impl CompiledSearch {
+    /// Validate the options and pattern, checking the pattern with a run of
+    /// the ripgrep at `rg_path` over empty input
+    pub async fn compile(mut options: SearchOptions, rg_path: &Path) -> Result<Self, AppError> {
+        // These need per-searcher state or extra ripgrep passes
+        let unsupported = [
+            ("within_results_of", options.within_results_of.is_some()),
+            ("only_in_files_matching", options.only_in_files_matching.is_some()),
+            ("newer_than", options.newer_than.is_some()),
+            ("older_than", options.older_than.is_some()),
+            ("first_result_fast", options.first_result_fast),
+            ("hard_limit", options.hard_limit.is_some()),
+            ("report_total", options.report_total),
+            ("include_last_commit", options.include_last_commit),
+            ("skip_invalid_paths", options.skip_invalid_paths),
+            ("diagnose", options.diagnose),
+            ("search_upward", options.search_upward),
+            ("pattern_fallbacks", !options.pattern_fallbacks.is_empty()),
+            ("smart_path_pattern", options.smart_path_pattern),
+            ("include_manifest", options.include_manifest),
+            ("resource_report", options.resource_report),
+        ];
+        if let Some((name, _)) = unsupported.iter().find(|(_, set)| *set) {
+            return Err(AppError::ConfigError(format!("{} is not supported by compiled searches", name)));
+        }
+        check_option_conflicts(&options)?;
+        
+        let mut warnings = Vec::new();
+        if options.normalize_pattern && !options.fixed_strings {
+            if let Some(normalized) = collapse_double_escapes(&options.pattern) {
+                warnings.push(format!("Pattern normalized from `{}` to `{}`", options.pattern, normalized));
+                options.pattern = normalized;
+            }
+        }
+        
+        // An invalid regex fails here rather than on every run; a
+        // definitions search checks the pattern it will actually run
+        let (pattern, fixed_strings) = if options.definitions_only {
+            (definition_pattern(&options.pattern, options.fixed_strings, &options.file_types), false)
+        } else {
+            (options.pattern.clone(), options.fixed_strings)
+        };
+        let mut check = TokioCommand::new(rg_path);
+        check.kill_on_drop(true).stdin(Stdio::null());
+        check.arg("--no-config");
+        if fixed_strings {
+            check.arg("-F");
+        }
+        check.arg("-e").arg(&pattern).arg("-");
+        run_command(check).await?;
+        
+        Ok(Self { options, warnings })
+    }
+}
+
+#[derive(Debug)]
+pub struct RipgrepSearcher {
+    root_dir: PathBuf,
+    
+    /// Further roots that searches may reach besides `root_dir`
+    extra_roots: Vec<PathBuf>,
+    
+    /// The ripgrep binary every command runs
+    rg_path: PathBuf,
+    
+    /// Custom file types every command defines, validated at startup
+    type_add: Vec<String>,
+    settings: RwLock<Arc<SearchSettings>>,
+    next_search_id: AtomicU64,
+    result_files: ResultFiles,
+    active_searches: ActiveSearches,
+    in_flight: AtomicUsize,
+    
+    /// File types from `rg --type-list`, loaded on first use
+    known_types: OnceCell<FileTypeIndex>,
+    
+    /// First line of `rg --version`, loaded on first use
+    rg_version: OnceCell<String>,
+    result_cache: ResultCache,
+    session: SessionBudget,
+}
+
+/// Ripgrep's file types and the plain extensions and file names that
+/// select them
+#[derive(Debug, Default)]
+struct FileTypeIndex {
+    names: HashSet<String>,
+    by_extension: HashMap<String, String>,
+    by_file_name: HashMap<String, String>,
+}
+
+impl FileTypeIndex {
+    /// Parse `rg --type-list`, where each line reads `name: glob, glob, ...`.
+    /// Types are listed alphabetically, and a glob shared by several types
+    /// maps to the first
+    fn parse(type_list: &str) -> Self {
+        let mut index = Self::default();
+        for (name, globs) in type_list.lines().filter_map(|line| line.split_once(':')) {
+            let name = name.trim();
+            index.names.insert(name.to_string());
+            for glob in globs.split(',').map(str::trim) {
+                let is_literal = |s: &str| !s.is_empty() && !s.contains(['*', '?', '[', '{']);
+                let target = match glob.strip_prefix("*.") {
+                    Some(extension) if is_literal(extension) => &mut index.by_extension,
+                    None if is_literal(glob) => &mut index.by_file_name,
+                    _ => continue,
+                };
+                let key = glob.strip_prefix("*.").unwrap_or(glob).to_string();
+                target.entry(key).or_insert_with(|| name.to_string());
+            }
+        }
+        index
+    }
+    
+    fn type_of(&self, path: &Path) -> Option<&str> {
+        let file_name = path.file_name()?.to_str()?;
+        self.by_file_name
+            .get(file_name)
+            .or_else(|| self.by_extension.get(path.extension()?.to_str()?))
+            .map(String::as_str)
+    }
+}
+
+/// Registration of a running search; dropping it frees the slot
+struct InFlightSearch<'a> {
+    counter: &'a AtomicUsize,
+    
+    /// Searches in flight when this one started, including itself
+    concurrent: usize,
+}
+
+impl InFlightSearch<'_> {
+    /// This search's share of the global thread budget, at least one thread
+    fn thread_share(&self, budget: Option<usize>) -> Option<usize> {
+        budget.map(|total| (total / self.concurrent).max(1))
+    }
+}
+
+impl Drop for InFlightSearch<'_> {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 impl RipgrepSearcher {
     pub fn new(root_dir: PathBuf) -> Self {
-        Self { root_dir }
+        Self {
+            root_dir,
+            extra_roots: Vec::new(),
+            rg_path: PathBuf::from("rg"),
+            type_add: Vec::new(),
+            settings: RwLock::new(Arc::new(SearchSettings::default())),
+            next_search_id: AtomicU64::new(1),
+            result_files: ResultFiles::new(RESULT_FILES_TTL, RESULT_FILES_CAPACITY),
+            active_searches: ActiveSearches::default(),
+            in_flight: AtomicUsize::new(0),
+            known_types: OnceCell::new(),
+            rg_version: OnceCell::new(),
+            result_cache: ResultCache::new(),
+            session: SessionBudget::new(),
+        }
+    }
+    
+    /// Also allow searches under these roots, selected with `root_index`
+    /// 1 and up in order
+    pub fn with_extra_roots(mut self, extra_roots: Vec<PathBuf>) -> Self {
+        self.extra_roots = extra_roots;
+        self
+    }
+    
+    /// Run this ripgrep binary instead of the `rg` found on `PATH`
+    pub fn with_rg_path(mut self, rg_path: PathBuf) -> Self {
+        self.rg_path = rg_path;
+        self
+    }
+    
+    /// Define these custom file types (`name:glob` specs) for every search,
+    /// so clients can select them in `file_types`
+    pub fn with_type_add(mut self, type_add: Vec<String>) -> Self {
+        self.type_add = type_add;
+        self
+    }
+    
+    /// Replace all reloadable settings at once
+    pub fn with_settings(self, settings: SearchSettings) -> Self {
+        self.reload_settings(settings);
+        self
+    }
+    
+    /// Snapshot of the current settings; a search uses one snapshot throughout
+    pub fn settings(&self) -> Arc<SearchSettings> {
+        self.settings.read().unwrap().clone()
+    }
+    
+    /// Atomically swap in new settings; searches already running keep the old ones
+    pub fn reload_settings(&self, settings: SearchSettings) {
+        *self.settings.write().unwrap() = Arc::new(settings);
+        
+        // Cached results may depend on the old profiles and limits
+        self.result_cache.clear();
+    }
+    
+    /// Resolve the request's profile and server-side defaults into the
+    /// effective options
+    fn resolve_options(&self, settings: &SearchSettings, options: &SearchOptions) -> Result<SearchOptions, AppError> {
+        let mut resolved = options.clone();
+        resolved.type_add = self.type_add.clone();
+        resolved.allowed_extensions = settings.allowed_extensions.clone();
+        if resolved.max_results.is_none() {
+            resolved.max_results = settings.max_results;
+        }
+        for glob in &settings.exclude_globs {
+            if !resolved.exclude_globs.contains(glob) {
+                resolved.exclude_globs.push(glob.clone());
+            }
+        }
+        if let Some(name) = &options.profile {
+            let profile = settings.profiles.get(name)
+                .ok_or_else(|| AppError::ConfigError(format!("Unknown search profile: {}", name)))?;
+            profile.apply(&mut resolved);
+        }
+        
+        // Look for declarations of the name rather than every use
+        if resolved.definitions_only {
+            resolved.pattern = definition_pattern(&resolved.pattern, resolved.fixed_strings, &resolved.file_types);
+            resolved.fixed_strings = false;
+        }
+        
+        if resolved.follow_internal_symlinks_only {
+            resolved.follow_symlinks = true;
+        }
+        
+        // Bound traversal through symlink cycles
+        if resolved.follow_symlinks && resolved.max_depth.is_none() {
+            resolved.max_depth = Some(settings.symlink_max_depth);
+        }
+        
+        Ok(resolved)
+    }
+    
+    /// Every configured root, in `root_index` order
+    pub fn roots(&self) -> Vec<&Path> {
+        std::iter::once(&self.root_dir).chain(&self.extra_roots).map(PathBuf::as_path).collect()
+    }
+    
+    /// The root a search's relative paths start from
+    fn base_root(&self, root_index: Option<usize>) -> Result<&Path, AppError> {
+        match root_index.unwrap_or(0) {
+            0 => Ok(&self.root_dir),
+            i => self.extra_roots.get(i - 1).map(PathBuf::as_path).ok_or_else(|| {
+                AppError::ConfigError(format!(
+                    "root_index {} is out of range; the server has {} roots",
+                    i,
+                    self.extra_roots.len() + 1
+                ))
+            }),
+        }
+    }
+    
+    /// Validate a search path to prevent path traversal attacks; the path
+    /// is joined to `base` but may resolve under any configured root
+    fn validate_path_in(&self, base: &Path, path: &str) -> Result<PathBuf, AppError> {
+        let search_path = base.join(path);
+        
+        // Canonicalize both paths to resolve any ".." components
+        let canonical_search_path = match std::fs::canonicalize(&search_path) {
+            Ok(p) => p,
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                return Err(AppError::PermissionDenied(path.to_string()));
+            }
+            Err(_) => return Err(AppError::InvalidPath(path.to_string())),
+        };
+        
+        let mut canonical_roots = Vec::new();
+        for root in std::iter::once(&self.root_dir).chain(&self.extra_roots) {
+            match std::fs::canonicalize(root) {
+                Ok(p) => canonical_roots.push(p),
+                Err(_) => return Err(AppError::ConfigError("Could not resolve root directory".to_string())),
+            }
+        }
+        
+        // Ensure the search path is within one of the root directories
+        if !canonical_roots.iter().any(|root| canonical_search_path.starts_with(root)) {
+            return Err(AppError::PathTraversal(path.to_string()));
+        }
+        
+        check_readable(&search_path, path)?;
+        Ok(search_path)
+    }
+    
+    /// The installed ripgrep's version line
+    async fn rg_version(&self) -> Result<&str, AppError> {
+        self.rg_version.get_or_try_init(|| async {
+            let mut cmd = TokioCommand::new(&self.rg_path);
+            cmd.arg("--version");
+            let output = run_command(cmd).await?;
+            Ok(String::from_utf8_lossy(&output.stdout).lines().next().unwrap_or_default().to_string())
+        }).await.map(String::as_str)
+    }
+    
+    /// Check that ripgrep runs and the roots exist, without searching.
+    /// Ripgrep is run afresh rather than trusting the cached version, so a
+    /// binary removed since startup is noticed
+    pub async fn health(&self) -> HealthReport {
+        let mut cmd = TokioCommand::new(&self.rg_path);
+        cmd.arg("--version").kill_on_drop(true);
+        let (rg_version, rg_error) = match run_command(cmd).await {
+            Ok(output) => {
+                let version = String::from_utf8_lossy(&output.stdout).lines().next().unwrap_or_default().to_string();
+                (Some(version), None)
+            }
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let roots: Vec<RootHealth> = self
+            .roots()
+            .into_iter()
+            .map(|root| RootHealth {
+                path: root.display().to_string(),
+                exists: root.is_dir(),
+            })
+            .collect();
+        
+        HealthReport {
+            ready: rg_version.is_some() && roots.iter().all(|root| root.exists),
+            rg_path: self.rg_path.display().to_string(),
+            rg_version,
+            rg_error,
+            roots,
+        }
+    }
+    
+    /// Version, arguments and root of a search about to run
+    async fn manifest(&self, options: &SearchOptions, search_paths: &[PathBuf]) -> Result<SearchManifest, AppError> {
+        let args = Self::search_command(&self.rg_path, options, search_paths)
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let root = std::fs::canonicalize(self.base_root(options.root_index)?)
+            .map_err(|_| AppError::ConfigError("Could not resolve root directory".to_string()))?;
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        
+        Ok(SearchManifest {
+            ripgrep_version: self.rg_version().await?.to_string(),
+            args,
+            root: root.to_string_lossy().into_owned(),
+            timestamp,
+        })
+    }
+    
+    /// Ripgrep's file types, cached after the first `--type-list` call
+    async fn known_types(&self) -> Result<&FileTypeIndex, AppError> {
+        self.known_types.get_or_try_init(|| async {
+            let mut cmd = TokioCommand::new(&self.rg_path);
+            cmd.arg("--no-config");
+            for spec in &self.type_add {
+                cmd.arg("--type-add").arg(spec);
+            }
+            cmd.arg("--type-list");
+            let output = run_command(cmd).await?;
+            Ok(FileTypeIndex::parse(&String::from_utf8_lossy(&output.stdout)))
+        }).await
+    }
+    
+    /// Individual matches per ripgrep file type, most first
+    async fn type_breakdown(&self, stdout: &str) -> Result<Vec<TypeCount>, AppError> {
+        let index = self.known_types().await?;
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for m in parse_json_matches(stdout)? {
+            let file_type = index.type_of(Path::new(m.path.as_str())).unwrap_or("other");
+            *counts.entry(file_type).or_default() += m.submatches.len();
+        }
+        
+        let mut breakdown: Vec<TypeCount> = counts
+            .into_iter()
+            .map(|(file_type, count)| TypeCount { file_type: file_type.to_string(), count })
+            .collect();
+        breakdown.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.file_type.cmp(&b.file_type)));
+        Ok(breakdown)
+    }
+    
+    /// Lowercase file type names and check them against ripgrep's list
+    /// before any search runs, naming every unknown type along with close
+    /// matches
+    async fn normalize_file_types(&self, file_types: &[String]) -> Result<Vec<String>, AppError> {
+        let known = &self.known_types().await?.names;
+        
+        let mut normalized = Vec::with_capacity(file_types.len());
+        let mut unknown = Vec::new();
+        for file_type in file_types {
+            let name = file_type.to_lowercase();
+            if known.contains(&name) {
+                normalized.push(name);
+                continue;
+            }
+            
+            // Closest names first
+            let mut suggestions: Vec<(usize, &str)> = known
+                .iter()
+                .map(|candidate| (edit_distance(candidate, &name), candidate.as_str()))
+                .filter(|&(distance, candidate)| distance <= 2 || candidate.starts_with(&name))
+                .collect();
+            suggestions.sort_unstable();
+            let suggestions: Vec<&str> = suggestions.into_iter().map(|(_, candidate)| candidate).collect();
+            
+            if suggestions.is_empty() {
+                unknown.push(file_type.clone());
+            } else {
+                unknown.push(format!("{} (did you mean: {}?)", file_type, suggestions.join(", ")));
+            }
+        }
+        
+        if unknown.is_empty() {
+            Ok(normalized)
+        } else {
+            Err(AppError::ConfigError(format!("Unknown file types: {}", unknown.join("; "))))
+        }
+    }
+    
+    /// Count this search as in flight until the returned guard drops
+    fn enter_search(&self) -> InFlightSearch<'_> {
+        let concurrent = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        InFlightSearch {
+            counter: &self.in_flight,
+            concurrent,
+        }
+    }
+    
+    /// The file a pattern names, when it looks like a path: no whitespace or
+    /// regex syntax beyond `.`, at least one `/` or an extension, and it
+    /// resolves to a regular file inside the root
+    fn pattern_as_file(&self, root: &Path, pattern: &str) -> Option<PathBuf> {
+        let looks_like_path = !pattern.is_empty()
+            && !pattern.contains(|c: char| c.is_whitespace() || "*?+()[]{}|^$\\".contains(c))
+            && (pattern.contains('/') || Path::new(pattern).extension().is_some());
+        if !looks_like_path {
+            return None;
+        }
+        
+        let path = self.validate_path_in(root, pattern).ok()?;
+        path.is_file().then_some(path)
+    }
+    
+    /// Modification time of a reference file, relative to `root`
+    fn reference_mtime(&self, root: &Path, path: &str) -> Result<SystemTime, AppError> {
+        let path = self.validate_path_in(root, path)?;
+        Ok(std::fs::metadata(path)?.modified()?)
+    }
+    
+    #[instrument(skip(self, options), fields(pattern = %options.pattern))]
+    pub async fn search(&self, options: &SearchOptions) -> Result<SearchResult, AppError> {
+        self.guarded(options, async {
+            if options.pattern_fallbacks.is_empty() {
+                self.search_once(options).await
+            } else {
+                self.fallback_search(options).await
+            }
+        })
+        .await
+    }
+    
+    /// Run a search under the session's time budget, which failed searches
+    /// count against too, its deadline and cancellation by `search_id`
+    async fn guarded(
+        &self,
+        options: &SearchOptions,
+        search: impl Future<Output = Result<SearchResult, AppError>>,
+    ) -> Result<SearchResult, AppError> {
+        let settings = self.settings();
+        self.session.check(settings.session_time_budget)?;
+        let start = Instant::now();
+        
+        // One deadline covers all the search runs: the cache signature, every
+        // pattern fallback, the count pass and ripgrep itself
+        let search = with_timeout(search_timeout(&settings, options), search);
+        
+        // Losing the race drops the search, which kills its ripgrep process
+        let result = match &options.search_id {
+            Some(search_id) => {
+                let active = self.active_searches.register(search_id)?;
+                tokio::select! {
+                    result = search => result.map(|result| self.rename_search(result, search_id)),
+                    _ = active.cancelled() => Err(AppError::Cancelled(search_id.clone())),
+                }
+            }
+            None => search.await,
+        };
+        self.session.charge(start.elapsed());
+        result
+    }
+    
+    /// One search, upward or through the result cache
+    async fn search_once(&self, options: &SearchOptions) -> Result<SearchResult, AppError> {
+        if options.search_upward {
+            self.upward_search(options).await
+        } else {
+            self.cached_search(options).await
+        }
+    }
+    
+    /// Try `pattern`, then each fallback, until one matches; without any
+    /// match the last attempt's empty result is returned
+    async fn fallback_search(&self, options: &SearchOptions) -> Result<SearchResult, AppError> {
+        let patterns = std::iter::once(&options.pattern).chain(&options.pattern_fallbacks);
+        let mut result = SearchResult::default();
+        for (index, pattern) in patterns.enumerate() {
+            let attempt = SearchOptions {
+                pattern: pattern.clone(),
+                pattern_fallbacks: Vec::new(),
+                ..options.clone()
+            };
+            result = self.search_once(&attempt).await?;
+            if result.stats.matched_lines > 0 {
+                debug!(index, %pattern, "Pattern fallback matched");
+                result.matched_pattern_index = Some(index);
+                break;
+            }
+        }
+        Ok(result)
+    }
+    
+    /// Stop the running search started with this `search_id`; false when
+    /// no such search is running
+    pub fn cancel_search(&self, search_id: &str) -> bool {
+        let cancelled = self.active_searches.cancel(search_id);
+        debug!(search_id, cancelled, "Cancel requested");
+        cancelled
+    }
+    
+    /// Give a result the client's id, keeping its files for refinement
+    fn rename_search(&self, mut result: SearchResult, search_id: &str) -> SearchResult {
+        let files = self.result_files.get(&result.search_id).unwrap_or_default();
+        self.result_files.insert(search_id.to_string(), files);
+        result.search_id = search_id.to_string();
+        result
+    }
+    
+    /// Search the files directly in `path`, then in each parent up to the
+    /// root, returning the first level that matches (or the root's empty result)
+    async fn upward_search(&self, options: &SearchOptions) -> Result<SearchResult, AppError> {
+        if !options.paths.is_empty() {
+            return Err(AppError::ConfigError(
+                "search_upward starts from a single `path`; `paths` cannot be combined with it".to_string(),
+            ));
+        }
+        
+        let root = self.base_root(options.root_index)?;
+        let start = self.validate_path_in(root, &options.path)?;
+        let canonical_root = std::fs::canonicalize(root)
+            .map_err(|_| AppError::ConfigError("Could not resolve root directory".to_string()))?;
+        let mut dir = std::fs::canonicalize(&start)?;
+        if !dir.is_dir() {
+            dir.pop();
+        }
+        
+        loop {
+            let level = match dir.strip_prefix(&canonical_root) {
+                Ok(relative) if relative.as_os_str().is_empty() => ".".to_string(),
+                Ok(relative) => relative.to_string_lossy().into_owned(),
+                Err(_) => return Err(AppError::PathTraversal(options.path.clone())),
+            };
+            debug!(%level, "Searching upward");
+            let level_options = SearchOptions {
+                path: level.clone(),
+                search_upward: false,
+                max_depth: Some(1),
+                ..options.clone()
+            };
+            let mut result = self.cached_search(&level_options).await?;
+            if result.stats.matched_lines > 0 || dir == canonical_root {
+                if result.stats.matched_lines > 0 {
+                    result.upward_level = Some(level);
+                }
+                return Ok(result);
+            }
+            dir.pop();
+        }
+    }
+    
+    /// Serve the search from the result cache when its files are unchanged
+    async fn cached_search(&self, options: &SearchOptions) -> Result<SearchResult, AppError> {
+        // Oversized path lists are rejected before the signature walks them
+        let settings = self.settings();
+        check_path_count(&settings, options)?;
+        let ttl = settings.result_cache_ttl;
+        if ttl.is_zero() || !options.cacheable() {
+            return self.run_search(options).await;
+        }
+        
+        // Without a signature there is nothing to validate a cached entry by
+        let Some(signature) = self.dir_signature(options).await else {
+            return self.run_search(options).await;
+        };
+        let key = serde_json::to_string(options)?;
+        
+        let start = Instant::now();
+        if let Some((mut result, files)) = self.result_cache.get(&key, signature, ttl) {
+            debug!("Serving search from the result cache");
+            result.search_id = format!("search-{}", self.next_search_id.fetch_add(1, Ordering::Relaxed));
+            result.stats.elapsed_ms = start.elapsed().as_millis() as u64;
+            result.stats.cached = true;
+            self.result_files.insert(result.search_id.clone(), files);
+            
+            // Nothing runs, so a streaming transport gets the lines at once
+            if let Some(sink) = &options.line_sink {
+                for line in &result.matches {
+                    let _ = sink.send(line.clone());
+                }
+            }
+            return Ok(result);
+        }
+        
+        let result = self.run_search(options).await?;
+        let files = self.result_files.get(&result.search_id).unwrap_or_default();
+        self.result_cache.insert(
+            key,
+            CachedResult {
+                created: Instant::now(),
+                signature,
+                result: result.clone(),
+                files,
+            },
+            ttl,
+        );
+        Ok(result)
+    }
+    
+    /// Count and latest mtime of the files the search would read: the
+    /// requested paths walked with the search's own file selection (types,
+    /// globs, hidden files, ignore sources, depth), so edits to hidden or
+    /// ignored files count exactly when the search would see them
+    async fn dir_signature(&self, options: &SearchOptions) -> Option<DirSignature> {
+        let mut options = self.resolve_options(&self.settings(), options).ok()?;
+        if !options.file_types.is_empty() {
+            options.file_types = self.normalize_file_types(&options.file_types).await.ok()?;
+        }
+        let requested = options.requested_paths();
+        let root = self.base_root(options.root_index).ok()?;
+        let paths = if requested.is_empty() {
+            vec![root.to_path_buf()]
+        } else {
+            requested
+                .iter()
+                .map(|path| self.validate_path_in(root, path))
+                .collect::<Result<Vec<_>, _>>()
+                .ok()?
+        };
+        
+        let mut cmd = Self::base_command(&self.rg_path, &options);
+        cmd.arg("--files");
+        cmd.args(&paths);
+        let output = run_command(cmd).await.ok()?;
+        
+        let mut signature = DirSignature {
+            file_count: 0,
+            latest_mtime: None,
+        };
+        for line in String::from_utf8_lossy(&output.stdout).lines().filter(|l| !l.is_empty()) {
+            signature.file_count += 1;
+            let mtime = std::fs::metadata(line).and_then(|m| m.modified()).ok();
+            signature.latest_mtime = signature.latest_mtime.max(mtime);
+        }
+        Some(signature)
+    }
+    
+    async fn run_search(&self, options: &SearchOptions) -> Result<SearchResult, AppError> {
+        debug!("Starting ripgrep search");
+        
+        let settings = self.settings();
+        
+        // Reject oversized path lists before anything spawns ripgrep
+        check_path_count(&settings, options)?;
+        let mut options = self.resolve_options(&settings, options)?;
+        
+        // Split the global thread budget among the searches running now
+        let in_flight = self.enter_search();
+        options.threads = in_flight.thread_share(settings.max_threads_global);
+        
+        let mut warnings = Vec::new();
+        if options.normalize_pattern && !options.fixed_strings {
+            if let Some(normalized) = collapse_double_escapes(&options.pattern) {
+                warn!(original = %options.pattern, %normalized, "Normalized double-escaped pattern");
+                warnings.push(format!("Pattern normalized from `{}` to `{}`", options.pattern, normalized));
+                options.pattern = normalized;
+            }
+        }
+        if !options.file_types.is_empty() {
+            options.file_types = self.normalize_file_types(&options.file_types).await?;
+        }
+        let options = &options;
+        
+        let requested = options.requested_paths();
+        check_option_conflicts(options)?;
+        
+        if requested.is_empty() && settings.require_explicit_path {
+            return Err(AppError::PathRequired);
+        }
+        
+        // A pasted file path was meant as a file lookup, not a content search
+        let root = self.base_root(options.root_index)?;
+        if options.smart_path_pattern {
+            if let Some(file) = self.pattern_as_file(root, &options.pattern) {
+                debug!(path = %file.display(), "Pattern names a file; returning it");
+                warnings.push(format!(
+                    "Pattern `{}` is the path of a file, so the file is returned instead of searching for the text",
+                    options.pattern
+                ));
+                let mut result = SearchResult {
+                    matches: vec![file.to_string_lossy().into_owned()],
+                    warnings,
+                    stats: SearchStats {
+                        matched_lines: 1,
+                        ..SearchStats::default()
+                    },
+                    ..SearchResult::default()
+                };
+                result.search_id = format!("search-{}", self.next_search_id.fetch_add(1, Ordering::Relaxed));
+                self.result_files.insert(result.search_id.clone(), vec![file]);
+                return Ok(result);
+            }
+        }
+        
+        // A pattern that matches every line of the whole tree only floods the client
+        if options.is_degenerate() {
+            if settings.reject_degenerate_patterns {
+                return Err(AppError::DegeneratePattern(options.pattern.clone()));
+            }
+            warn!(pattern = %options.pattern, "Unfiltered search matches every line");
+            warnings.push(format!(
+                "Pattern `{}` matches every line; narrow the search with a path or file type",
+                options.pattern
+            ));
+        }
+        
+        // Build the search paths
+        let mut invalid_paths = Vec::new();
+        let search_paths = if requested.is_empty() {
+            check_readable(root, ".")?;
+            vec![root.to_path_buf()]
+        } else if options.skip_invalid_paths {
+            // Search what we can and report the rest
+            let mut valid = Vec::new();
+            for path in &requested {
+                match self.validate_path_in(root, path) {
+                    Ok(validated) => valid.push(validated),
+                    Err(e) => {
+                        debug!(%path, error = %e, "Skipping invalid path");
+                        invalid_paths.push(path.to_string());
+                    }
+                }
+            }
+            valid
+        } else {
+            requested
+                .iter()
+                .map(|path| self.validate_path_in(root, path))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        let scope = if options.include_scope {
+            Some(search_scope(root, options, &search_paths)?)
+        } else {
+            None
+        };
+        
+        // Resolve mtime thresholds up front so a bad reference fails fast
+        let newer_than = options.newer_than.as_deref().map(|p| self.reference_mtime(root, p)).transpose()?;
+        let older_than = options.older_than.as_deref().map(|p| self.reference_mtime(root, p)).transpose()?;
+        
+        // Narrow the search to the files an earlier search matched
+        let search_paths = match &options.within_results_of {
+            Some(search_id) => self
+                .result_files
+                .get(search_id)
+                .ok_or_else(|| AppError::UnknownSearchId(search_id.clone()))?
+                .into_iter()
+                .filter(|file| search_paths.iter().any(|path| file.starts_with(path)))
+                .collect(),
+            None => search_paths,
+        };
+        
+        // Start timing the search
+        let start = Instant::now();
+        
+        // Two-stage query: first find the files containing the secondary pattern
+        let search_paths = match &options.only_in_files_matching {
+            Some(secondary) if !search_paths.is_empty() => {
+                self.files_matching(options, secondary, &search_paths).await?
+            }
+            _ => search_paths,
+        };
+        
+        // Only files with an allowed extension are ever searched
+        let search_paths = allowed_paths(&settings, search_paths);
+        
+        // Matches from files outside the requested mtime window, or reached
+        // through symlinks that lead out of the root, are dropped
+        let inside_root = if options.follow_internal_symlinks_only {
+            let canonical_root = std::fs::canonicalize(root)
+                .map_err(|_| AppError::ConfigError("Could not resolve root directory".to_string()))?;
+            Some(canonical_root)
+        } else {
+            None
+        };
+        let filter = FileFilter {
+            allowed_extensions: settings.allowed_extensions.clone(),
+            newer_than,
+            older_than,
+            inside_root,
+        };
+        
+        // An empty scope matches nothing; never let ripgrep fall back to its cwd
+        let mut diagnostics = options.diagnose.then(Vec::new);
+        let mut resources = None;
+        let (stdout, partial, filtered) = if search_paths.is_empty() {
+            (String::new(), false, false)
+        } else if options.first_result_fast
+            || options.hard_limit.is_some()
+            || options.stops_at_max_results()
+            || (options.line_sink.is_some() && !options.resource_report)
+        {
+            let (stdout, partial, filtered, stderr) = self.stream_lines(options, &search_paths, &filter).await?;
+            if let Some(diagnostics) = diagnostics.as_mut() {
+                diagnostics.extend(skipped_files(&stderr));
+            }
+            (stdout, partial, filtered)
+        } else {
+            let (output, usage) = self.build_command(options, &search_paths).await?;
+            resources = usage;
+            if let Some(diagnostics) = diagnostics.as_mut() {
+                diagnostics.extend(skipped_files(&String::from_utf8_lossy(&output.stderr)));
+            }
+            let stdout = decode_stdout(output.stdout, options.lossy_decode)?;
+            let (stdout, filtered) = filter_output(stdout, &filter, options);
+            (stdout, false, filtered)
+        };
+        if filtered {
+            warnings.push(FILTERED_STATS_WARNING.to_string());
+        }
+        
+        // The count pass only reports numbers, so it stays cheap on huge result
+        // sets; it also makes the stats of an early-stopped search honest
+        let wants_total = options.report_total || (options.reconcile_counts && partial);
+        let total_matches = if !wants_total {
+            None
+        } else if search_paths.is_empty() {
+            Some(0)
+        } else {
+            Some(self.count_matches(options, &search_paths, &filter).await?)
+        };
+        
+        // Calculate elapsed time
+        let elapsed = start.elapsed();
+        
+        let (mut result, files) = self.parse_output(options, &stdout)?;
+        if options.type_breakdown {
+            result.type_breakdown = Some(self.type_breakdown(&stdout).await?);
+        }
+        if options.patch_hunks {
+            result.patch_hunks = Some(replacement_hunks(&stdout, root, options).await);
+        }
+        if options.include_manifest {
+            result.manifest = Some(self.manifest(options, &search_paths).await?);
+        }
+        
+        let last_commits = if options.include_last_commit {
+            self.last_commits(root, &files).await
+        } else {
+            None
+        };
+        
+        // Remember which files matched so a follow-up search can refine them
+        result.search_id = format!("search-{}", self.next_search_id.fetch_add(1, Ordering::Relaxed));
+        self.result_files.insert(result.search_id.clone(), files);
+        
+        result.scope = scope;
+        if let Some(usage) = resources {
+            result.stats.peak_rss_kb = usage.peak_rss_kb;
+            result.stats.user_cpu_ms = usage.user_cpu_ms;
+            result.stats.system_cpu_ms = usage.system_cpu_ms;
+        }
+        result.last_commits = last_commits;
+        result.invalid_paths = invalid_paths;
+        result.diagnostics = diagnostics;
+        result.warnings = warnings;
+        result.stats.elapsed_ms = elapsed.as_millis() as u64;
+        result.stats.total_matches = total_matches;
+        result.stats.partial = partial;
+        Ok(result)
+    }
+    
+    /// Run a compiled search under this searcher's root and settings, with
+    /// the same deadline, cancellation and session budget as `search`
+    #[allow(dead_code)] // Library API; the server builds a fresh command per request
+    pub async fn run_compiled(&self, compiled: &CompiledSearch) -> Result<SearchResult, AppError> {
+        self.guarded(&compiled.options, self.compiled_search(compiled)).await
+    }
+    
+    /// The single ripgrep pass of a compiled search
+    async fn compiled_search(&self, compiled: &CompiledSearch) -> Result<SearchResult, AppError> {
+        let settings = self.settings();
+        let start = Instant::now();
+        
+        check_path_count(&settings, &compiled.options)?;
+        let mut options = self.resolve_options(&settings, &compiled.options)?;
+        if !options.file_types.is_empty() {
+            options.file_types = self.normalize_file_types(&options.file_types).await?;
+        }
+        let in_flight = self.enter_search();
+        options.threads = in_flight.thread_share(settings.max_threads_global);
+        let options = &options;
+        
+        let requested = options.requested_paths();
+        if requested.is_empty() && settings.require_explicit_path {
+            return Err(AppError::PathRequired);
+        }
+        if settings.reject_degenerate_patterns && options.is_degenerate() {
+            return Err(AppError::DegeneratePattern(options.pattern.clone()));
+        }
+        let root = self.base_root(options.root_index)?;
+        let search_paths = if requested.is_empty() {
+            check_readable(root, ".")?;
+            vec![root.to_path_buf()]
+        } else {
+            requested
+                .iter()
+                .map(|path| self.validate_path_in(root, path))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        let scope = if options.include_scope {
+            Some(search_scope(root, options, &search_paths)?)
+        } else {
+            None
+        };
+        let search_paths = allowed_paths(&settings, search_paths);
+        let inside_root = if options.follow_internal_symlinks_only {
+            let canonical_root = std::fs::canonicalize(root)
+                .map_err(|_| AppError::ConfigError("Could not resolve root directory".to_string()))?;
+            Some(canonical_root)
+        } else {
+            None
+        };
+        let filter = FileFilter {
+            allowed_extensions: settings.allowed_extensions.clone(),
+            inside_root,
+            ..FileFilter::default()
+        };
+        
+        let (stdout, filtered) = if search_paths.is_empty() {
+            (String::new(), false)
+        } else {
+            let cmd = Self::search_command(&self.rg_path, options, &search_paths);
+            let output = run_command(cmd).await?;
+            filter_output(decode_stdout(output.stdout, options.lossy_decode)?, &filter, options)
+        };
+        drop(in_flight);
+        let (mut result, files) = self.parse_output(options, &stdout)?;
+        if options.type_breakdown {
+            result.type_breakdown = Some(self.type_breakdown(&stdout).await?);
+        }
+        if options.patch_hunks {
+            result.patch_hunks = Some(replacement_hunks(&stdout, root, options).await);
+        }
+        
+        result.search_id = format!("search-{}", self.next_search_id.fetch_add(1, Ordering::Relaxed));
+        self.result_files.insert(result.search_id.clone(), files);
+        result.scope = scope;
+        result.warnings = compiled.warnings.clone();
+        if filtered {
+            result.warnings.push(FILTERED_STATS_WARNING.to_string());
+        }
+        result.stats.elapsed_ms = start.elapsed().as_millis() as u64;
+        Ok(result)
+    }
+    
+    /// The files a search of `path` would read (`--files`), relative to the
+    /// root and sorted, honoring ignore files like any search
+    pub async fn list_files(&self, options: &ListFilesOptions) -> Result<Vec<String>, AppError> {
+        let root = self.base_root(options.root_index)?;
+        let search_path = if options.path.is_empty() {
+            check_readable(root, ".")?;
+            root.to_path_buf()
+        } else {
+            self.validate_path_in(root, &options.path)?
+        };
+        let file_types = if options.file_types.is_empty() {
+            Vec::new()
+        } else {
+            self.normalize_file_types(&options.file_types).await?
+        };
+        let filters = SearchOptions {
+            case_sensitive: true,
+            file_types,
+            globs: options.globs.clone(),
+            max_depth: options.max_depth,
+            type_add: self.type_add.clone(),
+            ..SearchOptions::new("")
+        };
+        
+        let mut cmd = Self::base_command(&self.rg_path, &filters);
+        cmd.arg("--files");
+        cmd.arg(&search_path);
+        let output = run_command(cmd).await?;
+        
+        let allowed = self.settings().allowed_extensions.clone();
+        let mut files: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .filter(|line| has_allowed_extension(Path::new(line), allowed.as_deref()))
+            .map(|line| relative_components(Path::new(line), root).join("/"))
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+    
+    /// Count individual matches per file (`--count-matches`) without
+    /// returning the matched lines
+    #[instrument(skip(self, options), fields(pattern = %options.pattern))]
+    pub async fn count(&self, options: &SearchOptions) -> Result<CountResult, AppError> {
+        let settings = self.settings();
+        self.session.check(settings.session_time_budget)?;
+        let start = Instant::now();
+        
+        check_path_count(&settings, options)?;
+        let mut options = self.resolve_options(&settings, options)?;
+        if !options.file_types.is_empty() {
+            options.file_types = self.normalize_file_types(&options.file_types).await?;
+        }
+        
+        let requested = options.requested_paths();
+        if requested.is_empty() && settings.require_explicit_path {
+            return Err(AppError::PathRequired);
+        }
+        let root = self.base_root(options.root_index)?;
+        let search_paths = if requested.is_empty() {
+            check_readable(root, ".")?;
+            vec![root.to_path_buf()]
+        } else {
+            requested
+                .iter()
+                .map(|path| self.validate_path_in(root, path))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        let search_paths = allowed_paths(&settings, search_paths);
+        if search_paths.is_empty() {
+            return Ok(CountResult { total: 0, per_file: Vec::new() });
+        }
+        
+        let in_flight = self.enter_search();
+        options.threads = in_flight.thread_share(settings.max_threads_global);
+        let mut cmd = Self::base_command(&self.rg_path, &options);
+        cmd.arg("--count-matches").arg("--with-filename");
+        if options.invert_match {
+            cmd.arg("-v"); // Counts the non-matching lines instead
+        }
+        cmd.arg(&options.pattern);
+        cmd.args(&search_paths);
+        let output = with_timeout(search_timeout(&settings, &options), run_command(cmd)).await;
+        drop(in_flight);
+        self.session.charge(start.elapsed());
+        
+        let allowed = settings.allowed_extensions.as_deref();
+        let per_file: Vec<FileCount> = count_per_file(&String::from_utf8_lossy(&output?.stdout))
+            .into_iter()
+            .filter(|f| has_allowed_extension(Path::new(&f.path), allowed))
+            .collect();
+        Ok(CountResult {
+            total: per_file.iter().map(|f| f.count).sum(),
+            per_file,
+        })
+    }
+    
+    /// Run only the parsing stage on canned ripgrep stdout, without spawning
+    /// ripgrep. The options must describe the flags the output was produced
+    /// with (e.g. a structured option for `--json` output). Nothing is
+    /// remembered for refinement, so the result's `search_id` is empty, and
+    /// `type_breakdown`, which needs ripgrep's type list, and `patch_hunks`,
+    /// which read the files on disk, are left out
+    #[allow(dead_code)] // For tests and embedders; the server always spawns ripgrep
+    pub fn parse_only(&self, options: &SearchOptions, stdout: &[u8]) -> Result<SearchResult, AppError> {
+        let options = self.resolve_options(&self.settings(), options)?;
+        let stdout = decode_stdout(stdout.to_vec(), options.lossy_decode)?;
+        let (result, _) = self.parse_output(&options, &stdout)?;
+        Ok(result)
+    }
+    
+    /// Shape ripgrep's stdout into a result plus the files it matched. Only
+    /// the parts derived from the output are filled in
+    fn parse_output(&self, options: &SearchOptions, stdout: &str) -> Result<(SearchResult, Vec<PathBuf>), AppError> {
+        // Output paths are shown relative to the root the search ran in
+        let root = self.base_root(options.root_index)?;
+        
+        // JSON output is parsed into per-line records; plain text passes through
+        let mut file_summaries = None;
+        let mut structured_matches = None;
+        let mut grouped_matches = None;
+        let mut context_blocks = None;
+        let mut histogram = None;
+        let mut density = None;
+        let mut comment_partition = None;
+        let mut capped_lines = 0;
+        let rg_stats;
+        let (matches, matched_lines, files) = if options.uses_json_output() {
+            rg_stats = summary_stats(stdout);
+            let mut records = parse_json_matches(stdout)?;
+            if let Some(cap) = options.max_submatches_per_line {
+                capped_lines = cap_submatches(&mut records, cap);
+            }
+            if options.raw_json {
+                // Raw events take the place of every reshaping below
+            } else if options.summary_per_file {
+                file_summaries = Some(summarize_files(&records));
+            } else if options.merge_context {
+                context_blocks = Some(merge_context_blocks(stdout)?);
+            } else if options.histogram {
+                histogram = Some(match_histogram(&records));
+            } else if options.density_report {
+                density = Some(match_density(&records));
+            } else if options.partition_comments {
+                comment_partition = Some(partition_comments(&records, root, options.line_numbers));
+            } else if options.output_format == OutputFormat::Grouped {
+                grouped_matches = Some(group_matches(&records));
+            } else if options.wants_structured() {
+                structured_matches = Some(structure_matches(&records, options));
+            }
+            let aggregated = file_summaries.is_some()
+                || structured_matches.is_some()
+                || grouped_matches.is_some()
+                || context_blocks.is_some()
+                || histogram.is_some()
+                || density.is_some()
+                || comment_partition.is_some();
+            
+            // A zero-width window is exactly the matched text
+            let window = options.match_window_chars.or(options.only_matching.then_some(0));
+            let matches = if options.raw_json {
+                stdout.lines().filter(|l| !l.is_empty()).map(str::to_string).collect()
+            } else if aggregated {
+                Vec::new()
+            } else if let Some(snippet) = &options.snippet {
+                match_snippets(&records, snippet, options.line_numbers)
+            } else if let Some(chars) = window {
+                match_windows(&records, chars, options)
+            } else if options.output_format == OutputFormat::GitGrep {
+                git_grep_lines(&records, root, options.line_numbers)
+            } else {
+                // Options that only need the event stream keep the plain shape
+                text_lines(stdout, options)?
+            };
+            (matches, records.len(), record_files(&records))
+        } else {
+            let (lines, stats) = split_text_stats(stdout);
+            rg_stats = stats;
+            let matches: Vec<String> = lines
+                .iter()
+                .inspect(|line| trace!(%line, "Match"))
+                .map(|s| s.to_string())
+                .collect();
+            let files = if options.color {
+                let plain: Vec<String> = matches.iter().map(|line| strip_ansi(line)).collect();
+                matched_files(&plain, options.line_numbers, options.null_output)
+            } else {
+                matched_files(&matches, options.line_numbers, options.null_output)
+            };
+            // Context lines and `--` separators are not matches; ripgrep's
+            // stats count exactly, and are missing only when it was stopped
+            let matched_lines = if options.context_lines.is_none() || options.patch_hunks {
+                matches.len()
+            } else if rg_stats.searches > 0 {
+                rg_stats.matched_lines
+            } else {
+                count_text_matches(&matches, options)
+            };
+            (matches, matched_lines, files)
+        };
+        
+        // A sample spreads over the whole result set instead of its first files
+        let matches = match options.sample {
+            Some(size) => sample_lines(matches, size),
+            None => matches,
+        };
+        
+        // Trim the match lines to the client's context budget
+        let (matches, token_estimate, truncated) = match options.token_budget {
+            Some(budget) => {
+                let (kept, estimate, truncated) = fit_token_budget(matches, budget, options.line_numbers, options.null_output);
+                (kept, Some(estimate), truncated)
+            }
+            None => (matches, None, false),
+        };
+        
+        // The global cap applies last, to whatever the budget kept
+        let (matches, truncated) = match options.max_results {
+            Some(max) if matches.len() > max => (matches.into_iter().take(max).collect(), true),
+            _ => (matches, truncated),
+        };
+        let (structured_matches, truncated) = match (structured_matches, options.max_results) {
+            (Some(mut structured), Some(max)) if structured.len() > max => {
+                structured.truncate(max);
+                (Some(structured), true)
+            }
+            (structured, _) => (structured, truncated),
+        };
+        let (grouped_matches, truncated) = match (grouped_matches, options.max_results) {
+            (Some(groups), Some(max)) if groups.iter().map(|g| g.matches.len()).sum::<usize>() > max => {
+                (Some(truncate_groups(groups, max)), true)
+            }
+            (grouped, _) => (grouped, truncated),
+        };
+        
+        // Stopped or filtered output lost ripgrep's stats block
+        let stats_known = rg_stats.searches > 0;
+        let matched_files = (options.path_components || options.include_mime).then(|| {
+            files
+                .iter()
+                .map(|file| MatchedFile {
+                    path: file.to_string_lossy().into_owned(),
+                    components: if options.path_components {
+                        relative_components(file, root)
+                    } else {
+                        Vec::new()
+                    },
+                    mime: options.include_mime.then(|| guess_mime(file).to_string()),
+                })
+                .collect()
+        });
+        
+        let result = SearchResult {
+            search_id: String::new(),
+            scope: None,
+            matches,
+            patch_hunks: None,
+            grouped_matches,
+            file_summaries,
+            structured_matches,
+            context_blocks,
+            histogram,
+            density,
+            comment_partition,
+            type_breakdown: None,
+            matched_files,
+            last_commits: None,
+            upward_level: None,
+            matched_pattern_index: None,
+            manifest: None,
+            invalid_paths: Vec::new(),
+            diagnostics: None,
+            warnings: Vec::new(),
+            stats: SearchStats {
+                matched_lines,
+                elapsed_ms: 0,
+                total_matches: None,
+                partial: false,
+                token_estimate,
+                truncated,
+                cached: false,
+                capped_lines,
+                files_searched: stats_known.then_some(rg_stats.searches),
+                files_with_matches: if stats_known { rg_stats.searches_with_match } else { files.len() },
+                match_ratio: (options.match_ratio && stats_known).then(|| rg_stats.match_ratio()),
+                peak_rss_kb: None,
+                user_cpu_ms: None,
+                system_cpu_ms: None,
+            },
+        };
+        Ok((result, files))
+    }
+    
+    /// Start a ripgrep command carrying every option that decides what matches
+    fn base_command(rg_path: &Path, options: &SearchOptions) -> TokioCommand {
+        let mut cmd = TokioCommand::new(rg_path);
+        
+        // A cancelled search must not leave ripgrep running
+        cmd.kill_on_drop(true);
+        
+        // Configure output format
+        cmd.arg("--no-config"); // Ignore user config files
+        
+        if options.fixed_strings {
+            cmd.arg("-F"); // Fixed strings mode
+        }
+        
+        match options.case() {
+            CaseMode::Sensitive => {}
+            CaseMode::Insensitive => {
+                cmd.arg("-i"); // Case insensitive
+            }
+            CaseMode::Smart => {
+                cmd.arg("-S"); // Case sensitive only with an uppercase letter
+            }
+        }
+        
+        if options.word_boundary {
+            cmd.arg("-w"); // Whole words only
+        }
+        
+        if options.multiline || options.multiline_dotall {
+            cmd.arg("-U"); // Matches may span lines
+        }
+        if options.multiline_dotall {
+            cmd.arg("--multiline-dotall");
+        }
+        
+        if options.pcre2 {
+            cmd.arg("-P"); // PCRE2 regex engine
+        }
+        
+        if let Some(encoding) = &options.encoding {
+            cmd.arg("-E").arg(encoding); // Transcode to UTF-8 before matching
+        }
+        
+        // Custom types must be defined before they are selected
+        for spec in &options.type_add {
+            cmd.arg("--type-add").arg(spec);
+        }
+        
+        // The allowlist narrows the walk; the request's own types and globs
+        // can widen it again, so the output is filtered as well
+        if let Some(allowed) = options.allowed_extensions.as_deref().filter(|_| options.file_types.is_empty()) {
+            cmd.args(allowlist_args(allowed));
+        }
+        
+        // Add file types if specified
+        for file_type in &options.file_types {
+            cmd.arg("-t").arg(file_type);
+        }
+        
+        for glob in &options.globs {
+            cmd.arg("-g").arg(glob);
+        }
+        for glob in &options.exclude_globs {
+            cmd.arg("-g").arg(format!("!{}", glob));
+        }
+        
+        // Add max depth if specified
+        if let Some(depth) = options.max_depth {
+            cmd.arg("--max-depth").arg(depth.to_string());
+        }
+        
+        if let Some(size) = &options.max_filesize {
+            cmd.arg("--max-filesize").arg(size);
+        }
+        
+        if options.follow_symlinks {
+            cmd.arg("--follow"); // Follow symbolic links
+        }
+        
+        if let Some(threads) = options.threads {
+            cmd.arg("-j").arg(threads.to_string());
+        }
+        
+        // Disable every ignore source the client did not ask for
+        if let Some(sources) = &options.use_ignore_files {
+            for source in IgnoreSource::ALL {
+                if !sources.contains(&source) {
+                    cmd.arg(source.disable_flag());
+                }
+            }
+        }
+        if options.no_ignore {
+            cmd.arg("--no-ignore"); // Every ignore source at once
+        }
+        
+        if options.search_hidden {
+            cmd.arg("--hidden"); // Names starting with `.`
+        }
+        
+        cmd
+    }
+    
+    async fn build_command(
+        &self,
+        options: &SearchOptions,
+        search_paths: &[PathBuf],
+    ) -> Result<(std::process::Output, Option<ResourceUsage>), AppError> {
+        let cmd = Self::search_command(&self.rg_path, options, search_paths);
+        if options.resource_report {
+            let (output, usage) = run_command_measured(cmd).await?;
+            Ok((output, Some(usage)))
+        } else {
+            Ok((run_command(cmd).await?, None))
+        }
+    }
+    
+    /// Stream ripgrep's output line by line and stop it once the first
+    /// matching file is complete (`first_result_fast`), `hard_limit` matching
+    /// lines have arrived or more lines than `max_results` would keep are in,
+    /// so memory stays bounded however much matches. Each line is also passed
+    /// on to the options' line sink. Returns the output, whether ripgrep was
+    /// cut short, and its stderr
+    async fn stream_lines(
+        &self,
+        options: &SearchOptions,
+        search_paths: &[PathBuf],
+        filter: &FileFilter,
+    ) -> Result<(String, bool, bool, String), AppError> {
+        let mut cmd = Self::search_command(&self.rg_path, options, search_paths);
+        cmd.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).kill_on_drop(true);
+        
+        let mut child = cmd.spawn().map_err(|e| spawn_error(&cmd, e))?;
+        let (Some(stdout), Some(mut stderr_pipe)) = (child.stdout.take(), child.stderr.take()) else {
+            return Err(AppError::RipgrepError("Ripgrep output unavailable".to_string()));
+        };
+        
+        // Drain stderr alongside stdout so a chatty `--debug` cannot fill
+        // the pipe and stall ripgrep
+        let stderr_reader = tokio::spawn(async move {
+            let mut stderr = Vec::new();
+            let _ = stderr_pipe.read_to_end(&mut stderr).await;
+            stderr
+        });
+        
+        // One line past the cap is enough to know the result is truncated
+        let cap = options.max_results.filter(|_| options.stops_at_max_results());
+        let over_limit = |matched: usize| {
+            options.hard_limit.is_some_and(|limit| matched >= limit) || cap.is_some_and(|max| matched > max)
+        };
+        
+        let mut lines = BufReader::new(stdout).split(b'\n');
+        let mut output_filter = OutputFilter::new(filter, options);
+        let mut collected = String::new();
+        let mut stopped_early = false;
+        let mut matched = 0;
+        let mut in_stats = false;
+        while let Some(bytes) = lines.next_segment().await? {
+            let line = decode_stdout(bytes, options.lossy_decode)?;
+            let line = line.strip_suffix('\r').unwrap_or(&line);
+            if !output_filter.push(line, &mut collected) {
+                continue;
+            }
+            if options.uses_json_output() {
+                match serde_json::from_str(line) {
+                    Ok(RgEvent::End {}) if options.first_result_fast => stopped_early = true,
+                    Ok(RgEvent::Match(_)) => {
+                        matched += 1;
+                        stopped_early = over_limit(matched);
+                    }
+                    _ => {}
+                }
+            } else if line.is_empty() {
+                // Only the `--stats` block follows
+                in_stats = true;
+            } else if !in_stats {
+                matched += 1;
+                stopped_early = over_limit(matched);
+            }
+            if let Some(sink) = options.line_sink.as_ref().filter(|_| !in_stats) {
+                // A receiver that went away only means nobody is watching
+                for live in live_lines(line, options, self.base_root(options.root_index)?) {
+                    let _ = sink.send(live);
+                }
+            }
+            if stopped_early {
+                break;
+            }
+        }
+        
+        if stopped_early {
+            // Ripgrep may already be done; a failed kill is harmless
+            let _ = child.kill().await;
+        }
+        let status = child.wait().await?;
+        let stderr = stderr_reader.await.unwrap_or_default();
+        
+        // A killed ripgrep reports no meaningful status
+        let output = std::process::Output { status, stdout: Vec::new(), stderr };
+        let output = if stopped_early { output } else { check_output(output)? };
+        
+        Ok((
+            collected,
+            stopped_early,
+            output_filter.dropped,
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ))
+    }
+    
+    /// The full search invocation: filters, output flags, pattern and paths
+    fn search_command(rg_path: &Path, options: &SearchOptions, search_paths: &[PathBuf]) -> TokioCommand {
+        let mut cmd = Self::base_command(rg_path, options);
+        
+        // Always prefix lines with their path, even when the scope narrows
+        // down to a single file
+        cmd.arg("--with-filename");
+        
+        // Plain text is the default whatever ripgrep detects about its output
+        cmd.arg("--color").arg(if options.color { "always" } else { "never" });
+        
+        // Piped output is otherwise held back in a block buffer
+        if options.line_sink.is_some() {
+            cmd.arg("--line-buffered");
+        }
+        
+        if options.line_numbers {
+            cmd.arg("-n"); // Line numbers
+        }
+        
+        if options.invert_match {
+            cmd.arg("-v"); // Non-matching lines
+        }
+        
+        // The JSON printer reports offsets already
+        if options.columns && !options.uses_json_output() {
+            cmd.arg("--column"); // path:line:column:text
+        }
+        
+        if options.byte_offset && !options.uses_json_output() {
+            cmd.arg("-b"); // path:line:offset:text
+        }
+        
+        if let Some(max) = options.max_count_per_file {
+            cmd.arg("-m").arg(max.to_string()); // Matching lines per file
+        }
+        
+        if let Some(sort) = options.sort {
+            cmd.arg("--sort").arg(sort.flag_value()); // Single-threaded
+        }
+        
+        if let Some(replacement) = &options.replace {
+            cmd.arg("-r").arg(replacement); // Printed substitution only
+        }
+        
+        // The JSON printer ignores -o, so JSON modes cut matches out themselves
+        if options.only_matching && !options.uses_json_output() {
+            cmd.arg("-o"); // Only the matched parts
+        }
+        
+        // JSON output delimits paths already
+        if options.null_output && !options.uses_json_output() {
+            cmd.arg("--null"); // NUL after each path
+        }
+        
+        // Add context lines if specified
+        // Hunk context is read from the files instead
+        if let Some(context) = options.context_lines.filter(|_| !options.patch_hunks) {
+            cmd.arg("-C").arg(context.to_string());
+        }
+        
+        // Structured output modes parse ripgrep's JSON event stream, whose
+        // summary carries the stats; plain text appends them as a block
+        if options.uses_json_output() {
+            cmd.arg("--json");
+        } else {
+            cmd.arg("--stats");
+        }
+        
+        if options.diagnose {
+            cmd.arg("--debug"); // Log skipped files to stderr
+        }
+        
+        // Add pattern and paths
+        cmd.arg(&options.pattern);
+        cmd.args(search_paths);
+        
+        cmd
+    }
+    
+    /// Look up each file's latest commit in one `git log` pass over at most
+    /// `MAX_LAST_COMMIT_FILES` files, stopping once every file was seen.
+    /// Returns `None` when the root is not inside a git work tree;
+    /// untracked files are left out
+    async fn last_commits(&self, root: &Path, files: &[PathBuf]) -> Option<Vec<FileCommit>> {
+        let inside_repo = TokioCommand::new("git")
+            .arg("-C")
+            .arg(root)
+            .args(["rev-parse", "--is-inside-work-tree"])
+            .kill_on_drop(true)
+            .output()
+            .await
+            .is_ok_and(|output| output.status.success());
+        if !inside_repo {
+            debug!("Root is not a git work tree; skipping last commits");
+            return None;
+        }
+        
+        let files = &files[..files.len().min(MAX_LAST_COMMIT_FILES)];
+        if files.is_empty() {
+            return Some(Vec::new());
+        }
+        
+        // Newest commits come first, each as `\0hash\tdate\0` and then its
+        // changed files, relative to the root and NUL-terminated
+        let mut child = TokioCommand::new("git")
+            .arg("-C")
+            .arg(root)
+            .args(["log", "-z", "--name-only", "--relative", "--format=%x00%H%x09%cI", "--"])
+            .args(files)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .ok()?;
+        let stdout = child.stdout.take()?;
+        
+        let mut latest: HashMap<String, (String, String)> = HashMap::new();
+        let mut segments = BufReader::new(stdout).split(b'\0');
+        let mut header = false;
+        let mut commit = None;
+        while latest.len() < files.len() {
+            let Ok(Some(segment)) = segments.next_segment().await else {
+                break;
+            };
+            let segment = String::from_utf8_lossy(&segment);
+            if segment.is_empty() {
+                header = true;
+            } else if std::mem::take(&mut header) {
+                commit = segment.split_once('\t').map(|(hash, date)| (hash.to_string(), date.to_string()));
+            } else if let Some(commit) = &commit {
+                let name = segment.strip_prefix('\n').unwrap_or(&segment);
+                latest.entry(name.to_string()).or_insert_with(|| commit.clone());
+            }
+        }
+        // Dropping the child kills a `git log` still walking older history
+        drop(child);
+        
+        let commits = files
+            .iter()
+            .filter_map(|file| {
+                let (commit, date) = latest.get(&relative_components(file, root).join("/"))?;
+                Some(FileCommit {
+                    path: file.to_string_lossy().into_owned(),
+                    commit: commit.clone(),
+                    date: date.clone(),
+                })
+            })
+            .collect();
+        Some(commits)
+    }
+    
+    /// List the files under `search_paths` that contain `pattern`, using the
+    /// same filters as the main search
+    async fn files_matching(&self, options: &SearchOptions, pattern: &str, search_paths: &[PathBuf]) -> Result<Vec<PathBuf>, AppError> {
+        let mut cmd = Self::base_command(&self.rg_path, options);
+        cmd.arg("--files-with-matches");
+        cmd.arg(pattern);
+        cmd.args(search_paths);
+        
+        let output = run_command(cmd).await?;
+        let files = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect();
+        
+        Ok(files)
+    }
+    
+    /// Count every individual match without transferring the matched lines
+    async fn count_matches(
+        &self,
+        options: &SearchOptions,
+        search_paths: &[PathBuf],
+        filter: &FileFilter,
+    ) -> Result<usize, AppError> {
+        let mut cmd = Self::base_command(&self.rg_path, options);
+        cmd.arg("--count-matches").arg("--with-filename");
+        if options.invert_match {
+            cmd.arg("-v"); // Counts the non-matching lines instead
+        }
+        cmd.arg(&options.pattern);
+        cmd.args(search_paths);
+        
+        let output = run_command(cmd).await?;
+        let total = count_per_file(&String::from_utf8_lossy(&output.stdout))
+            .into_iter()
+            .filter(|f| filter.keeps(Path::new(&f.path)))
+            .map(|f| f.count)
+            .sum();
+        
+        Ok(total)
+    }
+}
+
+/// The deadline a search runs under: its own `timeout_ms`, else the
+/// server's default. A zero timeout is rejected as an option conflict instead
+fn search_timeout(settings: &SearchSettings, options: &SearchOptions) -> Option<u64> {
+    options.timeout_ms.or(settings.search_timeout_ms).filter(|&ms| ms > 0)
+}
+
+/// Fail `run` with a timeout error once `timeout_ms` have passed. Dropping
+/// the timed-out future kills any ripgrep still running (`kill_on_drop`),
+/// and tokio reaps it in the background
+async fn with_timeout<T>(
+    timeout_ms: Option<u64>,
+    run: impl Future<Output = Result<T, AppError>>,
+) -> Result<T, AppError> {
+    match timeout_ms {
+        Some(ms) => tokio::time::timeout(Duration::from_millis(ms), run)
+            .await
+            .map_err(|_| AppError::Timeout(ms))?,
+        None => run.await,
+    }
+}
+
+/// Reject a request naming more paths than the server allows
+fn check_path_count(settings: &SearchSettings, options: &SearchOptions) -> Result<(), AppError> {
+    let count = options.requested_paths().len();
+    if count > settings.max_paths_per_request {
+        return Err(AppError::TooManyPaths(count, settings.max_paths_per_request));
+    }
+    Ok(())
+}
+
+/// Reject option combinations that cannot produce a sensible result
+fn check_option_conflicts(options: &SearchOptions) -> Result<(), AppError> {
+    // Structured modes carry no escape codes to keep
+    if options.color && options.uses_json_output() {
+        return Err(AppError::ConfigError(
+            "color only applies to plain text output and cannot be combined with structured options".to_string(),
+        ));
+    }
+    
+    // The JSON printer ignores replacements
+    if options.replace.is_some() && options.uses_json_output() {
+        return Err(AppError::ConfigError(
+            "replace only applies to plain text output and cannot be combined with structured options".to_string(),
+        ));
+    }
+    
+    // Only windows have cuts to move
+    if options.smart_truncate && options.match_window_chars.is_none() {
+        return Err(AppError::ConfigError("smart_truncate only applies to match_window_chars windows".to_string()));
+    }
+    
+    // Hunks are rebuilt from plain `path:line:text` preview lines, one
+    // replaced line per original line
+    if options.patch_hunks
+        && (options.replace.is_none() || !options.line_numbers || options.columns || options.byte_offset || options.color)
+    {
+        return Err(AppError::ConfigError(
+            "patch_hunks needs replace with line_numbers, and cannot be combined with columns, byte_offset or color"
+                .to_string(),
+        ));
+    }
+    if options.patch_hunks
+        && (options.replace.as_deref().is_some_and(|r| r.contains('\n')) || options.multiline || options.multiline_dotall)
+    {
+        return Err(AppError::ConfigError(
+            "patch_hunks only previews single-line replacements; drop newlines from replace and multiline".to_string(),
+        ));
+    }
+    
+    if options.max_submatches_per_line == Some(0) {
+        return Err(AppError::ConfigError("max_submatches_per_line must be at least 1".to_string()));
+    }
+    if let Some(size) = options.max_filesize.as_deref().filter(|size| !is_filesize(size)) {
+        return Err(AppError::ConfigError(format!(
+            "Invalid max_filesize {:?}: expected a byte count with an optional K, M or G suffix",
+            size
+        )));
+    }
+    if options.tab_width == Some(0) {
+        return Err(AppError::ConfigError("tab_width must be at least 1".to_string()));
+    }
+    if options.timeout_ms == Some(0) {
+        return Err(AppError::ConfigError("timeout_ms must be at least 1".to_string()));
+    }
+    if options.max_results == Some(0) || options.max_count_per_file == Some(0) || options.sample == Some(0) {
+        return Err(AppError::ConfigError(
+            "max_results, max_count_per_file and sample must be at least 1".to_string(),
+        ));
+    }
+    
+    Ok(())
+}
+
+/// Ripgrep's output as text; invalid UTF-8 fails the search unless the
+/// request accepts replacement characters
+fn decode_stdout(stdout: Vec<u8>, lossy: bool) -> Result<String, AppError> {
+    match String::from_utf8(stdout) {
+        Ok(text) => Ok(text),
+        Err(e) if lossy => Ok(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+        Err(_) => Err(AppError::RipgrepError(
+            "Invalid UTF-8 in output; set the files' encoding or lossy_decode".to_string(),
+        )),
+    }
+}
+
+/// Execute a ripgrep command, treating "no matches" as success
+async fn run_command(mut cmd: TokioCommand) -> Result<std::process::Output, AppError> {
+    let output = cmd.output().await.map_err(|e| spawn_error(&cmd, e))?;
+    check_output(output)
+}
+
+/// Like `run_command`, sampling the child's resource use until it exits
+async fn run_command_measured(mut cmd: TokioCommand) -> Result<(std::process::Output, ResourceUsage), AppError> {
+    cmd.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    let mut child = cmd.spawn().map_err(|e| spawn_error(&cmd, e))?;
+    let mut monitor = ResourceMonitor::new(child.id());
+    let (Some(mut stdout_pipe), Some(mut stderr_pipe)) = (child.stdout.take(), child.stderr.take()) else {
+        return Err(AppError::RipgrepError("Ripgrep output unavailable".to_string()));
+    };
+    
+    let read = async {
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        tokio::try_join!(stdout_pipe.read_to_end(&mut stdout), stderr_pipe.read_to_end(&mut stderr))?;
+        Ok::<_, std::io::Error>((stdout, stderr))
+    };
+    tokio::pin!(read);
+    let mut ticker = tokio::time::interval(RESOURCE_SAMPLE_INTERVAL);
+    let (stdout, stderr) = loop {
+        tokio::select! {
+            read = &mut read => break read?,
+            _ = ticker.tick() => {
+                monitor.sample();
+            }
+        }
+    };
+    
+    // The pipes close as ripgrep exits; its final CPU times stay readable
+    // until it is reaped
+    for _ in 0..RESOURCE_FINAL_SAMPLES {
+        if monitor.sample() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    let status = child.wait().await?;
+    let output = check_output(std::process::Output { status, stdout, stderr })?;
+    Ok((output, monitor.usage()))
+}
+
+/// How often a measured search samples ripgrep's resource use
+const RESOURCE_SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Samples, a millisecond apart, waiting for ripgrep to finish exiting
+const RESOURCE_FINAL_SAMPLES: usize = 50;
+
+fn spawn_error(cmd: &TokioCommand, e: std::io::Error) -> AppError {
+    let program = cmd.as_std().get_program().to_string_lossy().into_owned();
+    AppError::RipgrepError(format!("Failed to execute ripgrep at `{}`: {}", program, e))
+}
+
+/// Fail on ripgrep's error exit codes; 1 only means nothing matched
+fn check_output(output: std::process::Output) -> Result<std::process::Output, AppError> {
+    // Check if the command was successful
+    // Note: ripgrep returns status code 1 when no matches found, which is not an error
+    if !output.status.success() && output.status.code() != Some(1) {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        error!(%stderr, "Ripgrep command failed");
+        if let Some(path) = permission_denied_path(&stderr) {
+            return Err(AppError::PermissionDenied(path));
+        }
+        if pcre2_unavailable(&stderr) {
+            return Err(AppError::RipgrepError(
+                "PCRE2 is not available in this ripgrep build; search without pcre2 or install ripgrep with PCRE2 support".to_string(),
+            ));
+        }
+        return Err(AppError::RipgrepError(format!("Ripgrep failed: {}", stderr)));
+    }
+    
+    Ok(output)
+}
+
+/// Whether ripgrep refused `-P` because it was built without PCRE2
+fn pcre2_unavailable(stderr: &str) -> bool {
+    stderr.contains("PCRE2 is not available")
+}
+
+/// Fail early when the server process cannot read `path`; `display` names
+/// it in the error as the client wrote it
+fn check_readable(path: &Path, display: &str) -> Result<(), AppError> {
+    let denied = |e: &std::io::Error| e.kind() == std::io::ErrorKind::PermissionDenied;
+    
+    // Metadata only needs access to the parent; opening proves read access.
+    // Special files are left to ripgrep: opening a FIFO with no writer
+    // would block the runtime worker indefinitely
+    let readable = std::fs::metadata(path).and_then(|meta| {
+        if meta.is_dir() {
+            std::fs::read_dir(path).map(drop)
+        } else if meta.is_file() {
+            std::fs::File::open(path).map(drop)
+        } else {
+            Ok(())
+        }
+    });
+    match readable {
+        Err(e) if denied(&e) => Err(AppError::PermissionDenied(display.to_string())),
+        _ => Ok(()),
+    }
+}
+
+/// First path ripgrep's stderr reports as unreadable, e.g.
+/// `rg: ./secret: Permission denied (os error 13)`
+fn permission_denied_path(stderr: &str) -> Option<String> {
+    stderr.lines().find_map(|line| {
+        let rest = line.strip_prefix("rg: ")?;
+        let (path, _) = rest.split_once(": Permission denied")?;
+        Some(path.to_string())
+    })
+}
+
+/// Whether `path`'s extension is on the allowlist, ignoring case; every
+/// path passes without one
+fn has_allowed_extension(path: &Path, allowed: Option<&[String]>) -> bool {
+    let Some(allowed) = allowed else {
+        return true;
+    };
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| allowed.iter().any(|a| a.eq_ignore_ascii_case(ext)))
+}
+
+/// Name of the ripgrep type the extension allowlist is defined as
+const ALLOWLIST_TYPE: &str = "allowlisted";
+
+/// `--type-add`/`-t` arguments selecting the allowlisted extensions, in any
+/// letter case as `has_allowed_extension` compares them
+fn allowlist_args(allowed: &[String]) -> Vec<String> {
+    let mut args = Vec::with_capacity(allowed.len() * 2 + 2);
+    for ext in allowed {
+        let glob: String = ext
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphabetic() {
+                    format!("[{}{}]", c.to_ascii_lowercase(), c.to_ascii_uppercase())
+                } else {
+                    c.to_string()
+                }
+            })
+            .collect();
+        args.push("--type-add".to_string());
+        args.push(format!("{}:*.{}", ALLOWLIST_TYPE, glob));
+    }
+    args.push("-t".to_string());
+    args.push(ALLOWLIST_TYPE.to_string());
+    args
+}
+
+/// Drop named files without an allowed extension; ripgrep searches named
+/// files whatever their type
+fn allowed_paths(settings: &SearchSettings, search_paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    let allowed = settings.allowed_extensions.as_deref();
+    search_paths
+        .into_iter()
+        .filter(|path| !path.is_file() || has_allowed_extension(path, allowed))
+        .collect()
+}
+
+/// Per-file conditions that ripgrep's own filters cannot fully express,
+/// checked against the files in its output. Listing the candidate files up
+/// front and passing them as arguments would overflow the argument list on
+/// large trees
+#[derive(Debug, Default)]
+struct FileFilter {
+    allowed_extensions: Option<Vec<String>>,
+    newer_than: Option<SystemTime>,
+    older_than: Option<SystemTime>,
+    
+    /// Canonical root that files' resolved targets must stay inside
+    inside_root: Option<PathBuf>,
+}
+
+impl FileFilter {
+    fn is_empty(&self) -> bool {
+        self.allowed_extensions.is_none()
+            && self.newer_than.is_none()
+            && self.older_than.is_none()
+            && self.inside_root.is_none()
+    }
+    
+    fn keeps(&self, path: &Path) -> bool {
+        has_allowed_extension(path, self.allowed_extensions.as_deref())
+            && self.in_mtime_window(path)
+            && self.inside_root.as_deref().is_none_or(|root| {
+                std::fs::canonicalize(path).is_ok_and(|target| target.starts_with(root))
+            })
+    }
+    
+    fn in_mtime_window(&self, path: &Path) -> bool {
+        if self.newer_than.is_none() && self.older_than.is_none() {
+            return true;
+        }
+        match std::fs::metadata(path).and_then(|m| m.modified()) {
+            Ok(mtime) => {
+                self.newer_than.is_none_or(|t| mtime > t) && self.older_than.is_none_or(|t| mtime < t)
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Drops the output of the files a `FileFilter` rejects, line by line so
+/// streamed output is filtered as it arrives. Once anything was dropped,
+/// ripgrep's stats no longer describe the output and are dropped too
+struct OutputFilter<'a> {
+    filter: &'a FileFilter,
+    options: &'a SearchOptions,
+    verdicts: HashMap<String, bool>,
+    dropped: bool,
+    kept_any: bool,
+    pending_separator: bool,
+    in_stats: bool,
+}
+
+impl<'a> OutputFilter<'a> {
+    fn new(filter: &'a FileFilter, options: &'a SearchOptions) -> Self {
+        Self {
+            filter,
+            options,
+            verdicts: HashMap::new(),
+            dropped: false,
+            kept_any: false,
+            pending_separator: false,
+            in_stats: false,
+        }
+    }
+    
+    /// Append `line` to `out` unless it belongs to a rejected file; returns
+    /// whether it was appended
+    fn push(&mut self, line: &str, out: &mut String) -> bool {
+        let keep = if self.filter.is_empty() {
+            true
+        } else if self.options.uses_json_output() {
+            self.keeps_event(line)
+        } else {
+            self.keeps_text_line(line, out)
+        };
+        if keep {
+            out.push_str(line);
+            out.push('\n');
+        }
+        keep
+    }
+    
+    fn keeps_event(&mut self, line: &str) -> bool {
+        match serde_json::from_str::<RgPathEvent>(line) {
+            Ok(event) if event.kind == "summary" => !self.dropped,
+            Ok(RgPathEvent { data: Some(RgPathData { path: Some(path) }), .. }) => self.keeps_file(path.as_str()),
+            _ => true,
+        }
+    }
+    
+    /// Group separators are held back until the next kept line, so dropped
+    /// groups leave no doubled or dangling `--`
+    fn keeps_text_line(&mut self, line: &str, out: &mut String) -> bool {
+        if self.in_stats {
+            return !self.dropped;
+        }
+        if line.is_empty() {
+            // Only the `--stats` block follows
+            self.in_stats = true;
+            return !self.dropped;
+        }
+        
+        let plain = if self.options.color { strip_ansi(line) } else { line.to_string() };
+        if plain == "--" {
+            self.pending_separator = self.kept_any;
+            return false;
+        }
+        let keep = match text_output_path(&plain, self.options.null_output) {
+            Some(path) => self.keeps_file(path),
+            None => true,
+        };
+        if keep {
+            if std::mem::take(&mut self.pending_separator) {
+                out.push_str("--\n");
+            }
+            self.kept_any = true;
+        }
+        keep
+    }
+    
+    fn keeps_file(&mut self, path: &str) -> bool {
+        let filter = self.filter;
+        let keep = *self
+            .verdicts
+            .entry(path.to_string())
+            .or_insert_with(|| filter.keeps(Path::new(path)));
+        self.dropped |= !keep;
+        keep
+    }
+}
+
+/// Why a filtered result reports no `files_searched`
+const FILTERED_STATS_WARNING: &str = "Some matched files were dropped (newer_than, older_than, allowed extensions or \
+     symlinks out of the root), so files_searched is unknown and files_with_matches counts the kept files";
+
+/// Run a whole ripgrep output through an `OutputFilter`; also returns
+/// whether any file was dropped
+fn filter_output(stdout: String, filter: &FileFilter, options: &SearchOptions) -> (String, bool) {
+    if filter.is_empty() {
+        return (stdout, false);
+    }
+    let mut output_filter = OutputFilter::new(filter, options);
+    let mut filtered = String::with_capacity(stdout.len());
+    for line in stdout.lines() {
+        output_filter.push(line, &mut filtered);
+    }
+    (filtered, output_filter.dropped)
+}
+
+/// The file a text output line, match or context, came from: the first
+/// `path:` or `path-` prefix that is an existing file
+fn text_output_path(line: &str, null_output: bool) -> Option<&str> {
+    if null_output {
+        return line.split_once('\0').map(|(path, _)| path);
+    }
+    line.match_indices([':', '-'])
+        .map(|(i, _)| &line[..i])
+        .find(|prefix| Path::new(prefix).is_file())
+}
+
+/// Collect the distinct files that produced the given output lines
+fn matched_files(lines: &[String], line_numbers: bool, null_output: bool) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+    for line in lines {
+        if let Some(path) = text_line_path(line, line_numbers, null_output) {
+            // Context lines and odd filenames can yield bogus prefixes, so
+            // only keep candidates that really are files
+            if seen.insert(path) && Path::new(path).is_file() {
+                files.push(PathBuf::from(path));
+            }
+        }
+    }
+    files
+}
+
+/// Parse `path:count` lines from `--count-matches --with-filename`
+fn count_per_file(stdout: &str) -> Vec<FileCount> {
+    let mut counts: Vec<FileCount> = stdout
+        .lines()
+        .filter_map(|line| {
+            let (path, count) = line.rsplit_once(':')?;
+            Some(FileCount {
+                path: path.to_string(),
+                count: count.trim().parse().ok()?,
+            })
+        })
+        .collect();
+    counts.sort_by(|a, b| a.path.cmp(&b.path));
+    counts
+}
+
+/// Matched files whose last commit `include_last_commit` looks up, which
+/// bounds the `git log` command line
+const MAX_LAST_COMMIT_FILES: usize = 500;
+
+/// Unchanged lines around each change in `patch_hunks` unless the request
+/// sets `context_lines`
+const DEFAULT_PATCH_CONTEXT: usize = 3;
+
+/// Turn `replace` preview lines (`path:line:replaced`) into unified-diff
+/// hunks against the files on disk, with `context` unchanged lines around
+/// each change (`context_lines`, or a default); changes whose context
+/// overlaps share a hunk. Each line keeps the file's own line ending
+async fn replacement_hunks(stdout: &str, root_dir: &Path, options: &SearchOptions) -> Vec<PatchHunk> {
+    let context = options.context_lines.unwrap_or(DEFAULT_PATCH_CONTEXT);
+    
+    // Replaced lines per file, in ripgrep's file order
+    let (lines, _) = split_text_stats(stdout);
+    let mut files: Vec<(&str, BTreeMap<usize, &str>)> = Vec::new();
+    for line in lines {
+        let Some(path) = text_line_path(line, true, options.null_output) else {
+            continue;
+        };
+        let Some((number, text)) = line[path.len() + 1..].split_once(':') else {
+            continue;
+        };
+        let Ok(number) = number.parse::<usize>() else {
+            continue;
+        };
+        match files.iter_mut().find(|(p, _)| *p == path) {
+            Some((_, changes)) => {
+                changes.insert(number, text);
+            }
+            None => files.push((path, BTreeMap::from([(number, text)]))),
+        }
+    }
+    
+    let mut hunks = Vec::new();
+    for (path, changes) in files {
+        let Ok(content) = tokio::fs::read_to_string(path).await else {
+            continue;
+        };
+        // Each line with its terminator: `\n`, `\r\n`, or none at the end
+        let original: Vec<(&str, &str)> = content
+            .split_inclusive('\n')
+            .map(|line| {
+                let text = line.strip_suffix('\n').map_or(line, |l| l.strip_suffix('\r').unwrap_or(l));
+                (text, &line[text.len()..])
+            })
+            .collect();
+        
+        // A replacement that leaves the line as it was is no change
+        let changes: BTreeMap<usize, &str> = changes
+            .into_iter()
+            .filter(|(n, text)| {
+                n.checked_sub(1)
+                    .and_then(|i| original.get(i))
+                    .is_some_and(|(old, _)| *old != text.strip_suffix('\r').unwrap_or(text))
+            })
+            .collect();
+        
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for &n in changes.keys() {
+            let start = n.saturating_sub(context).max(1);
+            let end = (n + context).min(original.len());
+            match ranges.last_mut() {
+                Some((_, last_end)) if start <= *last_end + 1 => *last_end = end,
+                _ => ranges.push((start, end)),
+            }
+        }
+        
+        let relative = relative_components(Path::new(path), root_dir).join("/");
+        for (start, end) in ranges {
+            let count = end - start + 1;
+            let mut diff = format!("--- a/{0}\n+++ b/{0}\n@@ -{1},{2} +{1},{2} @@\n", relative, start, count);
+            for n in start..=end {
+                let (old, ending) = original[n - 1];
+                match changes.get(&n) {
+                    Some(new) => {
+                        push_diff_line(&mut diff, '-', old, ending);
+                        push_diff_line(&mut diff, '+', new.strip_suffix('\r').unwrap_or(new), ending);
+                    }
+                    None => push_diff_line(&mut diff, ' ', old, ending),
+                }
+            }
+            hunks.push(PatchHunk {
+                path: relative.clone(),
+                old_start: start,
+                old_lines: count,
+                new_start: start,
+                new_lines: count,
+                diff,
+            });
+        }
+    }
+    hunks
+}
+
+/// Append one diff line ending as the file's line did; the last line of a
+/// file without a final newline gets diff's marker for that
+fn push_diff_line(diff: &mut String, marker: char, text: &str, ending: &str) {
+    diff.push(marker);
+    diff.push_str(text);
+    if ending.is_empty() {
+        diff.push_str("\n\\ No newline at end of file\n");
+    } else {
+        diff.push_str(ending);
+    }
+}
+
+/// Extract the path prefix of a `path:line:text` (or `path:text`) match line.
+/// Under `--null` the path ends at the NUL, which no filename can contain
+fn text_line_path(line: &str, line_numbers: bool, null_output: bool) -> Option<&str> {
+    if null_output {
+        return line.split_once('\0').map(|(path, _)| path);
+    }
+    
+    if !line_numbers {
+        return line.split_once(':').map(|(path, _)| path);
+    }
+    
+    line.match_indices(':').find_map(|(i, _)| {
+        let rest = &line[i + 1..];
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        (digits > 0 && rest[digits..].starts_with(':')).then(|| &line[..i])
+    })
+}
+
+/// A single event from ripgrep's `--json` output stream
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "lowercase")]
+enum RgEvent {
+    Begin {},
+    Match(RgLine),
+    Context(RgLine),
+    End {},
+    Summary(RgSummary),
+}
+
+/// The final event of a completed `--json` run
+#[derive(Debug, Deserialize)]
+struct RgSummary {
+    #[serde(default)]
+    stats: RgStats,
+}
+
+/// The parts of ripgrep's stats the result reports
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+struct RgStats {
+    #[serde(default)]
+    searches: usize,
+    #[serde(default)]
+    searches_with_match: usize,
+    #[serde(default)]
+    matched_lines: usize,
+}
+
+impl RgStats {
+    /// Share of searched files with a match; zero when nothing was searched
+    fn match_ratio(&self) -> f64 {
+        if self.searches == 0 {
+            0.0
+        } else {
+            self.searches_with_match as f64 / self.searches as f64
+        }
+    }
+}
+
+/// A matched (or context) line reported by ripgrep
+#[derive(Debug, Deserialize)]
+struct RgLine {
+    path: RgText,
+    lines: RgText,
+    line_number: Option<u64>,
+    absolute_offset: u64,
+    submatches: Vec<RgSubmatch>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RgSubmatch {
+    start: usize,
+    end: usize,
+}
+
+/// Just the type and path of a `--json` event, enough to filter by file
+#[derive(Debug, Deserialize)]
+struct RgPathEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    data: Option<RgPathData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RgPathData {
+    path: Option<RgText>,
+}
+
+/// Ripgrep encodes text as UTF-8 when possible and as base64 bytes otherwise
+#[derive(Debug, Deserialize)]
+struct RgText {
+    text: Option<String>,
+}
+
+impl RgText {
+    fn as_str(&self) -> &str {
+        self.text.as_deref().unwrap_or_default()
+    }
+}
+
+/// Ripgrep's stats from the summary event of `--json` output; a run that was
+/// stopped early has no summary and reports zeros
+fn summary_stats(stdout: &str) -> RgStats {
+    stdout
+        .lines()
+        .rev()
+        .find_map(|line| match serde_json::from_str(line) {
+            Ok(RgEvent::Summary(summary)) => Some(summary.stats),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+/// Split text output into its match lines and the `--stats` block ripgrep
+/// appends after a blank line. Counts keep only their digits, so grouping
+/// separators such as `1,234` or `1 234` parse the same
+fn split_text_stats(stdout: &str) -> (Vec<&str>, RgStats) {
+    let lines: Vec<&str> = stdout.lines().collect();
+    let block_start = lines.iter().rposition(|line| line.is_empty());
+    let Some(start) = block_start.filter(|&i| lines[i + 1..].iter().any(|l| l.ends_with(" files searched"))) else {
+        return (lines, RgStats::default());
+    };
+    
+    let count = |suffix: &str| {
+        lines[start + 1..]
+            .iter()
+            .find_map(|line| line.strip_suffix(suffix))
+            .and_then(|n| n.chars().filter(char::is_ascii_digit).collect::<String>().parse().ok())
+            .unwrap_or(0)
+    };
+    let stats = RgStats {
+        searches: count(" files searched"),
+        searches_with_match: count(" files contained matches"),
+        matched_lines: count(" matched lines"),
+    };
+    (lines[..start].to_vec(), stats)
+}
+
+/// Match lines in text output with context: ripgrep writes `path:N:`
+/// before a match, `path-N-` before a context line and `--` between
+/// groups. Without line numbers the two kinds look alike, so only the
+/// group separators are left out
+fn count_text_matches(lines: &[String], options: &SearchOptions) -> usize {
+    lines
+        .iter()
+        .map(|line| if options.color { strip_ansi(line) } else { line.clone() })
+        .filter(|line| line != "--")
+        .filter(|line| {
+            if !options.line_numbers {
+                return true;
+            }
+            match line.split_once('\0').filter(|_| options.null_output) {
+                Some((_, rest)) => {
+                    let after_digits = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+                    after_digits.len() < rest.len() && after_digits.starts_with(':')
+                }
+                None => is_text_match_line(line),
+            }
+        })
+        .count()
+}
+
+/// Whether a text line is a match (`path:N:`) rather than context
+/// (`path-N-`). Paths and text may hold such runs too, so the first split
+/// whose path is an existing file decides, then any match-style split
+fn is_text_match_line(line: &str) -> bool {
+    let splits: Vec<(usize, bool)> = line
+        .match_indices([':', '-'])
+        .filter_map(|(i, sep)| {
+            let rest = &line[i + 1..];
+            let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            (digits > 0 && rest[digits..].starts_with(sep)).then_some((i, sep == ":"))
+        })
+        .collect();
+    match splits.iter().find(|(i, _)| Path::new(&line[..*i]).is_file()) {
+        Some(&(_, is_match)) => is_match,
+        None => splits.iter().any(|&(_, is_match)| is_match),
+    }
+}
+
+/// Parse ripgrep `--json` output into its matched lines, in output order
+fn parse_json_matches(stdout: &str) -> Result<Vec<RgLine>, AppError> {
+    let mut records = Vec::new();
+    
+    for line in stdout.lines().filter(|l| !l.is_empty()) {
+        let event: RgEvent = serde_json::from_str(line)
+            .map_err(|e| AppError::RipgrepError(format!("Invalid JSON output: {}", e)))?;
+        
+        if let RgEvent::Match(m) = event {
+            trace!(path = m.path.as_str(), line = ?m.line_number, text = m.lines.as_str().trim_end(), "Match");
+            records.push(m);
+        }
+    }
+    
+    Ok(records)
+}
+
+/// Keep at most `cap` submatches per record, returning how many records
+/// lost some
+fn cap_submatches(records: &mut [RgLine], cap: usize) -> usize {
+    let mut capped = 0;
+    for m in records.iter_mut().filter(|m| m.submatches.len() > cap) {
+        m.submatches.truncate(cap);
+        capped += 1;
+    }
+    capped
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+/// The distinct files among the parsed records, in order of first match
+fn record_files(records: &[RgLine]) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    records
+        .iter()
+        .map(|m| m.path.as_str())
+        .filter(|path| seen.insert(*path))
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Render one windowed line per submatch
+fn match_windows(records: &[RgLine], chars: usize, options: &SearchOptions) -> Vec<String> {
+    let mut windows = Vec::new();
+    
+    for m in records {
+        for submatch in &m.submatches {
+            let window = if options.smart_truncate {
+                smart_match_window(m.lines.as_str(), submatch.start, submatch.end, chars)
+            } else {
+                match_window(m.lines.as_str(), submatch.start, submatch.end, chars).to_string()
+            };
+            match (options.line_numbers, m.line_number) {
+                (true, Some(n)) => windows.push(format!("{}:{}:{}", m.path.as_str(), n, window)),
+                _ => windows.push(format!("{}:{}", m.path.as_str(), window)),
+            }
+        }
+    }
+    
+    windows
+}
+
+/// One `path:line:snippet` entry per match
+fn match_snippets(records: &[RgLine], snippet: &SnippetOptions, line_numbers: bool) -> Vec<String> {
+    let mut snippets = Vec::new();
+    
+    for m in records {
+        for submatch in &m.submatches {
+            let text = match_snippet(m.lines.as_str(), submatch.start, submatch.end, snippet);
+            match (line_numbers, m.line_number) {
+                (true, Some(n)) => snippets.push(format!("{}:{}:{}", m.path.as_str(), n, text)),
+                _ => snippets.push(format!("{}:{}", m.path.as_str(), text)),
+            }
+        }
+    }
+    
+    snippets
+}
+
+/// The match with its surrounding characters, marking each side that stops
+/// short of the line boundary with the ellipsis
+fn match_snippet(line: &str, start: usize, end: usize, snippet: &SnippetOptions) -> String {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let (window_start, window_end) = match_window_bounds(line, start, end, snippet.before_chars, snippet.after_chars);
+    
+    let mut text = String::new();
+    if window_start > 0 {
+        text.push_str(&snippet.ellipsis);
+    }
+    text.push_str(&line[window_start..window_end]);
+    if window_end < line.len() {
+        text.push_str(&snippet.ellipsis);
+    }
+    text
+}
+
+/// Remove ANSI escape sequences (`ESC [ ... final`) from colored output
+fn strip_ansi(line: &str) -> String {
+    let mut plain = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip the '[' and parameters up to the final byte
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        break;
+                    }
+                }
+            }
+        } else {
+            plain.push(c);
+        }
+    }
+    
+    plain
+}
+
+/// Count each distinct matched string, most frequent first and ties in
+/// alphabetical order
+fn match_histogram(records: &[RgLine]) -> Vec<HistogramEntry> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for m in records {
+        for submatch in &m.submatches {
+            if let Some(value) = m.lines.as_str().get(submatch.start..submatch.end) {
+                *counts.entry(value).or_default() += 1;
+            }
+        }
+    }
+    
+    let mut histogram: Vec<HistogramEntry> = counts
+        .into_iter()
+        .map(|(value, count)| HistogramEntry { value: value.to_string(), count })
+        .collect();
+    histogram.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+    histogram
+}
+
+/// Rank the matched files by matches per line, densest first. Line counts
+/// come from reading each file; one that can no longer be read is left out
+fn match_density(records: &[RgLine]) -> Vec<FileDensity> {
+    let mut density: Vec<FileDensity> = summarize_files(records)
+        .into_iter()
+        .filter_map(|summary| {
+            let contents = std::fs::read(&summary.path).ok()?;
+            let line_count = contents.split(|&b| b == b'\n').count()
+                - usize::from(contents.ends_with(b"\n"));
+            Some(FileDensity {
+                density: summary.match_count as f64 / line_count.max(1) as f64,
+                path: summary.path,
+                match_count: summary.match_count,
+                line_count,
+            })
+        })
+        .collect();
+    density.sort_by(|a, b| b.density.total_cmp(&a.density).then_with(|| a.path.cmp(&b.path)));
+    density
+}
+
+/// Merge ripgrep's match and context events into blocks of consecutive
+/// lines, starting a new block at a file change or a gap in line numbers
+fn merge_context_blocks(stdout: &str) -> Result<Vec<ContextBlock>, AppError> {
+    let mut blocks: Vec<ContextBlock> = Vec::new();
+    
+    // Blocks close at every file boundary, so context never continues into
+    // the next file, not even when ripgrep reads the same path twice
+    let mut open = false;
+    for line in stdout.lines().filter(|l| !l.is_empty()) {
+        let event: RgEvent = serde_json::from_str(line)
+            .map_err(|e| AppError::RipgrepError(format!("Invalid JSON output: {}", e)))?;
+        let record = match event {
+            RgEvent::Match(record) | RgEvent::Context(record) => record,
+            RgEvent::Begin {} | RgEvent::End {} => {
+                open = false;
+                continue;
+            }
+            RgEvent::Summary(_) => continue,
+        };
+        let Some(line_number) = record.line_number else {
+            continue;
+        };
+        
+        let path = record.path.as_str();
+        let text = record.lines.as_str().trim_end_matches(['\r', '\n']).to_string();
+        match blocks.last_mut() {
+            Some(block) if open && block.path == path && line_number <= block.end_line + 1 => {
+                // Ripgrep never repeats a line, but stay safe if it did
+                if line_number > block.end_line {
+                    block.end_line = line_number;
+                    block.lines.push(text);
+                }
+            }
+            _ => blocks.push(ContextBlock {
+                path: path.to_string(),
+                start_line: line_number,
+                end_line: line_number,
+                lines: vec![text],
+            }),
+        }
+        open = true;
+    }
+    
+    Ok(blocks)
+}
+
+/// A path's components relative to the root, independent of the platform
+/// separator
+fn relative_components(path: &Path, root_dir: &Path) -> Vec<String> {
+    let relative = path.strip_prefix(root_dir).unwrap_or(path);
+    relative
+        .components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect()
+}
+
+/// The effective scope of a search, after its paths were validated
+fn search_scope(root: &Path, options: &SearchOptions, search_paths: &[PathBuf]) -> Result<SearchScope, AppError> {
+    let root = std::fs::canonicalize(root)
+        .map_err(|_| AppError::ConfigError("Could not resolve root directory".to_string()))?;
+    let paths = search_paths
+        .iter()
+        .map(|path| {
+            let resolved = std::fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+            match resolved.strip_prefix(&root) {
+                Ok(relative) if relative.as_os_str().is_empty() => ".".to_string(),
+                Ok(relative) => relative.to_string_lossy().into_owned(),
+                // Under another configured root
+                Err(_) => resolved.to_string_lossy().into_owned(),
+            }
+        })
+        .collect();
+    let ignore_sources = if options.no_ignore {
+        Vec::new()
+    } else {
+        options.use_ignore_files.clone().unwrap_or_else(|| IgnoreSource::ALL.to_vec())
+    };
+    
+    Ok(SearchScope {
+        root: root.to_string_lossy().into_owned(),
+        paths,
+        file_types: options.file_types.clone(),
+        globs: options.globs.clone(),
+        exclude_globs: options.exclude_globs.clone(),
+        max_depth: options.max_depth,
+        ignore_sources,
+        search_hidden: options.search_hidden,
+    })
+}
+
+/// MIME types by lowercase file extension
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("rs", "text/x-rust"),
+    ("py", "text/x-python"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("ts", "text/x-typescript"),
+    ("go", "text/x-go"),
+    ("c", "text/x-c"),
+    ("h", "text/x-c"),
+    ("cpp", "text/x-c++"),
+    ("java", "text/x-java"),
+    ("rb", "text/x-ruby"),
+    ("sh", "text/x-shellscript"),
+    ("html", "text/html"),
+    ("css", "text/css"),
+    ("md", "text/markdown"),
+    ("txt", "text/plain"),
+    ("csv", "text/csv"),
+    ("xml", "application/xml"),
+    ("json", "application/json"),
+    ("toml", "application/toml"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("wasm", "application/wasm"),
+    ("bin", "application/octet-stream"),
+    ("exe", "application/octet-stream"),
+    ("dll", "application/octet-stream"),
+    ("so", "application/octet-stream"),
+    ("o", "application/octet-stream"),
+];
+
+/// MIME type from the file extension; otherwise `text/plain` unless the
+/// start of the file holds a NUL byte
+fn guess_mime(path: &Path) -> &'static str {
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+    if let Some(&(_, mime)) = extension.and_then(|ext| MIME_TYPES.iter().find(|(known, _)| *known == ext)) {
+        return mime;
+    }
+    
+    let mut head = [0u8; 1024];
+    let read = std::fs::File::open(path).and_then(|mut f| std::io::Read::read(&mut f, &mut head));
+    match read {
+        Ok(n) if !head[..n].contains(&0) => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Line and block comment markers for a file, from its extension
+fn comment_markers(path: &Path) -> (&'static [&'static str], Option<(&'static str, &'static str)>) {
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    match extension.as_str() {
+        "rs" | "c" | "h" | "cc" | "cpp" | "hpp" | "java" | "js" | "mjs" | "jsx" | "ts" | "tsx" | "go"
+        | "cs" | "swift" | "kt" | "scala" | "dart" => (&["//"], Some(("/*", "*/"))),
+        "css" => (&[], Some(("/*", "*/"))),
+        "py" | "rb" | "sh" | "bash" | "zsh" | "pl" | "r" | "yaml" | "yml" | "toml" | "cfg" | "conf" => (&["#"], None),
+        "sql" | "lua" | "hs" => (&["--"], None),
+        "html" | "xml" | "md" => (&[], Some(("<!--", "-->"))),
+        _ => (&[], None),
+    }
+}
+
+/// Whether the text before a match leaves it inside a comment. Only the
+/// matched line is seen, so a block comment counts when it opens earlier on
+/// the line, or when the line continues one with a leading `*`
+fn in_comment(path: &Path, line: &str, match_start: usize) -> bool {
+    let (line_markers, block) = comment_markers(path);
+    let before = line.get(..match_start).unwrap_or(line);
+    
+    if line_markers.iter().any(|marker| before.contains(marker)) {
+        return true;
+    }
+    match block {
+        Some((open, close)) => {
+            let opened = before.rfind(open);
+            let closed = before.rfind(close);
+            let continuation = open == "/*" && before.trim_start().starts_with('*');
+            match (opened, closed) {
+                (Some(o), Some(c)) => o > c,
+                (Some(_), None) => true,
+                (None, _) => continuation,
+            }
+        }
+        None => false,
+    }
+}
+
+/// Split the matched lines into those with a match in a comment and those
+/// with a match in code (heuristic; strings holding markers fool it)
+fn partition_comments(records: &[RgLine], root_dir: &Path, line_numbers: bool) -> CommentPartition {
+    let mut in_comments = Vec::new();
+    let mut in_code = Vec::new();
+    for m in records {
+        let path = Path::new(m.path.as_str());
+        let (mut comment, mut code) = (false, false);
+        for submatch in &m.submatches {
+            if in_comment(path, m.lines.as_str(), submatch.start) {
+                comment = true;
+            } else {
+                code = true;
+            }
+        }
+        if comment {
+            in_comments.push(m);
+        }
+        if code {
+            in_code.push(m);
+        }
+    }
+    
+    CommentPartition {
+        in_comments: git_grep_lines(in_comments, root_dir, line_numbers),
+        in_code: git_grep_lines(in_code, root_dir, line_numbers),
+    }
+}
+
+/// Render matched lines the way `git grep` prints them from the repository
+/// root: `path:line:text` (or `path:text`), with `/`-separated relative paths
+fn git_grep_lines<'a>(
+    records: impl IntoIterator<Item = &'a RgLine>,
+    root_dir: &Path,
+    line_numbers: bool,
+) -> Vec<String> {
+    records
+        .into_iter()
+        .map(|m| {
+            let relative = relative_components(Path::new(m.path.as_str()), root_dir).join("/");
+            let text = m.lines.as_str().trim_end_matches(['\r', '\n']);
+            match (line_numbers, m.line_number) {
+                (true, Some(n)) => format!("{}:{}:{}", relative, n, text),
+                _ => format!("{}:{}", relative, text),
+            }
+        })
+        .collect()
+}
+
+/// One line of ripgrep's output in the shape `matches` gives it, for a line
+/// sink: events are rendered like the final result's lines (without the
+/// `--` separators, which need the lines around them), text passes through
+fn live_lines(line: &str, options: &SearchOptions, root_dir: &Path) -> Vec<String> {
+    if !options.uses_json_output() {
+        vec![line.to_string()]
+    } else if options.output_format == OutputFormat::GitGrep {
+        parse_json_matches(line)
+            .map(|records| git_grep_lines(&records, root_dir, options.line_numbers))
+            .unwrap_or_default()
+    } else {
+        text_lines(line, options).unwrap_or_default()
+    }
+}
+
+/// Render `--json` events the way ripgrep's text printer would: `path:N:`
+/// before a match line, `path-N-` before a context line and `--` between
+/// groups of context, with the paths exactly as ripgrep reported them
+fn text_lines(stdout: &str, options: &SearchOptions) -> Result<Vec<String>, AppError> {
+    let mut lines = Vec::new();
+    let mut previous: Option<(String, Option<u64>)> = None;
+    
+    for line in stdout.lines().filter(|l| !l.is_empty()) {
+        let event: RgEvent = serde_json::from_str(line)
+            .map_err(|e| AppError::RipgrepError(format!("Invalid JSON output: {}", e)))?;
+        let (record, is_match) = match event {
+            RgEvent::Match(m) => (m, true),
+            RgEvent::Context(m) => (m, false),
+            _ => continue,
+        };
+        let path = record.path.as_str();
+        let sep = if is_match { ':' } else { '-' };
+        
+        // Only output with context separates groups that do not touch
+        if options.context_lines.is_some() {
+            let adjoins = previous.as_ref().is_some_and(|(prev_path, prev_line)| {
+                prev_path == path && prev_line.zip(record.line_number).is_some_and(|(a, b)| b == a + 1)
+            });
+            if previous.is_some() && !adjoins {
+                lines.push("--".to_string());
+            }
+        }
+        
+        // A multiline match spans several lines, each printed on its own
+        let text = record.lines.as_str().strip_suffix('\n').unwrap_or(record.lines.as_str());
+        let mut last_line = record.line_number;
+        for (i, text) in text.split('\n').enumerate() {
+            let text = text.strip_suffix('\r').unwrap_or(text);
+            let mut rendered = path.to_string();
+            rendered.push(if options.null_output { '\0' } else { sep });
+            let line_number = record.line_number.map(|n| n + i as u64);
+            if let Some(n) = line_number.filter(|_| options.line_numbers) {
+                rendered.push_str(&format!("{}{}", n, sep));
+            }
+            if i == 0 {
+                let first = record.submatches.first().filter(|_| is_match);
+                if let Some(submatch) = first.filter(|_| options.columns) {
+                    rendered.push_str(&format!("{}{}", submatch.start + 1, sep));
+                }
+                if options.byte_offset {
+                    rendered.push_str(&format!("{}{}", record.absolute_offset, sep));
+                }
+            }
+            rendered.push_str(text);
+            lines.push(rendered);
+            last_line = line_number;
+        }
+        previous = Some((path.to_string(), last_line));
+    }
+    
+    Ok(lines)
+}
+
+/// Fold the parsed records into one summary per file
+fn summarize_files(records: &[RgLine]) -> Vec<FileSummary> {
+    let mut summaries: Vec<FileSummary> = Vec::new();
+    
+    for m in records {
+        // ripgrep reports each file's matches contiguously
+        match summaries.last_mut() {
+            Some(summary) if summary.path == m.path.as_str() => {
+                summary.match_count += m.submatches.len();
+            }
+            _ => summaries.push(FileSummary {
+                path: m.path.as_str().to_string(),
+                match_count: m.submatches.len(),
+                first_line: m.line_number,
+                first_match_text: m.lines.as_str().trim_end_matches(['\r', '\n']).to_string(),
+            }),
+        }
+    }
+    
+    summaries
+}
+
+/// Gather the parsed records into one group per file
+fn group_matches(records: &[RgLine]) -> Vec<FileMatches> {
+    let mut groups: Vec<FileMatches> = Vec::new();
+    
+    for m in records {
+        let line = LineMatch {
+            line_number: m.line_number,
+            text: m.lines.as_str().trim_end_matches(['\r', '\n']).to_string(),
+        };
+        // ripgrep reports each file's matches contiguously
+        match groups.last_mut() {
+            Some(group) if group.path == m.path.as_str() => group.matches.push(line),
+            _ => groups.push(FileMatches {
+                path: m.path.as_str().to_string(),
+                matches: vec![line],
+            }),
+        }
+    }
+    
+    groups
+}
+
+/// Keep the first `max` lines across the groups, dropping emptied groups
+fn truncate_groups(mut groups: Vec<FileMatches>, max: usize) -> Vec<FileMatches> {
+    let mut remaining = max;
+    groups.retain_mut(|group| {
+        group.matches.truncate(remaining);
+        remaining -= group.matches.len();
+        !group.matches.is_empty()
+    });
+    groups
+}
+
+/// A size ripgrep's `--max-filesize` accepts, like `1024`, `500K` or `1M`
+fn is_filesize(size: &str) -> bool {
+    let digits = size.strip_suffix(['K', 'M', 'G']).unwrap_or(size);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Columns a tab counts for in `indent` unless the request sets `tab_width`
+const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// Width of a line's leading spaces and tabs
+fn line_indent(line: &str, tab_width: usize) -> usize {
+    line.chars()
+        .map_while(|c| match c {
+            ' ' => Some(1),
+            '\t' => Some(tab_width),
+            _ => None,
+        })
+        .sum()
+}
+
+/// Expand the parsed records into one structured entry per match
+fn structure_matches(records: &[RgLine], options: &SearchOptions) -> Vec<StructuredMatch> {
+    let mut structured = Vec::new();
+    let mut file_lines = HashMap::new();
+    
+    for m in records {
+        let full_line = m.lines.as_str().trim_end_matches(['\r', '\n']);
+        let prev_line = if options.include_prev_line {
+            m.line_number.and_then(|n| preceding_line(&mut file_lines, m.path.as_str(), n))
+        } else {
+            None
+        };
+        let indent = options
+            .include_indent
+            .then(|| line_indent(full_line, options.tab_width.unwrap_or(DEFAULT_TAB_WIDTH)));
+        let entry = |submatch: Option<&RgSubmatch>| {
+            let json = submatch.filter(|_| options.output_format == OutputFormat::Json);
+            let highlighted = submatch.filter(|_| options.highlight_matches);
+            let only = submatch.filter(|_| options.only_matching);
+            StructuredMatch {
+                match_id: match_id(m, submatch),
+                path: m.path.as_str().to_string(),
+                line_number: m.line_number,
+                column: submatch
+                    .filter(|_| options.columns || options.output_format == OutputFormat::Json)
+                    .map(|s| s.start + 1),
+                full_line: full_line.to_string(),
+                prev_line: prev_line.clone(),
+                indent,
+                matched_text: highlighted.or(json).or(only).and_then(|s| m.lines.as_str().get(s.start..s.end)).map(str::to_string),
+                match_start: highlighted.map(|s| s.start),
+                match_end: highlighted.map(|s| s.end),
+                start_byte: submatch.filter(|_| options.byte_ranges).map(|s| m.absolute_offset + s.start as u64),
+                end_byte: submatch.filter(|_| options.byte_ranges).map(|s| m.absolute_offset + s.end as u64),
+                byte_offset: options.byte_offset.then(|| m.absolute_offset + only.map_or(0, |s| s.start as u64)),
+                delta_lines: options
+                    .relative_to_line
+                    .zip(m.line_number)
+                    .map(|(reference, line)| line as i64 - reference as i64),
+                score: options.rank_relevance.then(|| relevance_score(m, submatch)),
+            }
+        };
+        
+        if m.submatches.is_empty() {
+            structured.push(entry(None));
+        } else {
+            structured.extend(m.submatches.iter().map(|s| entry(Some(s))));
+        }
+    }
+    
+    // Stable sort keeps ripgrep's order among equally scored matches
+    if options.rank_relevance {
+        structured.sort_by_key(|m| std::cmp::Reverse(m.score));
+    }
+    
+    structured
+}
+
+/// The line before 1-based `line_number`, reading each file only once
+fn preceding_line(cache: &mut HashMap<String, Vec<String>>, path: &str, line_number: u64) -> Option<String> {
+    let index = (line_number as usize).checked_sub(2)?;
+    let lines = cache.entry(path.to_string()).or_insert_with(|| match std::fs::read(path) {
+        Ok(contents) => String::from_utf8_lossy(&contents).lines().map(str::to_string).collect(),
+        Err(_) => Vec::new(),
+    });
+    lines.get(index).cloned()
+}
+
+/// Seed of the hash that picks `sample` lines, fixed so samples reproduce
+const SAMPLE_SEED: u64 = 0x5eed_0f5a_3b1e;
+
+/// The `size` lines with the lowest seeded hash, in sorted order. Picking
+/// by content makes the sample independent of ripgrep's output order
+fn sample_lines(lines: Vec<String>, size: usize) -> Vec<String> {
+    if lines.len() <= size {
+        return lines;
+    }
+    
+    let mut keyed: Vec<(u64, String)> = lines
+        .into_iter()
+        .map(|line| {
+            let mut hash: u64 = 0xcbf29ce484222325 ^ SAMPLE_SEED;
+            for byte in line.bytes() {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+            (hash, line)
+        })
+        .collect();
+    keyed.sort_unstable();
+    
+    let mut sample: Vec<String> = keyed.into_iter().take(size).map(|(_, line)| line).collect();
+    sample.sort();
+    sample
+}
+
+/// Content-addressed id of one match: FNV-1a over the path, line number
+/// and matched text, which stays the same across runs and builds
+fn match_id(m: &RgLine, submatch: Option<&RgSubmatch>) -> String {
+    let matched = submatch
+        .and_then(|s| m.lines.as_str().get(s.start..s.end))
+        .unwrap_or("");
+    let line = m.line_number.map(|n| n.to_string()).unwrap_or_default();
+    
+    let mut hash: u64 = 0xcbf29ce484222325;
+    // NUL separators keep ("ab", "c") and ("a", "bc") apart
+    for field in [m.path.as_str(), &line, matched] {
+        for byte in field.bytes().chain(std::iter::once(0)) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+/// Levenshtein distance between two short ASCII-ish names
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    
+    previous[b.len()]
+}
+
+/// Rough token count for LLM context budgets: the larger of the word count
+/// and one token per four characters
+fn estimate_tokens(text: &str) -> usize {
+    text.split_whitespace().count().max(text.chars().count().div_ceil(4))
+}
+
+/// Keep the lines that fit in `budget` estimated tokens, visiting files
+/// round-robin so each file keeps one line before any file gets a second.
+/// Lines too large for the remaining budget are skipped. Returns the kept
+/// lines in their original order, their token estimate and whether any
+/// line was dropped
+fn fit_token_budget(
+    lines: Vec<String>,
+    budget: usize,
+    line_numbers: bool,
+    null_output: bool,
+) -> (Vec<String>, usize, bool) {
+    // Group line indices by file, in order of each file's first line;
+    // context and separator lines stay with the file before them
+    let mut groups: Vec<(&str, Vec<usize>)> = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        match text_line_path(line, line_numbers, null_output) {
+            Some(path) if groups.last().is_none_or(|(last, _)| *last != path) => {
+                match groups.iter_mut().find(|(existing, _)| *existing == path) {
+                    Some((_, indices)) => indices.push(i),
+                    None => groups.push((path, vec![i])),
+                }
+            }
+            _ => match groups.last_mut() {
+                Some((_, indices)) => indices.push(i),
+                None => groups.push(("", vec![i])),
+            },
+        }
+    }
+    
+    let mut keep = vec![false; lines.len()];
+    let mut used = 0;
+    let rounds = groups.iter().map(|(_, indices)| indices.len()).max().unwrap_or(0);
+    for round in 0..rounds {
+        for (_, indices) in &groups {
+            if let Some(&i) = indices.get(round) {
+                let cost = estimate_tokens(&lines[i]);
+                if used + cost <= budget {
+                    used += cost;
+                    keep[i] = true;
+                }
+            }
+        }
+    }
+    
+    let truncated = keep.contains(&false);
+    let kept = lines
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(line, keep)| keep.then_some(line))
+        .collect();
+    (kept, used, truncated)
+}
+
+/// Explain the files ripgrep's `--debug` log reports as ignored, e.g.
+/// `rg: DEBUG|ignore::walk|...: ignoring ./build: Ignore(IgnoreMatch(Gitignore(...)))`
+fn skipped_files(stderr: &str) -> Vec<String> {
+    stderr
+        .lines()
+        .filter_map(|line| line.split_once(": ignoring ").map(|(_, rest)| rest))
+        .filter_map(|rest| rest.split_once(": "))
+        .map(|(path, reason)| format!("{} was skipped: {}", path, describe_skip(reason)))
+        .collect()
+}
+
+/// Turn ripgrep's debug representation of a skip reason into prose
+fn describe_skip(reason: &str) -> String {
+    // Quoted field value following `key: "` in the debug representation
+    let field = |key: &str| {
+        let start = reason.find(key)? + key.len();
+        let end = reason[start..].find('"')?;
+        Some(&reason[start..start + end])
+    };
+    
+    if reason.contains("(Hidden)") {
+        "hidden file (names starting with `.` are skipped)".to_string()
+    } else if reason.contains("Types(") {
+        "excluded by the file type filter".to_string()
+    } else if let Some(rule) = field("original: \"") {
+        match field("from: Some(\"") {
+            Some(from) => format!("matches ignore rule `{}` in {}", rule, from),
+            None => format!("matches ignore rule `{}`", rule),
+        }
+    } else {
+        reason.to_string()
+    }
+}
+
+/// Escapes that clients commonly double up through JSON string escaping
+const NORMALIZED_ESCAPES: &[char] = &['d', 'D', 'w', 'W', 's', 'S', 'b', 'B'];
+
+/// Collapse `\\X` to `\X` for the character classes and word boundaries in
+/// `NORMALIZED_ESCAPES`. Only runs of exactly two backslashes are touched, so
+/// an intentionally escaped literal backslash (`\\\\d`) is left alone.
+/// Returns `None` when nothing changed
+fn collapse_double_escapes(pattern: &str) -> Option<String> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut normalized = String::with_capacity(pattern.len());
+    let mut changed = false;
+    let mut i = 0;
+    
+    while i < chars.len() {
+        if chars[i] != '\\' {
+            normalized.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        
+        let run = chars[i..].iter().take_while(|&&c| c == '\\').count();
+        let next = chars.get(i + run);
+        if run == 2 && next.is_some_and(|c| NORMALIZED_ESCAPES.contains(c)) {
+            normalized.push('\\');
+            changed = true;
+        } else {
+            normalized.extend(&chars[i..i + run]);
+        }
+        i += run;
+    }
+    
+    changed.then_some(normalized)
+}
+
+/// Relevance heuristic for one match: every match scores 1, a match that
+/// is a whole word (not flanked by letters, digits or `_`) adds 2, and a
+/// match whose text also appears in the file name adds 1
+fn relevance_score(record: &RgLine, submatch: Option<&RgSubmatch>) -> u32 {
+    let Some(submatch) = submatch else {
+        return 1;
+    };
+    let line = record.lines.as_str();
+    let Some(matched) = line.get(submatch.start..submatch.end) else {
+        return 1;
+    };
+    
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut score = 1;
+    
+    let before = line[..submatch.start].chars().next_back();
+    let after = line[submatch.end..].chars().next();
+    if !before.is_some_and(is_word) && !after.is_some_and(is_word) {
+        score += 2;
+    }
+    
+    let file_name = Path::new(record.path.as_str())
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if !matched.is_empty() && file_name.contains(&matched.to_lowercase()) {
+        score += 1;
+    }
+    
+    score
+}
+
+/// Cut the byte range `start..end` out of `line`, extended by up to `chars`
+/// characters on each side without running past the line boundaries
+fn match_window(line: &str, start: usize, end: usize, chars: usize) -> &str {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let (window_start, window_end) = match_window_bounds(line, start, end, chars, chars);
+    &line[window_start..window_end]
+}
+
+/// Byte range of the match window within a line without its terminator,
+/// keeping up to `before` characters before the match and `after` after it
+fn match_window_bounds(line: &str, start: usize, end: usize, before: usize, after: usize) -> (usize, usize) {
+    let end = end.min(line.len());
+    let start = start.min(end);
+    
+    // Offsets come from ripgrep, but never slice through a multi-byte char
+    if !line.is_char_boundary(start) || !line.is_char_boundary(end) {
+        return (0, line.len());
+    }
+    
+    let window_start = line[..start]
+        .char_indices()
+        .rev()
+        .take(before)
+        .last()
+        .map_or(start, |(i, _)| i);
+    let window_end = line[end..]
+        .char_indices()
+        .nth(after)
+        .map_or(line.len(), |(i, _)| end + i);
+    
+    (window_start, window_end)
+}
+
+/// Like `match_window`, but a cut that would split a word moves inward to
+/// the nearest whitespace or token boundary, and cut ends are marked with
+/// `…`. A side keeps its plain cut when no boundary lies between it and
+/// the match
+fn smart_match_window(line: &str, start: usize, end: usize, chars: usize) -> String {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let (mut window_start, mut window_end) = match_window_bounds(line, start, end, chars, chars);
+    let start = start.clamp(window_start, window_end);
+    let end = end.clamp(start, window_end);
+    
+    if window_start > 0 {
+        if let Some(boundary) = (window_start..=start).find(|&i| is_token_boundary(line, i)) {
+            window_start = boundary;
+        }
+    }
+    if window_end < line.len() {
+        if let Some(boundary) = (end..=window_end).rev().find(|&i| is_token_boundary(line, i)) {
+            window_end = boundary;
+        }
+    }
+    
+    let mut window = String::new();
+    if window_start > 0 {
+        window.push('…');
+        window.push_str(line[window_start..window_end].trim_start());
+    } else {
+        window.push_str(&line[window_start..window_end]);
+    }
+    if window_end < line.len() {
+        window.truncate(window.trim_end().len());
+        window.push('…');
+    }
+    window
+}
+
+/// Whether byte offset `i` separates two tokens: a line end, whitespace on
+/// either side, or a switch between word and non-word characters
+fn is_token_boundary(line: &str, i: usize) -> bool {
+    if !line.is_char_boundary(i) {
+        return false;
+    }
+    
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    match (line[..i].chars().next_back(), line[i..].chars().next()) {
+        (Some(before), Some(after)) => {
+            before.is_whitespace() || after.is_whitespace() || is_word(before) != is_word(after)
+        }
+        _ => true,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use std::fs::File;
+    use std::io::Write;
+    
+    fn setup_test_files() -> TempDir {
+        let temp_dir = TempDir::new().unwrap();
+        
+        // Create a test file
+        let file_path = temp_dir.path().join("test_file.rs");
+        let mut file = File::create(file_path).unwrap();
+        writeln!(file, "fn hello_world() {{").unwrap();
+        writeln!(file, "    println!(\"Hello, world!\");").unwrap();
+        writeln!(file, "}}").unwrap();
+        writeln!(file, "").unwrap();
+        writeln!(file, "fn search_function(query: &str) {{").unwrap();
+        writeln!(file, "    println!(\"Searching for {{}}\", query);").unwrap();
+        writeln!(file, "}}").unwrap();
+        
+        // Create another file with different content
+        let file_path = temp_dir.path().join("test_file.js");
+        let mut file = File::create(file_path).unwrap();
+        writeln!(file, "function helloWorld() {{").unwrap();
+        writeln!(file, "    console.log(\"Hello, world!\");").unwrap();
+        writeln!(file, "}}").unwrap();
+        
+        temp_dir
+    }
+    
+    fn write_file(dir: &Path, name: &str, contents: &str) {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+    
+    /// Options as a client would send them, with every optional field defaulted
+    fn search_options(pattern: &str) -> SearchOptions {
+        serde_json::from_value(serde_json::json!({ "pattern": pattern })).unwrap()
+    }
+    
+    #[tokio::test]
+    async fn test_basic_search() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            fixed_strings: true,
+            ..search_options("hello")
+        };
+        
+        let result = searcher.search(&options).await.unwrap();
+        assert!(result.matches.len() >= 2); // Should find "hello" in both files
+        
+        // Test with file type filter
+        let options = SearchOptions {
+            fixed_strings: true,
+            file_types: vec!["rs".into()],
+            ..search_options("hello")
+        };
+        
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 1); // Should only find in Rust file
+    }
+    
+    #[tokio::test]
+    async fn test_missing_rg_binary() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf())
+            .with_rg_path(PathBuf::from("/nonexistent/bin/rg"));
+        
+        // The error names the binary that could not be run
+        match searcher.search(&search_options("hello")).await {
+            Err(AppError::RipgrepError(message)) => assert!(message.contains("/nonexistent/bin/rg"), "{}", message),
+            other => panic!("expected a ripgrep error, got {:?}", other),
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_path_traversal_prevention() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            path: "../../../etc/passwd".into(), // Attempt path traversal
+            fixed_strings: true,
+            ..search_options("hello")
+        };
+        
+        let result = searcher.search(&options).await;
+        assert!(result.is_err());
+        match result {
+            Err(AppError::PathTraversal(_)) => {}
+            _ => panic!("Expected PathTraversal error"),
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_root_index_resolves_in_that_root() {
+        let first = TempDir::new().unwrap();
+        let second = TempDir::new().unwrap();
+        write_file(first.path(), "a.txt", "needle first\n");
+        let now = SystemTime::now();
+        for (name, age) in [("old.txt", 200), ("ref.txt", 100), ("src/b.txt", 0)] {
+            write_file(second.path(), name, "needle here\n");
+            let file = std::fs::File::options().write(true).open(second.path().join(name)).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+        let searcher = RipgrepSearcher::new(first.path().to_path_buf())
+            .with_extra_roots(vec![second.path().to_path_buf()]);
+        let in_second = |options: SearchOptions| SearchOptions {
+            root_index: Some(1),
+            ..options
+        };
+        
+        // The reference file exists only in the second root
+        let options = in_second(SearchOptions {
+            newer_than: Some("ref.txt".into()),
+            ..search_options("needle")
+        });
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 1);
+        assert!(result.matches[0].contains("b.txt"));
+        
+        // The manifest names the root that was searched
+        let options = in_second(SearchOptions {
+            include_manifest: true,
+            ..search_options("needle")
+        });
+        let manifest = searcher.search(&options).await.unwrap().manifest.unwrap();
+        assert_eq!(Path::new(&manifest.root), std::fs::canonicalize(second.path()).unwrap());
+        
+        // Patch hunks and git-grep lines are relative to it
+        let options = in_second(SearchOptions {
+            replace: Some("pin".into()),
+            patch_hunks: true,
+            paths: vec!["src/b.txt".into()],
+            ..search_options("needle")
+        });
+        let hunks = searcher.search(&options).await.unwrap().patch_hunks.unwrap();
+        assert_eq!(hunks[0].path, "src/b.txt");
+        assert!(hunks[0].diff.starts_with("--- a/src/b.txt\n+++ b/src/b.txt\n"));
+        let options = in_second(SearchOptions {
+            output_format: OutputFormat::GitGrep,
+            paths: vec!["src/b.txt".into()],
+            ..search_options("needle")
+        });
+        assert_eq!(searcher.search(&options).await.unwrap().matches, vec!["src/b.txt:1:needle here"]);
+        
+        // list_files lists the chosen root
+        let options = ListFilesOptions {
+            root_index: Some(1),
+            ..ListFilesOptions::default()
+        };
+        assert_eq!(searcher.list_files(&options).await.unwrap(), vec!["old.txt", "ref.txt", "src/b.txt"]);
+    }
+    
+    #[test]
+    fn test_multiple_roots() {
+        let first = TempDir::new().unwrap();
+        let second = TempDir::new().unwrap();
+        let outside = TempDir::new().unwrap();
+        write_file(first.path(), "a.rs", "fn a() {}\n");
+        write_file(second.path(), "src/b.rs", "fn b() {}\n");
+        write_file(outside.path(), "c.rs", "fn c() {}\n");
+        let searcher = RipgrepSearcher::new(first.path().to_path_buf())
+            .with_extra_roots(vec![second.path().to_path_buf()]);
+        
+        // Paths may resolve under either root
+        assert!(searcher.validate_path_in(first.path(), "a.rs").is_ok());
+        let second_file = second.path().join("src/b.rs");
+        assert!(searcher.validate_path_in(first.path(), second_file.to_str().unwrap()).is_ok());
+        
+        // root_index picks the root relative paths start from
+        let root = searcher.base_root(Some(1)).unwrap();
+        assert_eq!(root, second.path());
+        assert_eq!(searcher.validate_path_in(root, "src/b.rs").unwrap(), second_file);
+        assert!(matches!(searcher.validate_path_in(root, "a.rs"), Err(AppError::InvalidPath(_))));
+        assert!(matches!(searcher.base_root(Some(2)), Err(AppError::ConfigError(_))));
+        
+        // Anything outside every root is still rejected
+        let outside_file = outside.path().join("c.rs");
+        assert!(matches!(
+            searcher.validate_path_in(first.path(), outside_file.to_str().unwrap()),
+            Err(AppError::PathTraversal(_))
+        ));
+        assert!(matches!(searcher.validate_path_in(root, "../"), Err(AppError::PathTraversal(_))));
+    }
+    
+    #[tokio::test]
+    async fn test_max_paths_per_request() {
+        let temp_dir = setup_test_files();
+        
+        let options = SearchOptions {
+            paths: vec!["test_file.rs".into(), "test_file.js".into()],
+            ..search_options("hello")
+        };
+        
+        // Two paths against a limit of one is rejected up front
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf())
+            .with_settings(SearchSettings {
+                max_paths_per_request: 1,
+                ..SearchSettings::default()
+            });
+        match searcher.search(&options).await {
+            Err(AppError::TooManyPaths(2, 1)) => {}
+            other => panic!("Expected TooManyPaths error, got {:?}", other),
+        }
+        
+        // The same request fits within a limit of two
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf())
+            .with_settings(SearchSettings {
+                max_paths_per_request: 2,
+                ..SearchSettings::default()
+            });
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 2);
+        
+        // The limit is checked before file types or the result cache can
+        // run ripgrep, so a missing binary never gets the chance to fail
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf())
+            .with_rg_path(temp_dir.path().join("no-such-rg"))
+            .with_settings(SearchSettings {
+                max_paths_per_request: 1,
+                result_cache_ttl: Duration::from_secs(60),
+                ..SearchSettings::default()
+            });
+        let typed = SearchOptions {
+            file_types: vec!["Rust".into()],
+            ..options.clone()
+        };
+        match searcher.search(&typed).await {
+            Err(AppError::TooManyPaths(2, 1)) => {}
+            other => panic!("Expected TooManyPaths error, got {:?}", other),
+        }
+        match searcher.count(&typed).await {
+            Err(AppError::TooManyPaths(2, 1)) => {}
+            other => panic!("Expected TooManyPaths error, got {:?}", other),
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_explicit_paths() {
+        let temp_dir = setup_test_files();
+        write_file(temp_dir.path(), "other.rs", "fn hello_again() {}\n");
+        write_file(temp_dir.path(), "nested/more.js", "// hello\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // Only the two named files are searched, though others match too
+        let options = SearchOptions {
+            paths: vec!["test_file.rs".into(), "test_file.js".into()],
+            case_sensitive: false,
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 4);
+        let mut files: Vec<String> = result
+            .matches
+            .iter()
+            .map(|m| {
+                let path = Path::new(m.split(':').next().unwrap());
+                relative_components(path, temp_dir.path()).join("/")
+            })
+            .collect();
+        files.dedup();
+        files.sort();
+        assert_eq!(files, vec!["test_file.js", "test_file.rs"]);
+    }
+    
+    #[tokio::test]
+    async fn test_reject_degenerate_patterns() {
+        let temp_dir = setup_test_files();
+        let settings = SearchSettings {
+            reject_degenerate_patterns: true,
+            ..SearchSettings::default()
+        };
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf()).with_settings(settings);
+        
+        for pattern in ["", ".*", "^", "$", "^.*$"] {
+            let result = searcher.search(&search_options(pattern)).await;
+            assert!(matches!(result, Err(AppError::DegeneratePattern(_))), "{:?}", pattern);
+        }
+        
+        // Naming the root itself does not narrow anything
+        let options = SearchOptions {
+            path: ".".into(),
+            ..search_options(".*")
+        };
+        assert!(matches!(searcher.search(&options).await, Err(AppError::DegeneratePattern(_))));
+        
+        // Scoped to one small file, the same pattern is fine
+        let options = SearchOptions {
+            path: "test_file.rs".into(),
+            ..search_options(".*")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(!result.matches.is_empty());
+        assert!(result.warnings.is_empty());
+        
+        // Ordinary patterns are untouched, and without the setting a
+        // degenerate search only warns
+        assert!(searcher.search(&search_options("^fn")).await.is_ok());
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        let result = searcher.search(&search_options(".*")).await.unwrap();
+        assert_eq!(result.warnings.len(), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_session_time_budget() {
+        let temp_dir = setup_test_files();
+        let settings = SearchSettings {
+            session_time_budget: Some(Duration::from_millis(1)),
+            ..SearchSettings::default()
+        };
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf()).with_settings(settings);
+        
+        // Spawning ripgrep takes longer than a millisecond, so the budget
+        // runs out within a few searches and stays exhausted
+        let mut allowed = 0;
+        let rejected = loop {
+            match searcher.search(&search_options("hello")).await {
+                Ok(_) => allowed += 1,
+                Err(e) => break e,
+            }
+            assert!(allowed < 10, "budget never ran out");
+        };
+        assert!(allowed >= 1);
+        assert!(matches!(rejected, AppError::SessionBudgetExhausted(_)));
+        assert!(matches!(
+            searcher.search(&search_options("world")).await,
+            Err(AppError::SessionBudgetExhausted(_))
+        ));
+    }
+    
+    #[tokio::test]
+    async fn test_require_explicit_path() {
+        let temp_dir = setup_test_files();
+        let settings = SearchSettings {
+            require_explicit_path: true,
+            ..SearchSettings::default()
+        };
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf()).with_settings(settings);
+        
+        let result = searcher.search(&search_options("hello")).await;
+        assert!(matches!(result, Err(AppError::PathRequired)));
+        
+        // Naming a path, even the root itself, is allowed
+        let options = SearchOptions {
+            path: ".".into(),
+            ..search_options("hello")
+        };
+        assert!(searcher.search(&options).await.is_ok());
+        
+        // Without the setting an empty path searches the whole root
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        assert!(searcher.search(&search_options("hello")).await.is_ok());
+    }
+    
+    #[tokio::test]
+    async fn test_within_results_of() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // "world" appears in both fixtures
+        let broad = searcher.search(&search_options("world")).await.unwrap();
+        assert!(broad.matches.iter().any(|m| m.contains("test_file.js")));
+        
+        // "fn " only appears in the Rust fixture
+        let first = searcher.search(&search_options("fn ")).await.unwrap();
+        
+        let options = SearchOptions {
+            within_results_of: Some(first.search_id.clone()),
+            ..search_options("world")
+        };
+        let refined = searcher.search(&options).await.unwrap();
+        assert!(!refined.matches.is_empty());
+        assert!(refined.matches.iter().all(|m| m.contains("test_file.rs")));
+        
+        // Unknown IDs are reported rather than silently widening the search
+        let options = SearchOptions {
+            within_results_of: Some("search-unknown".into()),
+            ..search_options("world")
+        };
+        match searcher.search(&options).await {
+            Err(AppError::UnknownSearchId(_)) => {}
+            other => panic!("Expected UnknownSearchId error, got {:?}", other),
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_report_total() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "repeat.txt", "a a a\nb\na\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let result = searcher.search(&search_options("a")).await.unwrap();
+        assert_eq!(result.stats.matched_lines, 2);
+        assert_eq!(result.stats.total_matches, None);
+        
+        let options = SearchOptions {
+            report_total: true,
+            ..search_options("a")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 2);
+        assert_eq!(result.stats.total_matches, Some(4));
+    }
+    
+    #[tokio::test]
+    async fn test_profiles() {
+        let temp_dir = setup_test_files();
+        write_file(temp_dir.path(), "nested/deep/hello.rs", "fn hello() {}\n");
+        
+        let profiles = HashMap::from([
+            ("code".to_string(), SearchProfile {
+                file_types: vec!["js".into()],
+                max_depth: None,
+                exclude_globs: Vec::new(),
+            }),
+            ("shallow".to_string(), SearchProfile {
+                file_types: vec!["rust".into()],
+                max_depth: Some(1),
+                exclude_globs: Vec::new(),
+            }),
+            ("no_nested".to_string(), SearchProfile {
+                file_types: Vec::new(),
+                max_depth: None,
+                exclude_globs: vec!["nested/**".into()],
+            }),
+        ]);
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf())
+            .with_settings(SearchSettings {
+                profiles,
+                ..SearchSettings::default()
+            });
+        
+        let options = SearchOptions {
+            profile: Some("code".into()),
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(!result.matches.is_empty());
+        assert!(result.matches.iter().all(|m| m.contains("test_file.js")));
+        
+        // The depth limit keeps the nested Rust file out
+        let options = SearchOptions {
+            profile: Some("shallow".into()),
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(!result.matches.is_empty());
+        assert!(result.matches.iter().all(|m| m.contains("test_file.rs")));
+        
+        // Explicit request options win over the profile
+        let options = SearchOptions {
+            profile: Some("code".into()),
+            file_types: vec!["rust".into()],
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(result.matches.iter().any(|m| m.contains("deep")));
+        
+        // Profile excludes add to the request's own
+        let options = SearchOptions {
+            profile: Some("no_nested".into()),
+            exclude_globs: vec!["*.js".into()],
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(!result.matches.is_empty());
+        assert!(result.matches.iter().all(|m| m.contains("test_file.rs")));
+        
+        let options = SearchOptions {
+            profile: Some("missing".into()),
+            ..search_options("hello")
+        };
+        assert!(matches!(searcher.search(&options).await, Err(AppError::ConfigError(_))));
+    }
+    
+    #[tokio::test]
+    async fn test_summary_per_file() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            summary_per_file: true,
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(result.matches.is_empty());
+        
+        let mut summaries = result.file_summaries.unwrap();
+        summaries.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(summaries.len(), 2);
+        
+        assert!(summaries[0].path.ends_with("test_file.js"));
+        assert_eq!(summaries[0].match_count, 2);
+        assert_eq!(summaries[0].first_line, Some(1));
+        assert_eq!(summaries[0].first_match_text, "function helloWorld() {");
+        
+        assert!(summaries[1].path.ends_with("test_file.rs"));
+        assert_eq!(summaries[1].match_count, 2);
+        assert_eq!(summaries[1].first_line, Some(1));
+        assert_eq!(summaries[1].first_match_text, "fn hello_world() {");
+    }
+    
+    #[tokio::test]
+    async fn test_byte_ranges() {
+        let temp_dir = setup_test_files();
+        write_file(temp_dir.path(), "twice.txt", "one\nfoo foo\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            file_types: vec!["rust".into()],
+            case_sensitive: true,
+            byte_ranges: true,
+            ..search_options("println")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let structured = result.structured_matches.unwrap();
+        assert_eq!(structured.len(), 2);
+        
+        // The range addresses the exact match within the file on disk
+        let contents = std::fs::read(temp_dir.path().join("test_file.rs")).unwrap();
+        for m in &structured {
+            let start = m.start_byte.unwrap() as usize;
+            let end = m.end_byte.unwrap() as usize;
+            assert_eq!(&contents[start..end], b"println");
+        }
+        
+        // Two matches on one line get distinct ranges
+        let options = SearchOptions {
+            path: "twice.txt".into(),
+            byte_ranges: true,
+            ..search_options("foo")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let ranges: Vec<_> = result
+            .structured_matches
+            .unwrap()
+            .iter()
+            .map(|m| (m.start_byte.unwrap(), m.end_byte.unwrap()))
+            .collect();
+        assert_eq!(ranges, vec![(4, 7), (8, 11)]);
+    }
+    
+    #[tokio::test]
+    async fn test_byte_offset() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // Line 5 follows 19 + 31 + 2 + 1 bytes of the first four lines
+        let options = SearchOptions {
+            path: "test_file.rs".into(),
+            byte_offset: true,
+            output_format: OutputFormat::Json,
+            ..search_options("search_function")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let structured = result.structured_matches.unwrap();
+        assert_eq!(structured.len(), 1);
+        assert_eq!(structured[0].byte_offset, Some(53));
+        let contents = std::fs::read_to_string(temp_dir.path().join("test_file.rs")).unwrap();
+        assert!(contents[53..].starts_with("fn search_function"));
+        
+        // With only_matching the offset is the match's own
+        let options = SearchOptions {
+            only_matching: true,
+            ..options
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.structured_matches.unwrap()[0].byte_offset, Some(56));
+        
+        // Plain text output carries ripgrep's offset after the line number
+        let options = SearchOptions {
+            path: "test_file.rs".into(),
+            byte_offset: true,
+            ..search_options("search_function")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 1);
+        assert!(result.matches[0].contains(":5:53:fn search_function"));
+    }
+    
+    #[tokio::test]
+    async fn test_rank_relevance() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "words.txt", "foobar comes first\nthen foo alone\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            rank_relevance: true,
+            ..search_options("foo")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let ranked = result.structured_matches.unwrap();
+        assert_eq!(ranked.len(), 2);
+        
+        // The whole-word match outranks the earlier substring match
+        assert_eq!(ranked[0].line_number, Some(2));
+        assert_eq!(ranked[1].line_number, Some(1));
+        assert!(ranked[0].score > ranked[1].score);
+        
+        // Ranking alone does not add byte ranges
+        assert!(ranked[0].start_byte.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_newer_and_older_than() {
+        let temp_dir = TempDir::new().unwrap();
+        let now = SystemTime::now();
+        for (name, age) in [("old.txt", 300), ("marker_a", 200), ("mid.txt", 100), ("marker_b", 50), ("new.txt", 0)] {
+            write_file(temp_dir.path(), name, "needle\n");
+            let file = std::fs::File::options().write(true).open(temp_dir.path().join(name)).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        let files_for = |result: SearchResult| {
+            let mut files = result.matches
+                .iter()
+                .map(|line| Path::new(line.split(':').next().unwrap()).file_name().unwrap().to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        };
+        
+        let options = SearchOptions {
+            newer_than: Some("marker_a".into()),
+            ..search_options("needle")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        
+        // Ripgrep's stats counted the dropped files too, so the matched files
+        // are recounted from what was kept and the searched ones are unknown
+        assert_eq!(result.stats.files_searched, None);
+        assert_eq!(result.stats.files_with_matches, 3);
+        assert!(result.warnings.iter().any(|w| w.contains("files_searched is unknown")));
+        assert_eq!(files_for(result), vec!["marker_b", "mid.txt", "new.txt"]);
+        let ratio = SearchOptions {
+            match_ratio: true,
+            ..options
+        };
+        assert_eq!(searcher.search(&ratio).await.unwrap().stats.match_ratio, None);
+        
+        let options = SearchOptions {
+            older_than: Some("marker_a".into()),
+            ..search_options("needle")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(files_for(result), vec!["old.txt"]);
+        
+        let options = SearchOptions {
+            newer_than: Some("marker_a".into()),
+            older_than: Some("marker_b".into()),
+            report_total: true,
+            ..search_options("needle")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.stats.total_matches, Some(1));
+        assert_eq!(files_for(result), vec!["mid.txt"]);
+        
+        // Reference files must stay inside the root
+        let options = SearchOptions {
+            newer_than: Some("../outside".into()),
+            ..search_options("needle")
+        };
+        assert!(searcher.search(&options).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_first_result_fast() {
+        let temp_dir = setup_test_files();
+        for i in 0..20 {
+            write_file(temp_dir.path(), &format!("many/file_{}.txt", i), "needle\nneedle again\n");
+        }
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            first_result_fast: true,
+            ..search_options("needle")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        
+        // Only one file's matches come back, and ripgrep was stopped early
+        let files: HashSet<_> = result.matches
+            .iter()
+            .map(|line| line.split(':').next().unwrap().to_string())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(result.matches.len(), 2);
+        assert!(result.stats.partial);
+        
+        // No match at all is a normal, complete result
+        let options = SearchOptions {
+            first_result_fast: true,
+            ..search_options("absent_pattern_xyz")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(result.matches.is_empty());
+        assert!(!result.stats.partial);
+    }
+    
+    #[tokio::test]
+    async fn test_event_stream_options_keep_text_shape() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        let options = SearchOptions {
+            context_lines: Some(1),
+            sort: Some(SortMode::Path),
+            ..search_options("(?i)hello")
+        };
+        let plain = searcher.search(&options).await.unwrap().matches;
+        assert!(plain.iter().any(|line| line == "--"));
+        
+        // Options that read ripgrep's event stream return the same lines,
+        // absolute paths and context included
+        let streamed = [
+            SearchOptions {
+                hard_limit: Some(100),
+                ..options.clone()
+            },
+            SearchOptions {
+                type_breakdown: true,
+                ..options.clone()
+            },
+        ];
+        for streamed in streamed {
+            assert_eq!(searcher.search(&streamed).await.unwrap().matches, plain);
+        }
+        let options = SearchOptions {
+            path: "test_file.rs".into(),
+            ..options
+        };
+        let plain = searcher.search(&options).await.unwrap().matches;
+        let fast = SearchOptions {
+            first_result_fast: true,
+            ..options
+        };
+        assert_eq!(searcher.search(&fast).await.unwrap().matches, plain);
+    }
+    
+    #[tokio::test]
+    async fn test_streamed_search_errors() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // Ripgrep's own message comes through, not just its exit status
+        for options in [
+            SearchOptions {
+                hard_limit: Some(10),
+                ..search_options("(unclosed")
+            },
+            SearchOptions {
+                max_results: Some(10),
+                ..search_options("(unclosed")
+            },
+        ] {
+            match searcher.search(&options).await {
+                Err(AppError::RipgrepError(message)) => assert!(message.contains("regex parse error"), "{}", message),
+                other => panic!("Expected RipgrepError, got {:?}", other),
+            }
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_hard_limit_reconcile_counts() {
+        let temp_dir = TempDir::new().unwrap();
+        for i in 0..20 {
+            write_file(temp_dir.path(), &format!("file_{}.txt", i), "needle\nneedle needle\n");
+        }
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // Stopping early caps the matches but leaves the total unknown
+        let options = SearchOptions {
+            hard_limit: Some(5),
+            ..search_options("needle")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 5);
+        assert!(result.stats.partial);
+        assert_eq!(result.stats.total_matches, None);
+        
+        // The reconciling pass counts every match, not just the returned ones
+        let options = SearchOptions {
+            reconcile_counts: true,
+            ..options
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 5);
+        assert!(result.stats.partial);
+        assert_eq!(result.stats.total_matches, Some(60));
+        
+        // A limit the search never reaches needs no reconciling
+        let options = SearchOptions {
+            hard_limit: Some(100),
+            ..options
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 40);
+        assert!(!result.stats.partial);
+        assert_eq!(result.stats.total_matches, None);
+    }
+    
+    #[tokio::test]
+    async fn test_normalize_pattern() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "words.txt", "a word here\nswordfish\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            normalize_pattern: true,
+            ..search_options("\\\\bword\\\\b")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        
+        // The collapsed pattern acts as a word boundary, skipping "swordfish"
+        assert_eq!(result.matches.len(), 1);
+        assert!(result.matches[0].contains("a word here"));
+        assert_eq!(result.warnings.len(), 1);
+        assert!(result.warnings[0].contains("\\bword\\b"));
+    }
+    
+    #[test]
+    fn test_collapse_double_escapes() {
+        // Conservative: escaped literal backslashes and unknown escapes stay
+        assert_eq!(collapse_double_escapes("\\\\bword\\\\b").as_deref(), Some("\\bword\\b"));
+        assert_eq!(collapse_double_escapes("\\\\\\\\d"), None);
+        assert_eq!(collapse_double_escapes("C:\\\\Users"), None);
+        assert_eq!(collapse_double_escapes("\\d+"), None);
+    }
+    
+    #[tokio::test]
+    async fn test_highlight_matches() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "calls.rs", "let total = compute(value) + compute(other);\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            highlight_matches: true,
+            ..search_options(r"compute\(\w+\)")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let structured = result.structured_matches.unwrap();
+        assert_eq!(structured.len(), 2);
+        
+        // Each span addresses its own match within the shared line
+        let texts: Vec<_> = structured.iter().map(|m| m.matched_text.clone().unwrap()).collect();
+        assert_eq!(texts, vec!["compute(value)", "compute(other)"]);
+        for m in &structured {
+            let (start, end) = (m.match_start.unwrap(), m.match_end.unwrap());
+            assert_eq!(&m.full_line[start..end], m.matched_text.as_deref().unwrap());
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_include_last_commit() {
+        let temp_dir = setup_test_files();
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .arg("-C")
+                .arg(temp_dir.path())
+                .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        let head = || {
+            let output = std::process::Command::new("git")
+                .arg("-C")
+                .arg(temp_dir.path())
+                .args(["rev-parse", "HEAD"])
+                .output()
+                .unwrap();
+            String::from_utf8(output.stdout).unwrap().trim().to_string()
+        };
+        git(&["init", "-q"]);
+        git(&["add", "test_file.rs"]);
+        git(&["commit", "-q", "-m", "Add test file"]);
+        let first = head();
+        write_file(temp_dir.path(), "docs/notes.txt", "hello again\n");
+        git(&["add", "docs"]);
+        git(&["commit", "-q", "-m", "Add notes"]);
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            include_last_commit: true,
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        
+        // Committed files get their own latest commit from the one pass; the
+        // untracked file is left out
+        let commits = result.last_commits.unwrap();
+        assert_eq!(commits.len(), 2);
+        let commit_of = |name: &str| &commits.iter().find(|c| c.path.ends_with(name)).unwrap().commit;
+        assert_eq!(commit_of("test_file.rs"), &first);
+        assert_eq!(commit_of("notes.txt"), &head());
+        assert!(commits.iter().all(|c| !c.date.is_empty()));
+    }
+    
+    #[tokio::test]
+    async fn test_diagnose_ignored_file() {
+        let temp_dir = setup_test_files();
+        std::fs::create_dir(temp_dir.path().join(".git")).unwrap();
+        write_file(temp_dir.path(), ".gitignore", "secret.txt\n");
+        write_file(temp_dir.path(), "secret.txt", "hello from an ignored file\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            diagnose: true,
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(result.matches.iter().all(|line| !line.contains("secret.txt")));
+        
+        // The diagnostics name the ignored file and the rule that hid it
+        let diagnostics = result.diagnostics.unwrap();
+        let explanation = diagnostics
+            .iter()
+            .find(|d| d.contains("secret.txt"))
+            .expect("ignored file should be diagnosed");
+        assert!(explanation.contains("ignore rule `secret.txt`"), "{}", explanation);
+        assert!(explanation.contains(".gitignore"), "{}", explanation);
+    }
+    
+    #[tokio::test]
+    async fn test_token_budget() {
+        let temp_dir = TempDir::new().unwrap();
+        let body = "needle with a fair amount of surrounding text on the line\n".repeat(10);
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            write_file(temp_dir.path(), name, &body);
+        }
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            token_budget: Some(80),
+            ..search_options("needle")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        
+        // The kept lines fit the budget and cover every file
+        let estimate = result.stats.token_estimate.unwrap();
+        assert!(estimate <= 80);
+        assert!(result.stats.truncated);
+        assert!(result.matches.len() < 30);
+        let files: HashSet<_> = result.matches
+            .iter()
+            .filter_map(|line| text_line_path(line, true, false))
+            .collect();
+        assert_eq!(files.len(), 3);
+        
+        // A generous budget keeps everything
+        let options = SearchOptions {
+            token_budget: Some(100_000),
+            ..search_options("needle")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 30);
+        assert!(!result.stats.truncated);
+    }
+    
+    #[tokio::test]
+    async fn test_file_type_normalization() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // Mixed-case names are lowercased before reaching ripgrep
+        let options = SearchOptions {
+            file_types: vec!["Rust".into(), "JS".into()],
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 4);
+        
+        // A misspelled type is rejected with a suggestion
+        let options = SearchOptions {
+            file_types: vec!["rsut".into()],
+            ..search_options("hello")
+        };
+        match searcher.search(&options).await {
+            Err(AppError::ConfigError(message)) => {
+                assert!(message.contains("rsut"), "{}", message);
+                assert!(message.contains("did you mean") && message.contains("rust"), "{}", message);
+            }
+            other => panic!("expected an unknown file type error, got {:?}", other),
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_skip_invalid_paths() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            paths: vec!["test_file.rs".into(), "../../../etc/passwd".into()],
+            skip_invalid_paths: true,
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        
+        // The valid path is searched and the traversal attempt is reported
+        assert!(!result.matches.is_empty());
+        assert!(result.matches.iter().all(|line| line.contains("test_file.rs")));
+        assert_eq!(result.invalid_paths, vec!["../../../etc/passwd".to_string()]);
+        
+        // Without the flag the whole request still fails
+        let options = SearchOptions {
+            skip_invalid_paths: false,
+            ..options
+        };
+        assert!(searcher.search(&options).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_relative_to_line() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "lines.txt", "needle\n\n\n\ncursor\n\nneedle\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            relative_to_line: Some(5),
+            ..search_options("needle")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let deltas: Vec<_> = result
+            .structured_matches
+            .unwrap()
+            .iter()
+            .map(|m| m.delta_lines.unwrap())
+            .collect();
+        
+        // Line 1 is four lines above the reference, line 7 two below
+        assert_eq!(deltas, vec![-4, 2]);
+    }
+    
+    #[tokio::test]
+    async fn test_matched_lines_with_context() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // Each file returns its two matches plus the closing-brace context line
+        let options = SearchOptions {
+            context_lines: Some(1),
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(result.matches.len() >= 6, "{:?}", result.matches);
+        assert_eq!(result.stats.matched_lines, 4);
+    }
+    
+    #[tokio::test]
+    async fn test_merge_context() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "near.txt", "a\nneedle one\nb\nc\nneedle two\nd\ne\nf\ng\nneedle three\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            context_lines: Some(1),
+            merge_context: true,
+            ..search_options("needle")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let blocks = result.context_blocks.unwrap();
+        
+        // Lines 1-3 and 4-6 touch, so the first two matches share one block
+        assert_eq!(blocks.len(), 2);
+        assert_eq!((blocks[0].start_line, blocks[0].end_line), (1, 6));
+        assert_eq!(blocks[0].lines, vec!["a", "needle one", "b", "c", "needle two", "d"]);
+        assert_eq!((blocks[1].start_line, blocks[1].end_line), (9, 10));
+        assert!(result.matches.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_merge_context_per_file() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "a.txt", "a1\nneedle a\na3\n");
+        write_file(temp_dir.path(), "b.txt", "b1\nneedle b\nb3\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            context_lines: Some(2),
+            merge_context: true,
+            ..search_options("needle")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let mut blocks = result.context_blocks.unwrap();
+        blocks.sort_by(|x, y| x.path.cmp(&y.path));
+        
+        // The context reaches each file's edges but never into the other file
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks[0].path.ends_with("a.txt"));
+        assert_eq!(blocks[0].lines, vec!["a1", "needle a", "a3"]);
+        assert!(blocks[1].path.ends_with("b.txt"));
+        assert_eq!(blocks[1].lines, vec!["b1", "needle b", "b3"]);
+    }
+    
+    #[tokio::test]
+    async fn test_unknown_file_types_rejected() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // Every unknown type is reported in one error, with suggestions
+        let options = SearchOptions {
+            file_types: vec!["rustt".into(), "js".into(), "zzqqxx".into()],
+            ..search_options("hello")
+        };
+        match searcher.search(&options).await {
+            Err(AppError::ConfigError(message)) => {
+                assert!(message.contains("rustt (did you mean: rust"), "{}", message);
+                assert!(message.contains("zzqqxx"), "{}", message);
+                assert!(!message.contains("js"), "{}", message);
+            }
+            other => panic!("expected an unknown file type error, got {:?}", other),
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_only_matching() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        let expected = ["hello_world(", "search_function(", "helloWorld(", "log("];
+        
+        // Each entry ends in the matched call, not the rest of its line
+        let options = SearchOptions {
+            only_matching: true,
+            ..search_options(r"\w+\(")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let mut fragments: Vec<&str> = result.matches.iter().map(|m| m.rsplit(':').next().unwrap()).collect();
+        fragments.sort_unstable();
+        let mut sorted = expected;
+        sorted.sort_unstable();
+        assert_eq!(fragments, sorted);
+        
+        // Structured matches carry the same fragments next to the full line
+        let options = SearchOptions {
+            include_indent: true,
+            ..options
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let structured = result.structured_matches.unwrap();
+        assert_eq!(structured.len(), expected.len());
+        for m in &structured {
+            let text = m.matched_text.as_deref().unwrap();
+            assert!(expected.contains(&text), "{:?}", text);
+            assert!(m.full_line.contains(text) && m.full_line != text);
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_histogram() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "app.log", "INFO start\nWARN slow\nERROR boom\nINFO done\nERROR again\nINFO bye\n");
+        write_file(temp_dir.path(), "other.log", "INFO more\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            only_matching: true,
+            histogram: true,
+            case_sensitive: true,
+            ..search_options("ERROR|WARN|INFO")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        
+        let entry = |value: &str, count| HistogramEntry { value: value.to_string(), count };
+        assert_eq!(result.histogram.unwrap(), vec![entry("INFO", 4), entry("ERROR", 2), entry("WARN", 1)]);
+        assert!(result.matches.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_color_output() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            color: true,
+            case_sensitive: true,
+            ..search_options("println")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        
+        // The match is wrapped in color codes and the reset that follows it
+        assert_eq!(result.matches.len(), 2);
+        for line in &result.matches {
+            assert!(line.contains("\x1b["), "{:?}", line);
+            assert!(line.contains("println\x1b[0m"), "{:?}", line);
+            assert!(strip_ansi(line).contains("println!"));
+        }
+        
+        // Files are still tracked for refinement through the color codes
+        assert_eq!(searcher.result_files.get(&result.search_id).unwrap().len(), 1);
+        
+        // Uncolored output stays plain
+        let options = SearchOptions { color: false, ..options };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(result.matches.iter().all(|line| !line.contains('\x1b')));
+        
+        // Structured modes cannot carry color
+        let options = SearchOptions {
+            color: true,
+            byte_ranges: true,
+            ..search_options("println")
+        };
+        assert!(matches!(searcher.search(&options).await, Err(AppError::ConfigError(_))));
+    }
+    
+    #[tokio::test]
+    async fn test_path_components() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "subdir/file.rs", "needle\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            path_components: true,
+            ..search_options("needle")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let files = result.matched_files.unwrap();
+        
+        assert_eq!(files.len(), 1);
+        assert!(files[0].path.ends_with("file.rs"));
+        assert_eq!(files[0].components, vec!["subdir", "file.rs"]);
+    }
+    
+    #[tokio::test]
+    async fn test_result_cache_invalidation() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "notes.txt", "needle one\n");
+        let settings = SearchSettings {
+            result_cache_ttl: Duration::from_secs(60),
+            ..SearchSettings::default()
+        };
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf()).with_settings(settings);
+        
+        let first = searcher.search(&search_options("needle")).await.unwrap();
+        assert!(!first.stats.cached);
+        
+        // An identical query over unchanged files is served from the cache
+        let second = searcher.search(&search_options("needle")).await.unwrap();
+        assert!(second.stats.cached);
+        assert_eq!(second.matches, first.matches);
+        assert_ne!(second.search_id, first.search_id);
+        
+        // Editing a file changes the signature, so the next query re-runs
+        write_file(temp_dir.path(), "notes.txt", "needle one\nneedle two\n");
+        let file = std::fs::File::options().write(true).open(temp_dir.path().join("notes.txt")).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(5)).unwrap();
+        
+        let third = searcher.search(&search_options("needle")).await.unwrap();
+        assert!(!third.stats.cached);
+        assert_eq!(third.matches.len(), 2);
+        
+        // A hidden file is part of the signature only when the search reads it
+        let hidden = SearchOptions {
+            search_hidden: true,
+            ..search_options("needle")
+        };
+        write_file(temp_dir.path(), ".hidden.txt", "needle three\n");
+        let fourth = searcher.search(&hidden).await.unwrap();
+        assert!(!fourth.stats.cached);
+        assert_eq!(fourth.matches.len(), 3);
+        
+        write_file(temp_dir.path(), ".hidden.txt", "needle three\nneedle four\n");
+        let file = std::fs::File::options().write(true).open(temp_dir.path().join(".hidden.txt")).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+        
+        let fifth = searcher.search(&hidden).await.unwrap();
+        assert!(!fifth.stats.cached);
+        assert_eq!(fifth.matches.len(), 4);
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_permission_denied() {
+        use std::os::unix::fs::PermissionsExt;
+        
+        let temp_dir = setup_test_files();
+        let locked = temp_dir.path().join("locked");
+        std::fs::create_dir(&locked).unwrap();
+        write_file(&locked, "secret.rs", "hello\n");
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+        
+        // Permission bits do not restrict root, so there is nothing to test
+        if std::fs::read_dir(&locked).is_ok() {
+            std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+            return;
+        }
+        
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        let options = SearchOptions {
+            paths: vec!["locked".into()],
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await;
+        
+        // A locked directory found while walking is reported the same way
+        // when the output is streamed
+        let streamed = SearchOptions {
+            hard_limit: Some(100),
+            ..search_options("hello")
+        };
+        let streamed = searcher.search(&streamed).await;
+        
+        // Restore access so the temp dir can be removed
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+        match result {
+            Err(AppError::PermissionDenied(path)) => assert_eq!(path, "locked"),
+            other => panic!("Expected PermissionDenied error, got {:?}", other),
+        }
+        assert!(matches!(streamed, Err(AppError::PermissionDenied(_))), "{:?}", streamed);
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_validate_fifo_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let status = std::process::Command::new("mkfifo")
+            .arg(temp_dir.path().join("pipe.fifo"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        
+        // Validation must not open a FIFO that has no writer
+        let root = temp_dir.path().to_path_buf();
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let searcher = RipgrepSearcher::new(root.clone());
+            tx.send(searcher.validate_path_in(&root, "pipe.fifo").is_ok()).unwrap();
+        });
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(true));
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_null_output() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "a:1:b.rs", "hello\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // The path is tracked for refinement only when ripgrep NUL-terminates it
+        let options = SearchOptions {
+            null_output: true,
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let expected = temp_dir.path().join("a:1:b.rs");
+        assert_eq!(result.matches, vec![format!("{}\0{}:hello", expected.display(), 1)]);
+        let refined = SearchOptions {
+            within_results_of: Some(result.search_id),
+            ..search_options("hello")
+        };
+        assert_eq!(searcher.search(&refined).await.unwrap().matches.len(), 1);
+        
+        // Without it the name is cut at the first `:1:`
+        let result = searcher.search(&search_options("hello")).await.unwrap();
+        let refined = SearchOptions {
+            within_results_of: Some(result.search_id),
+            ..search_options("hello")
+        };
+        assert!(searcher.search(&refined).await.unwrap().matches.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_compiled_search() {
+        let compiled = CompiledSearch::compile(search_options("hello"), Path::new("rg")).await.unwrap();
+        
+        // One compiled search, two roots with different contents
+        let first = setup_test_files();
+        let second = TempDir::new().unwrap();
+        write_file(second.path(), "only.txt", "hello there\n");
+        
+        let result = RipgrepSearcher::new(first.path().to_path_buf()).run_compiled(&compiled).await.unwrap();
+        assert_eq!(result.matches.len(), 4);
+        
+        let result = RipgrepSearcher::new(second.path().to_path_buf()).run_compiled(&compiled).await.unwrap();
+        assert_eq!(result.matches.len(), 1);
+        assert!(result.matches[0].contains("only.txt"));
+        
+        // Each run resolves against its searcher's settings
+        write_file(second.path(), "skipped.txt", "hello again\n");
+        write_file(second.path(), "more.txt", "hello\nhello\n");
+        let settings = SearchSettings {
+            max_results: Some(2),
+            exclude_globs: vec!["skipped.txt".into()],
+            ..SearchSettings::default()
+        };
+        let searcher = RipgrepSearcher::new(second.path().to_path_buf()).with_settings(settings);
+        let result = searcher.run_compiled(&compiled).await.unwrap();
+        assert_eq!(result.matches.len(), 2);
+        assert!(result.stats.truncated);
+        assert!(result.matches.iter().all(|m| !m.contains("skipped.txt")));
+        
+        // A definitions search runs the definition pattern, not the name
+        write_file(first.path(), "main.rs", "fn main() {\n    hello_world();\n}\n");
+        let options = SearchOptions {
+            definitions_only: true,
+            ..search_options("hello_world")
+        };
+        let compiled = CompiledSearch::compile(options, Path::new("rg")).await.unwrap();
+        let result = RipgrepSearcher::new(first.path().to_path_buf()).run_compiled(&compiled).await.unwrap();
+        assert_eq!(result.matches.len(), 1);
+        assert!(result.matches[0].contains("test_file.rs:1:fn hello_world() {"));
+        
+        // Invalid patterns and multi-pass options fail at compile time
+        assert!(CompiledSearch::compile(search_options("(unclosed"), Path::new("rg")).await.is_err());
+        let unsupported = [
+            SearchOptions { within_results_of: Some("search-1".into()), ..search_options("hello") },
+            SearchOptions { only_in_files_matching: Some("fn".into()), ..search_options("hello") },
+            SearchOptions { newer_than: Some("test_file.rs".into()), ..search_options("hello") },
+            SearchOptions { older_than: Some("test_file.rs".into()), ..search_options("hello") },
+            SearchOptions { first_result_fast: true, ..search_options("hello") },
+            SearchOptions { hard_limit: Some(1), ..search_options("hello") },
+            SearchOptions { report_total: true, ..search_options("hello") },
+            SearchOptions { include_last_commit: true, ..search_options("hello") },
+            SearchOptions { skip_invalid_paths: true, ..search_options("hello") },
+            SearchOptions { diagnose: true, ..search_options("hello") },
+            SearchOptions { search_upward: true, ..search_options("hello") },
+            SearchOptions { pattern_fallbacks: vec!["hi".into()], ..search_options("hello") },
+            SearchOptions { smart_path_pattern: true, ..search_options("hello") },
+            SearchOptions { include_manifest: true, ..search_options("hello") },
+            SearchOptions { resource_report: true, ..search_options("hello") },
+        ];
+        for options in unsupported {
+            let compiled = CompiledSearch::compile(options.clone(), Path::new("rg")).await;
+            assert!(matches!(compiled, Err(AppError::ConfigError(_))), "{:?}", options);
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_type_breakdown() {
+        let temp_dir = setup_test_files();
+        write_file(temp_dir.path(), "more.rs", "hello hello\n");
+        write_file(temp_dir.path(), "Makefile", "hello:\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            type_breakdown: true,
+            case_sensitive: true,
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(!result.matches.is_empty());
+        
+        let count = |file_type: &str, count| TypeCount { file_type: file_type.to_string(), count };
+        assert_eq!(result.type_breakdown.unwrap(), vec![count("rust", 3), count("js", 1), count("make", 1)]);
+    }
+    
+    #[tokio::test]
+    async fn test_globs() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // Excluding *.js leaves only the Rust file
+        let options = SearchOptions {
+            exclude_globs: vec!["*.js".into()],
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 2);
+        assert!(result.matches.iter().all(|m| m.contains("test_file.rs")));
+        
+        // An include glob keeps only what it matches
+        let options = SearchOptions {
+            globs: vec!["*.js".into()],
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 2);
+        assert!(result.matches.iter().all(|m| m.contains("test_file.js")));
+        
+        // Empty lists add no flags
+        let command = RipgrepSearcher::search_command(Path::new("rg"), &search_options("hello"), &[]);
+        assert!(!command.as_std().get_args().any(|arg| arg == "-g"));
+    }
+    
+    #[tokio::test]
+    async fn test_raw_json() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            raw_json: true,
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        
+        // Every entry is one of ripgrep's own events, matches among them
+        let events: Vec<serde_json::Value> = result.matches
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert!(events.iter().all(|event| event["type"].is_string()));
+        let matches = events.iter().filter(|event| event["type"] == "match").count();
+        assert_eq!(matches, 4);
+        assert_eq!(result.stats.matched_lines, 4);
+        assert!(events.iter().any(|event| event["type"] == "summary"));
+    }
+    
+    #[tokio::test]
+    async fn test_word_boundary() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // `Hello, world!` has the whole word; `hello_world` and `helloWorld` do not
+        let options = SearchOptions {
+            word_boundary: true,
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 2);
+        assert!(result.matches.iter().all(|m| m.contains("Hello, world!")));
+        
+        let options = SearchOptions {
+            word_boundary: true,
+            ..search_options("ell")
+        };
+        assert!(searcher.search(&options).await.unwrap().matches.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_smart_path_pattern() {
+        let temp_dir = setup_test_files();
+        write_file(temp_dir.path(), "src/lib.rs", "pub fn lib() {}\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // The path is returned as the result rather than searched for
+        let options = SearchOptions {
+            smart_path_pattern: true,
+            ..search_options("src/lib.rs")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches, vec![temp_dir.path().join("src/lib.rs").to_string_lossy().into_owned()]);
+        assert_eq!(result.warnings.len(), 1);
+        
+        // The file can be refined like any other result
+        let refined = SearchOptions {
+            within_results_of: Some(result.search_id),
+            ..search_options("pub fn")
+        };
+        assert_eq!(searcher.search(&refined).await.unwrap().matches.len(), 1);
+        
+        // Missing files, regexes and the option left off keep the content search
+        for pattern in ["src/missing.rs", "lib.*rs"] {
+            let options = SearchOptions {
+                smart_path_pattern: true,
+                ..search_options(pattern)
+            };
+            assert!(searcher.search(&options).await.unwrap().warnings.is_empty());
+        }
+        assert!(searcher.search(&search_options("src/lib.rs")).await.unwrap().matches.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_multiline() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "sig.rs", "fn connect(\n    host: &str,\n) {}\nfn other() {}\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // Each line of the match is reported
+        let options = SearchOptions {
+            multiline: true,
+            ..search_options(r"connect\(\n\s+host")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 2);
+        assert!(result.matches[1].ends_with(":2:    host: &str,"));
+        
+        // Without it ripgrep cannot match across the line break
+        let options = SearchOptions {
+            multiline: false,
+            ..options
+        };
+        if let Ok(result) = searcher.search(&options).await {
+            assert!(result.matches.is_empty());
+        }
+        
+        // Dotall lets `.` cross lines as well; lazily, so it stops at the first `) {}`
+        let options = SearchOptions {
+            multiline_dotall: true,
+            ..search_options(r"connect\(.*?\) \{\}")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 3);
+    }
+    
+    #[tokio::test]
+    async fn test_invert_match() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            path: "test_file.rs".into(),
+            invert_match: true,
+            ..search_options("fn")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        
+        // The bodies, braces and blank line, but neither declaration
+        assert_eq!(result.matches.len(), 5);
+        assert_eq!(result.stats.matched_lines, 5);
+        assert!(result.matches.iter().any(|m| m.contains("println!(\"Hello, world!\")")));
+        assert!(!result.matches.iter().any(|m| m.contains("fn ")));
+    }
+    
+    #[tokio::test]
+    async fn test_search_upward() {
+        let temp_dir = setup_test_files();
+        write_file(temp_dir.path(), "project/config.toml", "name = \"demo\"\n");
+        write_file(temp_dir.path(), "project/src/nested/main.rs", "fn main() {}\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // Found two levels above the starting directory
+        let options = SearchOptions {
+            path: "project/src/nested".to_string(),
+            search_upward: true,
+            ..search_options("name =")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.upward_level.as_deref(), Some("project"));
+        assert_eq!(result.matches.len(), 1);
+        assert!(result.matches[0].contains("config.toml"));
+        
+        // Each level only covers its own files, so the nearest one wins
+        let options = SearchOptions {
+            path: "project/src/nested".to_string(),
+            search_upward: true,
+            ..search_options("fn ")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.upward_level.as_deref(), Some("project/src/nested"));
+        assert_eq!(result.matches.len(), 1);
+        
+        // Reaching the root without a match returns an empty result
+        let options = SearchOptions {
+            path: "project/src".to_string(),
+            search_upward: true,
+            ..search_options("no such text")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(result.matches.is_empty());
+        assert!(result.upward_level.is_none());
+        
+        // The start must lie inside the root
+        let options = SearchOptions {
+            path: "../".to_string(),
+            search_upward: true,
+            ..search_options("name =")
+        };
+        assert!(matches!(searcher.search(&options).await, Err(AppError::PathTraversal(_))));
+    }
+    
+    #[tokio::test]
+    async fn test_max_results_and_count_per_file() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // Four matching lines across the two files, cut to three overall
+        let options = SearchOptions {
+            max_results: Some(3),
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 3);
+        assert!(result.stats.truncated);
+        
+        // A cap above the result count changes nothing
+        let options = SearchOptions {
+            max_results: Some(10),
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 4);
+        assert!(!result.stats.truncated);
+        
+        // ripgrep keeps the first matching line of each file
+        let options = SearchOptions {
+            max_count_per_file: Some(1),
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 2);
+        assert!(result.matches.iter().any(|m| m.contains("test_file.rs")));
+        assert!(result.matches.iter().any(|m| m.contains("test_file.js")));
+        
+        let options = SearchOptions {
+            max_results: Some(0),
+            ..search_options("hello")
+        };
+        assert!(matches!(searcher.search(&options).await, Err(AppError::ConfigError(_))));
+    }
+    
+    #[tokio::test]
+    async fn test_max_results_stops_ripgrep() {
+        let temp_dir = TempDir::new().unwrap();
+        let body: String = (0..2000).map(|i| format!("needle {}\n", i)).collect();
+        for file in 0..100 {
+            write_file(temp_dir.path(), &format!("file{}.txt", file), &body);
+        }
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // 200,000 matching lines, but ripgrep is stopped right after the sixth
+        let options = SearchOptions {
+            max_results: Some(5),
+            ..search_options("needle")
+        };
+        let started = Instant::now();
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 5);
+        assert!(result.stats.truncated);
+        assert!(result.stats.partial);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        
+        // Structured output stops the same way
+        let options = SearchOptions {
+            output_format: OutputFormat::Json,
+            ..options
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.structured_matches.unwrap().len(), 5);
+        assert!(result.stats.partial);
+        
+        // A sample needs every line, so ripgrep runs to the end
+        let options = SearchOptions {
+            sample: Some(5),
+            max_results: Some(5),
+            paths: vec!["file0.txt".to_string()],
+            ..search_options("needle")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 5);
+        assert!(!result.stats.partial);
+    }
+    
+    #[tokio::test]
+    async fn test_pattern_fallbacks() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // The primary pattern finds nothing, so the first fallback answers
+        let options = SearchOptions {
+            pattern_fallbacks: vec!["hello_world".to_string(), "hello".to_string()],
+            ..search_options("goodbye")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matched_pattern_index, Some(1));
+        assert_eq!(result.matches.len(), 1);
+        assert!(result.matches[0].contains("fn hello_world()"));
+        
+        // A matching primary pattern wins
+        let options = SearchOptions {
+            pattern_fallbacks: vec!["goodbye".to_string()],
+            ..search_options("println")
+        };
+        assert_eq!(searcher.search(&options).await.unwrap().matched_pattern_index, Some(0));
+        
+        // Nothing matching leaves the index out
+        let options = SearchOptions {
+            pattern_fallbacks: vec!["farewell".to_string()],
+            ..search_options("goodbye")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(result.matches.is_empty());
+        assert!(result.matched_pattern_index.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_health() {
+        let temp_dir = setup_test_files();
+        let extra = TempDir::new().unwrap();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf())
+            .with_extra_roots(vec![extra.path().to_path_buf()]);
+        
+        let health = searcher.health().await;
+        assert!(health.ready);
+        assert!(health.rg_version.unwrap().starts_with("ripgrep "));
+        assert!(health.rg_error.is_none());
+        let roots: Vec<_> = health.roots.iter().map(|root| (root.path.clone(), root.exists)).collect();
+        assert_eq!(roots, vec![
+            (temp_dir.path().display().to_string(), true),
+            (extra.path().display().to_string(), true),
+        ]);
+        
+        // A vanished root or a missing binary makes the server not ready
+        let extra_path = extra.path().to_path_buf();
+        drop(extra);
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf())
+            .with_extra_roots(vec![extra_path])
+            .with_rg_path(temp_dir.path().join("no-such-rg"));
+        let health = searcher.health().await;
+        assert!(!health.ready);
+        assert!(health.rg_version.is_none() && health.rg_error.is_some());
+        assert!(!health.roots[1].exists);
+    }
+    
+    #[tokio::test]
+    async fn test_include_manifest() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            include_manifest: true,
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let manifest = result.manifest.unwrap();
+        assert!(manifest.ripgrep_version.starts_with("ripgrep "));
+        assert!(manifest.args.iter().any(|arg| arg == "-i"));
+        assert!(manifest.args.iter().any(|arg| arg == "hello"));
+        
+        let root = std::fs::canonicalize(temp_dir.path()).unwrap();
+        assert_eq!(manifest.root, root.to_string_lossy());
+        assert!(manifest.timestamp > 0);
+        
+        // Left off by default
+        let result = searcher.search(&search_options("hello")).await.unwrap();
+        assert!(result.manifest.is_none());
+    }
+    
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_resource_report() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            resource_report: true,
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 4);
+        assert!(result.stats.user_cpu_ms.is_some());
+        assert!(result.stats.system_cpu_ms.is_some());
+        
+        // Left off by default
+        let result = searcher.search(&search_options("hello")).await.unwrap();
+        assert!(result.stats.user_cpu_ms.is_none() && result.stats.peak_rss_kb.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_include_scope() {
+        let temp_dir = setup_test_files();
+        write_file(temp_dir.path(), "src/lib.rs", "fn hello() {}\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            include_scope: true,
+            path: "src/../src".to_string(),
+            file_types: vec!["rust".to_string()],
+            globs: vec!["*.rs".to_string()],
+            exclude_globs: vec!["target/**".to_string()],
+            max_depth: Some(2),
+            use_ignore_files: Some(vec![IgnoreSource::Vcs]),
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let scope = result.scope.unwrap();
+        let root = std::fs::canonicalize(temp_dir.path()).unwrap();
+        assert_eq!(scope.root, root.to_string_lossy());
+        assert_eq!(scope.paths, vec!["src"]);
+        assert_eq!(scope.file_types, vec!["rust"]);
+        assert_eq!(scope.globs, vec!["*.rs"]);
+        assert_eq!(scope.exclude_globs, vec!["target/**"]);
+        assert_eq!(scope.max_depth, Some(2));
+        assert_eq!(scope.ignore_sources, vec![IgnoreSource::Vcs]);
+        assert!(!scope.search_hidden);
+        
+        // The whole root with every ignore rule switched off
+        let options = SearchOptions {
+            include_scope: true,
+            no_ignore: true,
+            ..search_options("hello")
+        };
+        let scope = searcher.search(&options).await.unwrap().scope.unwrap();
+        assert_eq!(scope.paths, vec!["."]);
+        assert!(scope.ignore_sources.is_empty());
+        
+        // Left off by default
+        assert!(searcher.search(&search_options("hello")).await.unwrap().scope.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_search_timeout() {
+        let temp_dir = TempDir::new().unwrap();
+        let line = "lorem ipsum dolor sit amet\n".repeat(200);
+        for i in 0..2000 {
+            write_file(temp_dir.path(), &format!("dir{}/file{}.txt", i % 20, i), &line);
+        }
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            timeout_ms: Some(1),
+            ..search_options("(a|b|c|d|e)+.*(s|t)")
+        };
+        assert!(matches!(searcher.search(&options).await, Err(AppError::Timeout(1))));
+        
+        // The server default applies when the request sets no timeout
+        let defaulted = RipgrepSearcher::new(temp_dir.path().to_path_buf()).with_settings(SearchSettings {
+            search_timeout_ms: Some(1),
+            ..SearchSettings::default()
+        });
+        assert!(matches!(defaulted.search(&search_options(".")).await, Err(AppError::Timeout(1))));
+        
+        // Compiled searches run under the same deadline
+        let compiled = CompiledSearch::compile(search_options("."), Path::new("rg")).await.unwrap();
+        assert!(matches!(defaulted.run_compiled(&compiled).await, Err(AppError::Timeout(1))));
+        
+        // So do counts, under either the request's timeout or the default
+        assert!(matches!(searcher.count(&options).await, Err(AppError::Timeout(1))));
+        assert!(matches!(defaulted.count(&search_options(".")).await, Err(AppError::Timeout(1))));
+        
+        // The killed ripgrep processes are reaped rather than left as zombies
+        #[cfg(target_os = "linux")]
+        {
+            let zombie_children = || {
+                let me = std::process::id().to_string();
+                std::fs::read_dir("/proc")
+                    .unwrap()
+                    .filter_map(|entry| std::fs::read_to_string(entry.ok()?.path().join("stat")).ok())
+                    .filter(|stat| {
+                        // `pid (comm) state ppid ...`
+                        let fields: Vec<&str> = stat.rsplit(')').next().unwrap_or("").split_whitespace().collect();
+                        stat.contains("(rg)") && fields.first() == Some(&"Z") && fields.get(1) == Some(&me.as_str())
+                    })
+                    .count()
+            };
+            let mut zombies = zombie_children();
+            for _ in 0..20 {
+                if zombies == 0 {
+                    break;
+                }
+                // Spawning another child gives tokio's orphan reaper a turn
+                let _ = searcher.search(&search_options("lorem")).await;
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                zombies = zombie_children();
+            }
+            assert_eq!(zombies, 0);
+        }
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_search_timeout_covers_count_pass() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "a.txt", "needle\n");
+        let status = std::process::Command::new("mkfifo")
+            .arg(temp_dir.path().join("pipe.fifo"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // The line sink makes ripgrep flush each line, so the main pass stops
+        // at the first match; the count pass then blocks on the FIFO, which
+        // has no writer, and only the search's deadline ends it
+        let (sink, _lines) = tokio::sync::mpsc::unbounded_channel();
+        let options = SearchOptions {
+            paths: vec!["a.txt".into(), "pipe.fifo".into()],
+            hard_limit: Some(1),
+            report_total: true,
+            timeout_ms: Some(500),
+            line_sink: Some(sink),
+            ..search_options("needle")
+        };
+        let result = tokio::time::timeout(Duration::from_secs(10), searcher.search(&options))
+            .await
+            .expect("the timeout should cover the count pass");
+        assert!(matches!(result, Err(AppError::Timeout(500))), "{:?}", result);
+    }
+    
+    #[tokio::test]
+    async fn test_cancel_search() {
+        let temp_dir = TempDir::new().unwrap();
+        let line = "lorem ipsum dolor sit amet\n".repeat(2000);
+        for i in 0..500 {
+            write_file(temp_dir.path(), &format!("dir{}/file{}.txt", i % 20, i), &line);
+        }
+        let searcher = Arc::new(RipgrepSearcher::new(temp_dir.path().to_path_buf()));
+        
+        // A capped search streams ripgrep's output; nothing matches, so it
+        // runs until cancelled
+        let options = SearchOptions {
+            search_id: Some("slow".to_string()),
+            max_results: Some(10),
+            ..search_options("(a|b|c|d|e)+.*zzz")
+        };
+        let running = tokio::spawn({
+            let searcher = Arc::clone(&searcher);
+            let options = options.clone();
+            async move { searcher.search(&options).await }
+        });
+        while !running.is_finished() && !searcher.active_searches.entries.lock().unwrap().contains_key("slow") {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        
+        // The id is taken while the search runs
+        assert!(matches!(searcher.search(&options).await, Err(AppError::ConfigError(_))));
+        assert!(searcher.cancel_search("slow"));
+        assert!(matches!(running.await.unwrap(), Err(AppError::Cancelled(_))));
+        assert!(!searcher.cancel_search("slow"));
+        
+        // No ripgrep process is left searching the directory
+        #[cfg(target_os = "linux")]
+        {
+            let root = temp_dir.path().to_string_lossy().into_owned();
+            let running_rg = || {
+                std::fs::read_dir("/proc")
+                    .unwrap()
+                    .filter_map(|entry| std::fs::read(entry.ok()?.path().join("cmdline")).ok())
+                    .any(|cmdline| String::from_utf8_lossy(&cmdline).contains(&root))
+            };
+            for _ in 0..20 {
+                if !running_rg() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            assert!(!running_rg());
+        }
+        
+        // Unknown ids are reported, not errors
+        assert!(!searcher.cancel_search("never-started"));
+    }
+    
+    #[tokio::test]
+    async fn test_count() {
+        let temp_dir = setup_test_files();
+        write_file(temp_dir.path(), "repeat.txt", "hello hello hello\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // Individual matches, so the repeated line counts three times
+        let counts = searcher.count(&search_options("hello")).await.unwrap();
+        assert_eq!(counts.total, 7);
+        let per_file: Vec<_> = counts
+            .per_file
+            .iter()
+            .map(|f| (Path::new(&f.path).file_name().unwrap().to_string_lossy().into_owned(), f.count))
+            .collect();
+        assert_eq!(per_file, vec![
+            ("repeat.txt".to_string(), 3),
+            ("test_file.js".to_string(), 2),
+            ("test_file.rs".to_string(), 2),
+        ]);
+        
+        // Search options such as file types narrow the count too
+        let options = SearchOptions {
+            file_types: vec!["rust".to_string()],
+            ..search_options("hello")
+        };
+        assert_eq!(searcher.count(&options).await.unwrap().total, 2);
+    }
+    
+    #[tokio::test]
+    async fn test_list_files() {
+        let temp_dir = setup_test_files();
+        write_file(temp_dir.path(), "ignored/skip.rs", "fn skip() {}\n");
+        write_file(temp_dir.path(), ".ignore", "ignored/\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // Ignore files apply as they do to searches
+        let files = searcher.list_files(&ListFilesOptions::default()).await.unwrap();
+        assert_eq!(files, vec!["test_file.js", "test_file.rs"]);
+        
+        let options = ListFilesOptions {
+            file_types: vec!["rust".to_string()],
+            ..ListFilesOptions::default()
+        };
+        assert_eq!(searcher.list_files(&options).await.unwrap(), vec!["test_file.rs"]);
+        
+        let options = ListFilesOptions {
+            path: "../".to_string(),
+            ..ListFilesOptions::default()
+        };
+        assert!(matches!(searcher.list_files(&options).await, Err(AppError::PathTraversal(_))));
+    }
+    
+    #[tokio::test]
+    async fn test_search_stats() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // Both fixture files are read and both contain "hello"
+        let result = searcher.search(&search_options("hello")).await.unwrap();
+        assert_eq!(result.stats.files_searched, Some(2));
+        assert_eq!(result.stats.files_with_matches, 2);
+        assert_eq!(result.matches.len(), 4);
+        
+        // The JSON modes take the same counts from ripgrep's summary
+        let options = SearchOptions {
+            output_format: OutputFormat::Json,
+            ..search_options("console")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.stats.files_searched, Some(2));
+        assert_eq!(result.stats.files_with_matches, 1);
+        
+        // No match still reports how much was searched
+        let result = searcher.search(&search_options("no_such_text")).await.unwrap();
+        assert!(result.matches.is_empty());
+        assert_eq!(result.stats.files_searched, Some(2));
+        assert_eq!(result.stats.files_with_matches, 0);
+        assert_eq!(result.stats.match_ratio, None);
+    }
+    
+    #[tokio::test]
+    async fn test_match_ratio() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        let ratio = |pattern: &str| {
+            let options = SearchOptions {
+                match_ratio: true,
+                ..search_options(pattern)
+            };
+            let searcher = &searcher;
+            async move { searcher.search(&options).await.unwrap().stats.match_ratio }
+        };
+        
+        // One of the two fixture files logs to the console; both say hello
+        assert_eq!(ratio("console").await, Some(0.5));
+        assert_eq!(ratio("hello").await, Some(1.0));
+        assert_eq!(ratio("no_such_text").await, Some(0.0));
+    }
+    
+    #[tokio::test]
+    async fn test_type_add() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf())
+            .with_type_add(vec!["script:*.js".to_string()]);
+        
+        // The custom type passes the type check and selects its files
+        let options = SearchOptions {
+            file_types: vec!["script".into()],
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 2);
+        assert!(result.matches.iter().all(|m| m.contains("test_file.js")));
+    }
+    
+    #[tokio::test]
+    async fn test_replace_preview() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        let original = std::fs::read_to_string(temp_dir.path().join("test_file.rs")).unwrap();
+        
+        let options = SearchOptions {
+            replace: Some("goodbye".to_string()),
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 4);
+        assert!(result.matches.iter().all(|m| m.contains("goodbye") && !m.to_lowercase().contains("hello")));
+        
+        // The fixture on disk is untouched
+        let after = std::fs::read_to_string(temp_dir.path().join("test_file.rs")).unwrap();
+        assert_eq!(after, original);
+        
+        // Structured output cannot show replacements
+        let options = SearchOptions {
+            output_format: OutputFormat::Json,
+            ..options
+        };
+        assert!(matches!(searcher.search(&options).await, Err(AppError::ConfigError(_))));
+    }
+    
+    #[tokio::test]
+    async fn test_patch_hunks() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            replace: Some("term".to_string()),
+            patch_hunks: true,
+            context_lines: Some(1),
+            paths: vec!["test_file.rs".to_string()],
+            ..search_options("query")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let hunks = result.patch_hunks.unwrap();
+        assert_eq!(hunks.len(), 1);
+        
+        // Lines 5 and 6 change, with one line of context on either side
+        let hunk = &hunks[0];
+        assert_eq!((hunk.path.as_str(), hunk.old_start, hunk.old_lines), ("test_file.rs", 4, 4));
+        assert_eq!((hunk.new_start, hunk.new_lines), (4, 4));
+        assert_eq!(
+            hunk.diff,
+            "--- a/test_file.rs\n+++ b/test_file.rs\n@@ -4,4 +4,4 @@\n \n\
+             -fn search_function(query: &str) {\n+fn search_function(term: &str) {\n\
+             -    println!(\"Searching for {}\", query);\n+    println!(\"Searching for {}\", term);\n }\n"
+        );
+        
+        // Without replace there is nothing to diff
+        let without_replace = SearchOptions {
+            replace: None,
+            ..options.clone()
+        };
+        assert!(matches!(searcher.search(&without_replace).await, Err(AppError::ConfigError(_))));
+        
+        // A replacement spanning lines has no one-to-one preview to diff
+        for multi_line in [
+            SearchOptions {
+                replace: Some("a\nb".to_string()),
+                ..options.clone()
+            },
+            SearchOptions {
+                multiline: true,
+                ..options.clone()
+            },
+        ] {
+            assert!(matches!(searcher.search(&multi_line).await, Err(AppError::ConfigError(_))));
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_grouped_output() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            output_format: OutputFormat::Grouped,
+            sort: Some(SortMode::Path),
+            ..search_options("(?i)hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(result.matches.is_empty());
+        assert_eq!(result.stats.matched_lines, 4);
+        
+        let groups = result.grouped_matches.unwrap();
+        let summary: Vec<(String, Vec<Option<u64>>)> = groups
+            .iter()
+            .map(|g| {
+                let name = Path::new(&g.path).file_name().unwrap().to_string_lossy().into_owned();
+                (name, g.matches.iter().map(|m| m.line_number).collect())
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("test_file.js".to_string(), vec![Some(1), Some(2)]),
+                ("test_file.rs".to_string(), vec![Some(1), Some(2)]),
+            ]
+        );
+        assert_eq!(groups[1].matches[0].text, "fn hello_world() {");
+        
+        // max_results caps the lines across all groups
+        let options = SearchOptions {
+            max_results: Some(3),
+            ..options
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let groups = result.grouped_matches.unwrap();
+        assert_eq!(groups.iter().map(|g| g.matches.len()).collect::<Vec<_>>(), vec![2, 1]);
+        assert!(result.stats.truncated);
+    }
+    
+    #[tokio::test]
+    async fn test_sort_by_path() {
+        let temp_dir = TempDir::new().unwrap();
+        for name in ["c.txt", "a.txt", "b/z.txt", "b/y.txt", "d/e/f.txt"] {
+            write_file(temp_dir.path(), name, "needle\n");
+        }
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            sort: Some(SortMode::Path),
+            ..search_options("needle")
+        };
+        for _ in 0..3 {
+            let result = searcher.search(&options).await.unwrap();
+            let names: Vec<String> = result
+                .matches
+                .iter()
+                .map(|m| {
+                    let path = Path::new(m.split(':').next().unwrap());
+                    relative_components(path, temp_dir.path()).join("/")
+                })
+                .collect();
+            assert_eq!(names, vec!["a.txt", "b/y.txt", "b/z.txt", "c.txt", "d/e/f.txt"]);
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_case_mode() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        let count = |pattern: &str, case_mode: CaseMode| {
+            let options = SearchOptions {
+                case_mode: Some(case_mode),
+                ..search_options(pattern)
+            };
+            let searcher = &searcher;
+            async move { searcher.search(&options).await.unwrap().matches.len() }
+        };
+        
+        // "hello" opens two lines and "Hello" two others
+        assert_eq!(count("hello", CaseMode::Sensitive).await, 2);
+        assert_eq!(count("hello", CaseMode::Insensitive).await, 4);
+        assert_eq!(count("HELLO", CaseMode::Insensitive).await, 4);
+        
+        // Smart case ignores case only for an all-lowercase pattern
+        assert_eq!(count("hello", CaseMode::Smart).await, 4);
+        assert_eq!(count("Hello", CaseMode::Smart).await, 2);
+    }
+    
+    #[tokio::test]
+    async fn test_columns() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // "Hello" starts at byte 14 of `    println!("Hello, world!");`
+        let options = SearchOptions {
+            columns: true,
+            case_sensitive: true,
+            paths: vec!["test_file.rs".to_string()],
+            ..search_options("Hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 1);
+        assert!(result.matches[0].ends_with("test_file.rs:2:15:    println!(\"Hello, world!\");"), "{}", result.matches[0]);
+        
+        // Structured output reports the same column per match
+        let options = SearchOptions {
+            byte_ranges: true,
+            ..options
+        };
+        let structured = searcher.search(&options).await.unwrap().structured_matches.unwrap();
+        assert_eq!(structured.len(), 1);
+        assert_eq!(structured[0].column, Some(15));
+    }
+    
+    #[tokio::test]
+    async fn test_allowed_extensions() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf()).with_settings(SearchSettings {
+            allowed_extensions: Some(vec!["rs".to_string()]),
+            ..SearchSettings::default()
+        });
+        
+        // A whole-root search only reads the Rust file
+        let result = searcher.search(&search_options("hello")).await.unwrap();
+        assert_eq!(result.matches.len(), 2);
+        assert!(result.matches.iter().all(|m| m.contains("test_file.rs")));
+        
+        // Naming the JavaScript file, by path or glob, still returns nothing from it
+        let options = SearchOptions {
+            paths: vec!["test_file.js".to_string()],
+            ..search_options("hello")
+        };
+        assert!(searcher.search(&options).await.unwrap().matches.is_empty());
+        let options = SearchOptions {
+            globs: vec!["*.js".to_string()],
+            ..search_options("hello")
+        };
+        assert!(searcher.search(&options).await.unwrap().matches.iter().all(|m| !m.contains("test_file.js")));
+        
+        // So does asking for its type, in any output shape
+        let options = SearchOptions {
+            file_types: vec!["js".to_string(), "rust".to_string()],
+            output_format: OutputFormat::Json,
+            report_total: true,
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let structured = result.structured_matches.unwrap();
+        assert_eq!(structured.len(), 2);
+        assert!(structured.iter().all(|m| m.path.ends_with("test_file.rs")));
+        assert_eq!(result.stats.total_matches, Some(2));
+        
+        // Counting and listing files honor the allowlist too
+        let counts = searcher.count(&search_options("hello")).await.unwrap();
+        assert_eq!(counts.total, 2);
+        assert!(counts.per_file.iter().all(|f| f.path.ends_with("test_file.rs")));
+        let files = searcher.list_files(&ListFilesOptions::default()).await.unwrap();
+        assert_eq!(files, vec!["test_file.rs"]);
+    }
+    
+    #[tokio::test]
+    async fn test_max_filesize() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        write_file(temp_dir.path(), "bundle.min.js", &format!("var hello=1;{}", "x".repeat(4096)));
+        
+        let options = SearchOptions {
+            max_filesize: Some("1K".to_string()),
+            ..search_options("hello")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 4);
+        assert!(result.matches.iter().all(|m| !m.contains("bundle.min.js")));
+        
+        // Malformed sizes never reach ripgrep
+        for size in ["", "1KB", "-1", "1.5M", "M"] {
+            let options = SearchOptions {
+                max_filesize: Some(size.to_string()),
+                ..search_options("hello")
+            };
+            assert!(matches!(searcher.search(&options).await, Err(AppError::ConfigError(_))), "{:?}", size);
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_encoding() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("latin1.txt"), b"caf\xe9 cr\xe8me\n").unwrap();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // Transcoded, the accented text matches and comes back as UTF-8
+        let options = SearchOptions {
+            encoding: Some("latin1".to_string()),
+            ..search_options("café")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 1);
+        assert!(result.matches[0].ends_with("café crème"), "{:?}", result.matches);
+        
+        // Raw Latin-1 bytes fail the search unless lossy decoding is on
+        let options = search_options("caf");
+        assert!(matches!(searcher.search(&options).await, Err(AppError::RipgrepError(_))));
+        let options = SearchOptions {
+            lossy_decode: true,
+            ..options
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(result.matches[0].ends_with("caf\u{fffd} cr\u{fffd}me"), "{:?}", result.matches);
+    }
+    
+    #[tokio::test]
+    async fn test_pcre2() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // The default engine rejects lookahead
+        let options = search_options("hello(?=_world)");
+        assert!(matches!(searcher.search(&options).await, Err(AppError::RipgrepError(_))));
+        
+        let options = SearchOptions {
+            pcre2: true,
+            ..options
+        };
+        let result = match searcher.search(&options).await {
+            // A ripgrep built without PCRE2 gets the explanatory error
+            Err(AppError::RipgrepError(message)) if message.contains("PCRE2 is not available") => return,
+            other => other.unwrap(),
+        };
+        assert_eq!(result.matches.len(), 1);
+        assert!(result.matches[0].contains("fn hello_world()"));
+    }
+    
+    #[tokio::test]
+    async fn test_definitions_only() {
+        let temp_dir = setup_test_files();
+        write_file(temp_dir.path(), "main.rs", "fn main() {\n    hello_world();\n}\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // The definition is found, the call site in main.rs is not
+        let options = SearchOptions {
+            definitions_only: true,
+            ..search_options("hello_world")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 1);
+        assert!(result.matches[0].contains("test_file.rs:1:fn hello_world() {"));
+        
+        // Without it both show up
+        let result = searcher.search(&search_options("hello_world")).await.unwrap();
+        assert_eq!(result.matches.len(), 2);
+    }
+    
+    #[tokio::test]
+    async fn test_git_grep_format() {
+        let temp_dir = setup_test_files();
+        write_file(temp_dir.path(), "sub/dir/nested.rs", "fn hello_world() {}\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            case_sensitive: true,
+            output_format: OutputFormat::GitGrep,
+            ..search_options("fn hello_world")
+        };
+        let mut result = searcher.search(&options).await.unwrap();
+        result.matches.sort();
+        assert_eq!(
+            result.matches,
+            vec!["sub/dir/nested.rs:1:fn hello_world() {}", "test_file.rs:1:fn hello_world() {"]
+        );
+    }
+    
+    #[tokio::test]
+    async fn test_only_in_files_matching() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "both.txt", "alpha\nbeta\n");
+        write_file(temp_dir.path(), "primary_only.txt", "alpha\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let result = searcher.search(&search_options("alpha")).await.unwrap();
+        assert_eq!(result.matches.len(), 2);
+        
+        let options = SearchOptions {
+            only_in_files_matching: Some("beta".into()),
+            ..search_options("alpha")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 1);
+        assert!(result.matches[0].contains("both.txt"));
+        
+        // No file contains the secondary pattern, so nothing is searched
+        let options = SearchOptions {
+            only_in_files_matching: Some("gamma".into()),
+            ..search_options("alpha")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(result.matches.is_empty());
+    }
+    
+    #[test]
+    fn test_symlink_depth_cap() {
+        let searcher = RipgrepSearcher::new(PathBuf::from("/unused"));
+        let settings = SearchSettings {
+            symlink_max_depth: 5,
+            ..SearchSettings::default()
+        };
+        
+        let options = SearchOptions {
+            follow_symlinks: true,
+            ..search_options("x")
+        };
+        let resolved = searcher.resolve_options(&settings, &options).unwrap();
+        assert_eq!(resolved.max_depth, Some(5));
+        
+        // An explicit depth is left alone
+        let options = SearchOptions {
+            follow_symlinks: true,
+            max_depth: Some(2),
+            ..search_options("x")
+        };
+        let resolved = searcher.resolve_options(&settings, &options).unwrap();
+        assert_eq!(resolved.max_depth, Some(2));
+        
+        // Without symlink following there is no implicit cap
+        let resolved = searcher.resolve_options(&settings, &search_options("x")).unwrap();
+        assert_eq!(resolved.max_depth, None);
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlink_cycle_terminates() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "a/needle.txt", "needle\n");
+        write_file(temp_dir.path(), "b/other.txt", "needle\n");
+        std::os::unix::fs::symlink("../b", temp_dir.path().join("a/to_b")).unwrap();
+        std::os::unix::fs::symlink("../a", temp_dir.path().join("b/to_a")).unwrap();
+        
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf()).with_settings(SearchSettings {
+            symlink_max_depth: 4,
+            ..SearchSettings::default()
+        });
+        let options = SearchOptions {
+            follow_symlinks: true,
+            ..search_options("needle")
+        };
+        
+        // ripgrep may report the loop as an error; what matters is that the
+        // search finishes instead of walking the cycle forever
+        let outcome = tokio::time::timeout(Duration::from_secs(10), searcher.search(&options)).await;
+        assert!(outcome.is_ok(), "search through a symlink cycle did not terminate");
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_follow_internal_symlinks_only() {
+        let outside = TempDir::new().unwrap();
+        write_file(outside.path(), "secret/leak.txt", "needle outside\n");
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "real/inside.txt", "needle inside\n");
+        std::os::unix::fs::symlink("real", temp_dir.path().join("linked")).unwrap();
+        std::os::unix::fs::symlink(outside.path().join("secret"), temp_dir.path().join("escape")).unwrap();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // Plain symlink following reaches both targets
+        let options = SearchOptions {
+            follow_symlinks: true,
+            ..search_options("needle")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(result.matches.iter().any(|m| m.contains("needle outside")));
+        
+        // The internal link is kept, the escaping one is not
+        let options = SearchOptions {
+            follow_internal_symlinks_only: true,
+            ..search_options("needle")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(result.matches.iter().any(|m| m.contains("linked") && m.contains("needle inside")));
+        assert!(result.matches.iter().all(|m| !m.contains("needle outside")));
+        
+        // Structured results and the total count leave it out as well
+        let options = SearchOptions {
+            output_format: OutputFormat::Json,
+            report_total: true,
+            ..options
+        };
+        let result = searcher.search(&options).await.unwrap();
+        let structured = result.structured_matches.unwrap();
+        assert_eq!(structured.len(), 2);
+        assert!(structured.iter().all(|m| !m.path.contains("escape")));
+        assert_eq!(result.stats.total_matches, Some(2));
+        
+        // A compiled search drops the escaping link's matches too
+        let options = SearchOptions {
+            follow_internal_symlinks_only: true,
+            ..search_options("needle")
+        };
+        let compiled = CompiledSearch::compile(options, Path::new("rg")).await.unwrap();
+        let result = searcher.run_compiled(&compiled).await.unwrap();
+        assert!(result.matches.iter().any(|m| m.contains("linked") && m.contains("needle inside")));
+        assert!(result.matches.iter().all(|m| !m.contains("needle outside")));
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_filter_inside_root() {
+        let outside = TempDir::new().unwrap();
+        write_file(outside.path(), "leak.txt", "");
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "real/inside.txt", "");
+        std::os::unix::fs::symlink("real", temp_dir.path().join("linked")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), temp_dir.path().join("escape")).unwrap();
+        
+        let filter = FileFilter {
+            inside_root: Some(std::fs::canonicalize(temp_dir.path()).unwrap()),
+            ..FileFilter::default()
+        };
+        assert!(filter.keeps(&temp_dir.path().join("linked/inside.txt")));
+        assert!(!filter.keeps(&temp_dir.path().join("escape/leak.txt")));
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_follow_symlinks_keeps_traversal_check() {
+        let outside = TempDir::new().unwrap();
+        write_file(outside.path(), "shared/leak.txt", "needle outside\n");
+        let temp_dir = setup_test_files();
+        std::os::unix::fs::symlink(outside.path().join("shared"), temp_dir.path().join("shared")).unwrap();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        // Naming the escaping link, or a file under it, is rejected before
+        // ripgrep runs
+        let options = SearchOptions {
+            path: "shared/leak.txt".to_string(),
+            follow_symlinks: true,
+            ..search_options("needle")
+        };
+        assert!(matches!(searcher.search(&options).await, Err(AppError::PathTraversal(_))));
+        let options = SearchOptions {
+            paths: vec!["test_file.rs".to_string(), "shared".to_string()],
+            follow_symlinks: true,
+            ..search_options("needle")
+        };
+        assert!(matches!(searcher.search(&options).await, Err(AppError::PathTraversal(_))));
+    }
+    
+    #[test]
+    fn test_global_thread_budget() {
+        let searcher = RipgrepSearcher::new(PathBuf::from("/unused"));
+        let budget = Some(8);
+        let barrier = std::sync::Barrier::new(4);
+        
+        // Four searches in flight at once each get a reduced share
+        let mut shares: Vec<usize> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| {
+                    let slot = searcher.enter_search();
+                    let share = slot.thread_share(budget).unwrap();
+                    barrier.wait();
+                    share
+                }))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        shares.sort();
+        assert_eq!(shares, vec![2, 2, 4, 8]);
+        
+        // Finished searches release their slots
+        assert_eq!(searcher.enter_search().thread_share(budget), Some(8));
+        
+        // A budget smaller than the number of searches still grants one thread
+        let _slots: Vec<_> = (0..3).map(|_| searcher.enter_search()).collect();
+        assert_eq!(searcher.enter_search().thread_share(Some(2)), Some(1));
+        assert_eq!(searcher.enter_search().thread_share(None), None);
+    }
+    
+    #[test]
+    fn test_skipped_files() {
+        let stderr = concat!(
+            "rg: DEBUG|globset|crates/globset/src/lib.rs:453: built glob set; 0 literals\n",
+            r#"rg: DEBUG|ignore::walk|crates/ignore/src/walk.rs:1736: ignoring /r/secret.txt: Ignore(IgnoreMatch(Gitignore(Glob { from: Some("/r/.gitignore"), original: "secret.txt", actual: "**/secret.txt", is_allow: false, is_only_dir: false })))"#, "\n",
+            "rg: DEBUG|ignore::walk|crates/ignore/src/walk.rs:1736: ignoring /r/.env: Ignore(IgnoreMatch(Hidden))\n",
+        );
+        assert_eq!(skipped_files(stderr), vec![
+            "/r/secret.txt was skipped: matches ignore rule `secret.txt` in /r/.gitignore".to_string(),
+            "/r/.env was skipped: hidden file (names starting with `.` are skipped)".to_string(),
+        ]);
+    }
+    
+    #[test]
+    fn test_text_line_path() {
+        assert_eq!(text_line_path("/r/a.rs:12:let x = 1;", true, false), Some("/r/a.rs"));
+        assert_eq!(text_line_path("/r/a:b.rs:3:x: 1", true, false), Some("/r/a:b.rs"));
+        assert_eq!(text_line_path("/r/a.rs:let x = 1;", false, false), Some("/r/a.rs"));
+        assert_eq!(text_line_path("--", true, false), None);
+        
+        // A colon-digits-colon run inside the filename looks like a line number
+        assert_eq!(text_line_path("/r/a:1:b.rs:5:x", true, false), Some("/r/a"));
+        assert_eq!(text_line_path("/r/a:1:b.rs\u{0}5:x", true, true), Some("/r/a:1:b.rs"));
+        assert_eq!(text_line_path("/r/a:b.rs\0x", false, true), Some("/r/a:b.rs"));
+        assert_eq!(text_line_path("--", true, true), None);
+    }
+    
+    #[test]
+    fn test_result_files_bounded() {
+        let cache = ResultFiles::new(Duration::from_secs(60), 2);
+        cache.insert("a".into(), vec![PathBuf::from("a.rs")]);
+        cache.insert("b".into(), vec![PathBuf::from("b.rs")]);
+        cache.insert("c".into(), vec![PathBuf::from("c.rs")]);
+        
+        assert!(cache.get("a").is_none());
+        assert_eq!(cache.get("c"), Some(vec![PathBuf::from("c.rs")]));
+        
+        let expired = ResultFiles::new(Duration::ZERO, 2);
+        expired.insert("a".into(), vec![]);
+        assert!(expired.get("a").is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_match_window_chars() {
+        let temp_dir = setup_test_files();
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        
+        let options = SearchOptions {
+            fixed_strings: true,
+            case_sensitive: true,
+            file_types: vec!["rust".into()],
+            match_window_chars: Some(4),
+            ..search_options("println")
+        };
+        
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 2);
+        // Four leading spaces, the match, then four trailing characters
+        assert!(result.matches[0].ends_with(":2:    println!(\"H"));
+        
+        // Without a window there is nothing for smart_truncate to cut
+        let options = SearchOptions {
+            smart_truncate: true,
+            ..search_options("println")
+        };
+        assert!(matches!(searcher.search(&options).await, Err(AppError::ConfigError(_))));
+    }
+    
+    #[tokio::test]
+    async fn test_search_hidden_and_no_ignore() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::create_dir(temp_dir.path().join(".git")).unwrap();
+        write_file(temp_dir.path(), ".gitignore", "ignored.txt\n.hidden\n");
+        write_file(temp_dir.path(), ".hidden", "needle\n");
+        write_file(temp_dir.path(), "ignored.txt", "needle\n");
+        write_file(temp_dir.path(), "kept.txt", "needle\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        let files = |search_hidden: bool, no_ignore: bool| {
+            let options = SearchOptions {
+                search_hidden,
+                no_ignore,
+                ..search_options("needle")
+            };
+            let searcher = &searcher;
+            async move {
+                let result = searcher.search(&options).await.unwrap();
+                let mut names: Vec<String> = result.matches
+                    .iter()
+                    .filter_map(|m| Path::new(m.split(':').next()?).file_name()?.to_str().map(String::from))
+                    .collect();
+                names.sort();
+                names
+            }
+        };
+        
+        // The hidden file is also gitignored, so it takes both flags
+        assert_eq!(files(false, false).await, vec!["kept.txt"]);
+        assert_eq!(files(true, false).await, vec!["kept.txt"]);
+        assert_eq!(files(false, true).await, vec!["ignored.txt", "kept.txt"]);
+        assert_eq!(files(true, true).await, vec![".hidden", "ignored.txt", "kept.txt"]);
     }
     
-    /// Validate a search path to prevent path traversal attacks
-    fn validate_path(&self, path: &str) -> Result<PathBuf, AppError> {
-        let search_path = self.root_dir.join(path);
+    #[tokio::test]
+    async fn test_use_ignore_files_dot() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), ".ignore", "ignored.txt\n");
+        write_file(temp_dir.path(), "ignored.txt", "needle\n");
+        write_file(temp_dir.path(), "kept.txt", "needle\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
         
-        // Canonicalize both paths to resolve any ".." components
-        let canonical_search_path = match std::fs::canonicalize(&search_path) {
-            Ok(p) => p,
-            Err(_) => return Err(AppError::InvalidPath(path.to_string())),
+        // Honoring dot files hides the ignored file
+        let options = SearchOptions {
+            use_ignore_files: Some(vec![IgnoreSource::Dot]),
+            ..search_options("needle")
         };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 1);
+        assert!(result.matches[0].contains("kept.txt"));
         
-        let canonical_root = match std::fs::canonicalize(&self.root_dir) {
-            Ok(p) => p,
-            Err(_) => return Err(AppError::ConfigError("Could not resolve root directory".to_string())),
+        // Honoring nothing searches both files
+        let options = SearchOptions {
+            use_ignore_files: Some(vec![]),
+            ..search_options("needle")
         };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 2);
+    }
+    
+    #[tokio::test]
+    async fn test_use_ignore_files_parent() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), ".ignore", "nested.txt\n");
+        write_file(temp_dir.path(), "sub/nested.txt", "needle\n");
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
         
-        // Ensure the search path is within the root directory
-        if !canonical_search_path.starts_with(&canonical_root) {
-            return Err(AppError::PathTraversal(path.to_string()));
+        // The root .ignore is a parent of "sub" and applies by default
+        let options = SearchOptions {
+            path: "sub".into(),
+            use_ignore_files: Some(vec![IgnoreSource::Dot, IgnoreSource::Parent]),
+            ..search_options("needle")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert!(result.matches.is_empty());
+        
+        // Without parent ignore files the nested file is searched
+        let options = SearchOptions {
+            path: "sub".into(),
+            use_ignore_files: Some(vec![IgnoreSource::Dot]),
+            ..search_options("needle")
+        };
+        let result = searcher.search(&options).await.unwrap();
+        assert_eq!(result.matches.len(), 1);
+    }
+    
+    #[test]
+    fn test_parse_json_matches() {
+        let stdout = concat!(
+            r#"{"type":"begin","data":{"path":{"text":"src/a.rs"}}}"#, "\n",
+            r#"{"type":"match","data":{"path":{"text":"src/a.rs"},"lines":{"text":"let x = foo(foo);\n"},"line_number":3,"absolute_offset":10,"submatches":[{"match":{"text":"foo"},"start":8,"end":11},{"match":{"text":"foo"},"start":12,"end":15}]}}"#, "\n",
+            r#"{"type":"end","data":{"path":{"text":"src/a.rs"},"binary_offset":null,"stats":{}}}"#, "\n",
+        );
+        
+        let records = parse_json_matches(stdout).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(record_files(&records), vec![PathBuf::from("src/a.rs")]);
+        
+        let windows = match_windows(&records, 1, &search_options("foo"));
+        assert_eq!(windows, vec!["src/a.rs:3: foo(", "src/a.rs:3:(foo)"]);
+    }
+    
+    #[test]
+    fn test_matches_logged_at_trace() {
+        #[derive(Clone, Default)]
+        struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+        
+        impl std::io::Write for CapturedLogs {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
         }
         
-        Ok(search_path)
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        
+        let stdout = concat!(
+            r#"{"type":"match","data":{"path":{"text":"src/a.rs"},"lines":{"text":"let needle = 1;\n"},"line_number":7,"absolute_offset":0,"submatches":[]}}"#, "\n",
+        );
+        tracing::subscriber::with_default(subscriber, || parse_json_matches(stdout).unwrap());
+        
+        let logged = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logged.contains("TRACE"), "{}", logged);
+        assert!(logged.contains("src/a.rs") && logged.contains("let needle = 1;"), "{}", logged);
     }
     
-    #[instrument(skip(self, options), fields(pattern = %options.pattern))]
-    pub async fn search(&self, options: &SearchOptions) -> Result<SearchResult, AppError> {
-        debug!("Starting ripgrep search");
+    #[test]
+    fn test_parse_only() {
+        let searcher = RipgrepSearcher::new(PathBuf::from("/root"));
+        let stdout = concat!(
+            r#"{"type":"begin","data":{"path":{"text":"/root/src/a.rs"}}}"#, "\n",
+            r#"{"type":"context","data":{"path":{"text":"/root/src/a.rs"},"lines":{"text":"fn main() {\n"},"line_number":1,"absolute_offset":0,"submatches":[]}}"#, "\n",
+            r#"{"type":"match","data":{"path":{"text":"/root/src/a.rs"},"lines":{"text":"    let x = foo(foo);\n"},"line_number":2,"absolute_offset":12,"submatches":[{"match":{"text":"foo"},"start":12,"end":15},{"match":{"text":"foo"},"start":16,"end":19}]}}"#, "\n",
+            r#"{"type":"context","data":{"path":{"text":"/root/src/a.rs"},"lines":{"text":"}\n"},"line_number":3,"absolute_offset":34,"submatches":[]}}"#, "\n",
+            r#"{"type":"end","data":{"path":{"text":"/root/src/a.rs"},"binary_offset":null,"stats":{}}}"#, "\n",
+            r#"{"type":"summary","data":{"elapsed_total":{"human":"0.01s","nanos":1,"secs":0},"stats":{}}}"#, "\n",
+        );
         
-        // Build the search path
-        let search_path = if options.path.is_empty() {
-            self.root_dir.clone()
-        } else {
-            self.validate_path(&options.path)?
+        // Structured output: one entry per submatch, context lines skipped
+        let options = SearchOptions {
+            byte_ranges: true,
+            ..search_options("foo")
         };
+        let result = searcher.parse_only(&options, stdout.as_bytes()).unwrap();
+        let structured = result.structured_matches.unwrap();
+        assert_eq!(structured.len(), 2);
+        assert_eq!(structured[0].line_number, Some(2));
+        assert_eq!(structured[0].full_line, "    let x = foo(foo);");
+        assert_eq!((structured[1].start_byte, structured[1].end_byte), (Some(28), Some(31)));
+        assert_eq!(result.stats.matched_lines, 1);
+        assert!(result.search_id.is_empty());
         
-        // Start timing the search
-        let start = std::time::Instant::now();
+        // Plain JSON rendering uses root-relative paths
+        let options = SearchOptions {
+            output_format: OutputFormat::GitGrep,
+            ..search_options("foo")
+        };
+        let result = searcher.parse_only(&options, stdout.as_bytes()).unwrap();
+        assert_eq!(result.matches, vec!["src/a.rs:2:    let x = foo(foo);"]);
         
-        // Build the command
-        let output = self.build_command(options, &search_path).await?;
+        // The json format parses each match into its fields
+        let options = SearchOptions {
+            output_format: OutputFormat::Json,
+            ..search_options("foo")
+        };
+        let result = searcher.parse_only(&options, stdout.as_bytes()).unwrap();
+        assert!(result.matches.is_empty());
+        let structured = result.structured_matches.unwrap();
+        assert_eq!(structured.len(), 2);
+        assert_eq!(structured[1].path, "/root/src/a.rs");
+        assert_eq!(structured[1].line_number, Some(2));
+        assert_eq!(structured[1].column, Some(17));
+        assert_eq!(structured[1].full_line, "    let x = foo(foo);");
+        assert_eq!(structured[1].matched_text.as_deref(), Some("foo"));
+        assert!(structured[1].start_byte.is_none());
         
-        // Calculate elapsed time
-        let elapsed = start.elapsed();
+        // Context events join the match in one block
+        let options = SearchOptions {
+            merge_context: true,
+            ..search_options("foo")
+        };
+        let result = searcher.parse_only(&options, stdout.as_bytes()).unwrap();
+        let blocks = result.context_blocks.unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!((blocks[0].start_line, blocks[0].end_line), (1, 3));
         
-        // Parse the output
-        let stdout = String::from_utf8(output.stdout)
-            .map_err(|_| AppError::RipgrepError("Invalid UTF-8 in output".to_string()))?;
-            
-        let matches: Vec<String> = stdout
-            .lines()
-            .map(|s| s.to_string())
+        // Text output passes through unchanged
+        let text = b"/root/src/a.rs:2:    let x = foo(foo);\n";
+        let result = searcher.parse_only(&search_options("foo"), text).unwrap();
+        assert_eq!(result.matches, vec!["/root/src/a.rs:2:    let x = foo(foo);"]);
+        
+        // Malformed JSON is an error rather than silently empty
+        let options = SearchOptions {
+            byte_ranges: true,
+            ..search_options("foo")
+        };
+        assert!(searcher.parse_only(&options, b"{not json\n").is_err());
+    }
+    
+    #[test]
+    fn test_match_id() {
+        let searcher = RipgrepSearcher::new(PathBuf::from("/root"));
+        let options = SearchOptions {
+            byte_ranges: true,
+            ..search_options("foo|bar")
+        };
+        let first = r#"{"type":"match","data":{"path":{"text":"/root/a.rs"},"lines":{"text":"foo bar\n"},"line_number":3,"absolute_offset":0,"submatches":[{"match":{"text":"foo"},"start":0,"end":3},{"match":{"text":"bar"},"start":4,"end":7}]}}"#;
+        let second = r#"{"type":"match","data":{"path":{"text":"/root/b.rs"},"lines":{"text":"foo\n"},"line_number":3,"absolute_offset":0,"submatches":[{"match":{"text":"foo"},"start":0,"end":3}]}}"#;
+        
+        let ids = |stdout: String| -> Vec<String> {
+            let result = searcher.parse_only(&options, stdout.as_bytes()).unwrap();
+            result.structured_matches.unwrap().into_iter().map(|m| m.match_id).collect()
+        };
+        let run = ids(format!("{}\n{}\n", first, second));
+        let reordered = ids(format!("{}\n{}\n", second, first));
+        
+        // Same matches, same ids, whatever the order
+        assert_eq!(run.len(), 3);
+        assert_eq!(run, vec![reordered[1].clone(), reordered[2].clone(), reordered[0].clone()]);
+        
+        // Different text on one line, or the same text in another file, differ
+        let distinct: HashSet<_> = run.iter().collect();
+        assert_eq!(distinct.len(), 3);
+    }
+    
+    #[test]
+    fn test_max_submatches_per_line() {
+        let searcher = RipgrepSearcher::new(PathBuf::from("/root"));
+        let stdout = concat!(
+            r#"{"type":"match","data":{"path":{"text":"/root/a.csv"},"lines":{"text":"a,b,c,d\n"},"line_number":1,"absolute_offset":0,"submatches":[{"match":{"text":","},"start":1,"end":2},{"match":{"text":","},"start":3,"end":4},{"match":{"text":","},"start":5,"end":6}]}}"#, "\n",
+            r#"{"type":"match","data":{"path":{"text":"/root/a.csv"},"lines":{"text":"e,f\n"},"line_number":2,"absolute_offset":8,"submatches":[{"match":{"text":","},"start":1,"end":2}]}}"#, "\n",
+        );
+        let options = SearchOptions {
+            byte_ranges: true,
+            max_submatches_per_line: Some(2),
+            ..search_options(",")
+        };
+        let result = searcher.parse_only(&options, stdout.as_bytes()).unwrap();
+        
+        // The first line keeps its first two commas; the second is untouched
+        let ranges: Vec<_> = result
+            .structured_matches
+            .unwrap()
+            .iter()
+            .map(|m| (m.line_number, m.start_byte))
             .collect();
+        assert_eq!(ranges, vec![(Some(1), Some(1)), (Some(1), Some(3)), (Some(2), Some(9))]);
+        assert_eq!(result.stats.capped_lines, 1);
+    }
+    
+    #[test]
+    fn test_density_report() {
+        let temp_dir = TempDir::new().unwrap();
+        let small = temp_dir.path().join("small.rs");
+        let large = temp_dir.path().join("large.rs");
+        write_file(temp_dir.path(), "small.rs", "todo\ntodo\nok\nok\n");
+        write_file(temp_dir.path(), "large.rs", &"todo\n".repeat(97));
         
-        // Create a copy of matches.len() before moving matches
-        let matched_lines = matches.len();
+        // Canned output: two matches in the 4-line file, three in the 97-line one
+        let event = |path: &Path, line: u64| {
+            format!(
+                r#"{{"type":"match","data":{{"path":{{"text":"{}"}},"lines":{{"text":"todo\n"}},"line_number":{},"absolute_offset":0,"submatches":[{{"match":{{"text":"todo"}},"start":0,"end":4}}]}}}}"#,
+                path.display(),
+                line,
+            )
+        };
+        let stdout = [event(&large, 1), event(&large, 2), event(&large, 3), event(&small, 1), event(&small, 2)].join("\n");
         
-        Ok(SearchResult {
-            matches,
-            stats: SearchStats {
-                matched_lines,
-                elapsed_ms: elapsed.as_millis() as u64,
-            },
-        })
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        let options = SearchOptions {
+            density_report: true,
+            ..search_options("todo")
+        };
+        let result = searcher.parse_only(&options, stdout.as_bytes()).unwrap();
+        assert!(result.matches.is_empty());
+        
+        // Fewer matches, but far denser, so the small file ranks first
+        let density = result.density.unwrap();
+        let ranked: Vec<_> = density.iter().map(|d| (d.path.clone(), d.match_count, d.line_count)).collect();
+        assert_eq!(ranked, vec![
+            (small.display().to_string(), 2, 4),
+            (large.display().to_string(), 3, 97),
+        ]);
+        assert_eq!(density[0].density, 0.5);
     }
     
-    async fn build_command(&self, options: &SearchOptions, search_path: &Path) -> Result<std::process::Output, AppError> {
-        let mut cmd = TokioCommand::new("rg");
+    #[test]
+    fn test_include_prev_line() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = temp_dir.path().join("app.ini");
+        write_file(temp_dir.path(), "app.ini", "[server]\nport = 80\n\n[admin]\nport = 81\n");
         
-        // Configure output format
-        cmd.arg("--no-config"); // Ignore user config files
+        let event = |line: u64, text: &str| {
+            format!(
+                r#"{{"type":"match","data":{{"path":{{"text":"{}"}},"lines":{{"text":"{}\n"}},"line_number":{},"absolute_offset":0,"submatches":[]}}}}"#,
+                config.display(),
+                text,
+                line,
+            )
+        };
+        let stdout = [event(1, "[server]"), event(2, "port = 80"), event(5, "port = 81")].join("\n");
         
-        if options.fixed_strings {
-            cmd.arg("-F"); // Fixed strings mode
-        }
+        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        let options = SearchOptions {
+            include_prev_line: true,
+            ..search_options("server|port")
+        };
+        let structured = searcher.parse_only(&options, stdout.as_bytes()).unwrap().structured_matches.unwrap();
+        let labels: Vec<_> = structured.iter().map(|m| m.prev_line.as_deref()).collect();
+        assert_eq!(labels, vec![None, Some("[server]"), Some("[admin]")]);
+    }
+    
+    #[test]
+    fn test_include_indent() {
+        let event = |line: u64, text: &str| {
+            format!(
+                r#"{{"type":"match","data":{{"path":{{"text":"/root/a.rs"}},"lines":{{"text":"{}\n"}},"line_number":{},"absolute_offset":0,"submatches":[]}}}}"#,
+                text,
+                line,
+            )
+        };
+        let stdout = [event(1, "fn call() {"), event(3, "        call();"), event(7, "\\t\\t call();")].join("\n");
         
-        if !options.case_sensitive {
-            cmd.arg("-i"); // Case insensitive
-        }
+        let searcher = RipgrepSearcher::new(PathBuf::from("/root"));
+        let options = SearchOptions {
+            include_indent: true,
+            ..search_options("call")
+        };
+        let structured = searcher.parse_only(&options, stdout.as_bytes()).unwrap().structured_matches.unwrap();
+        let indents: Vec<_> = structured.iter().map(|m| m.indent).collect();
         
-        if options.line_numbers {
-            cmd.arg("-n"); // Line numbers
-        }
+        // The nested call is indented deeper than the top-level definition
+        assert_eq!(indents, vec![Some(0), Some(8), Some(9)]);
         
-        // Add context lines if specified
-        if let Some(context) = options.context_lines {
-            cmd.arg("-C").arg(context.to_string());
-        }
+        // Tabs count for the configured width
+        let options = SearchOptions {
+            tab_width: Some(2),
+            ..options
+        };
+        let structured = searcher.parse_only(&options, stdout.as_bytes()).unwrap().structured_matches.unwrap();
+        assert_eq!(structured[2].indent, Some(5));
+        assert!(structured[2].full_line.starts_with('\t'));
+    }
+    
+    #[test]
+    fn test_guess_mime() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "notes", "plain words\n");
+        write_file(temp_dir.path(), "blob", "a\0b");
         
-        // Add file types if specified
-        for file_type in &options.file_types {
-            cmd.arg("-t").arg(file_type);
-        }
+        assert_eq!(guess_mime(Path::new("/r/main.rs")), "text/x-rust");
+        assert_eq!(guess_mime(Path::new("/r/README.MD")), "text/markdown");
+        assert_eq!(guess_mime(Path::new("/r/lib.so")), "application/octet-stream");
         
-        // Add max depth if specified
-        if let Some(depth) = options.max_depth {
-            cmd.arg("--max-depth").arg(depth.to_string());
-        }
+        // Unknown extensions fall back to sniffing the contents
+        assert_eq!(guess_mime(&temp_dir.path().join("notes")), "text/plain");
+        assert_eq!(guess_mime(&temp_dir.path().join("blob")), "application/octet-stream");
+    }
+    
+    #[test]
+    fn test_partition_comments() {
+        let searcher = RipgrepSearcher::new(PathBuf::from("/root"));
+        let event = |path: &str, line: u64, text: &str, start: usize| {
+            format!(
+                r#"{{"type":"match","data":{{"path":{{"text":"{}"}},"lines":{{"text":"{}\n"}},"line_number":{},"absolute_offset":0,"submatches":[{{"match":{{"text":"retry"}},"start":{},"end":{}}}]}}}}"#,
+                path, text, line, start, start + 5,
+            )
+        };
+        let stdout = [
+            event("/root/a.rs", 1, "// retry on failure", 3),
+            event("/root/a.rs", 2, "    retry(3);", 4),
+            event("/root/a.rs", 3, "    x(); /* retry */", 12),
+            event("/root/a.rs", 4, "     * retry later", 7),
+            event("/root/b.py", 5, "retry()  # retry", 0),
+            event("/root/b.py", 6, "# retry", 2),
+        ]
+        .join("\n");
         
-        // Add pattern and path
-        cmd.arg(&options.pattern);
-        cmd.arg(search_path);
+        let options = SearchOptions {
+            partition_comments: true,
+            ..search_options("retry")
+        };
+        let result = searcher.parse_only(&options, stdout.as_bytes()).unwrap();
+        assert!(result.matches.is_empty());
         
-        // Execute the command
-        let output = cmd.output().await
-            .map_err(|e| AppError::RipgrepError(format!("Failed to execute ripgrep: {}", e)))?;
-            
-        // Check if the command was successful
-        // Note: ripgrep returns status code 1 when no matches found, which is not an error
-        if !output.status.success() && output.status.code() != Some(1) {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            error!(%stderr, "Ripgrep command failed");
-            return Err(AppError::RipgrepError(format!("Ripgrep failed: {}", stderr)));
-        }
+        let partition = result.comment_partition.unwrap();
+        assert_eq!(partition.in_comments, vec![
+            "a.rs:1:// retry on failure",
+            "a.rs:3:    x(); /* retry */",
+            "a.rs:4:     * retry later",
+            "b.py:6:# retry",
+        ]);
+        assert_eq!(partition.in_code, vec!["a.rs:2:    retry(3);", "b.py:5:retry()  # retry"]);
+    }
+    
+    #[test]
+    fn test_file_type_index() {
+        let index = FileTypeIndex::parse(concat!(
+            "c: *.[chH], *.[chH].in, *.cats, *.h\n",
+            "cpp: *.[ChH], *.cc, *.h, *.hpp\n",
+            "make: *.mak, *.mk, GNUmakefile, Makefile, [Gg][Nn][Uu]makefile\n",
+            "rust: *.rs\n",
+        ));
+        assert!(index.names.contains("cpp"));
+        assert_eq!(index.type_of(Path::new("/r/src/main.rs")), Some("rust"));
+        assert_eq!(index.type_of(Path::new("/r/Makefile")), Some("make"));
         
-        Ok(output)
+        // A glob shared by several types goes to the first listed
+        assert_eq!(index.type_of(Path::new("/r/x.h")), Some("c"));
+        assert_eq!(index.type_of(Path::new("/r/notes")), None);
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::TempDir;
-    use std::fs::File;
-    use std::io::Write;
     
-    fn setup_test_files() -> TempDir {
-        let temp_dir = TempDir::new().unwrap();
+    #[test]
+    fn test_match_snippet() {
+        let line = "the quick brown fox jumps over the lazy dog\n";
+        let snippet = |before_chars, after_chars| SnippetOptions {
+            before_chars,
+            after_chars,
+            ellipsis: "...".to_string(),
+        };
         
-        // Create a test file
-        let file_path = temp_dir.path().join("test_file.rs");
-        let mut file = File::create(file_path).unwrap();
-        writeln!(file, "fn hello_world() {{").unwrap();
-        writeln!(file, "    println!(\"Hello, world!\");").unwrap();
-        writeln!(file, "}}").unwrap();
-        writeln!(file, "").unwrap();
-        writeln!(file, "fn search_function(query: &str) {{").unwrap();
-        writeln!(file, "    println!(\"Searching for {{}}\", query);").unwrap();
-        writeln!(file, "}}").unwrap();
+        // "fox" at bytes 16..19, cut on both sides
+        assert_eq!(match_snippet(line, 16, 19, &snippet(6, 6)), "...brown fox jumps...");
         
-        // Create another file with different content
-        let file_path = temp_dir.path().join("test_file.js");
-        let mut file = File::create(file_path).unwrap();
-        writeln!(file, "function helloWorld() {{").unwrap();
-        writeln!(file, "    console.log(\"Hello, world!\");").unwrap();
-        writeln!(file, "}}").unwrap();
+        // Budgets reaching the line boundaries need no markers
+        assert_eq!(match_snippet(line, 16, 19, &snippet(16, 100)), "the quick brown fox jumps over the lazy dog");
+        assert_eq!(match_snippet(line, 0, 3, &snippet(5, 4)), "the qui...");
+        assert_eq!(match_snippet(line, 40, 43, &snippet(4, 5)), "...azy dog");
         
-        temp_dir
+        // The default marker is a single ellipsis character
+        let options: SnippetOptions = serde_json::from_str(r#"{"before_chars":2,"after_chars":0}"#).unwrap();
+        assert_eq!(match_snippet(line, 16, 19, &options), "…n fox…");
     }
     
-    #[tokio::test]
-    async fn test_basic_search() {
-        let temp_dir = setup_test_files();
+    #[test]
+    fn test_sample() {
+        let searcher = RipgrepSearcher::new(PathBuf::from("/root"));
+        let lines: Vec<String> = (0..10)
+            .flat_map(|file| (1..=50).map(move |line| format!("/root/src/file{}.rs:{}:let x = {};", file, line, line)))
+            .collect();
+        let options = SearchOptions {
+            sample: Some(20),
+            ..search_options("let")
+        };
+        
+        let result = searcher.parse_only(&options, lines.join("\n").as_bytes()).unwrap();
+        assert_eq!(result.matches.len(), 20);
+        assert_eq!(result.stats.matched_lines, 500);
+        
+        // The same sample whatever order ripgrep printed the lines in
+        let reversed: Vec<&str> = lines.iter().rev().map(String::as_str).collect();
+        let again = searcher.parse_only(&options, reversed.join("\n").as_bytes()).unwrap();
+        assert_eq!(again.matches, result.matches);
+        
+        // Drawn from across the files rather than the first one
+        let files: HashSet<&str> = result.matches.iter().filter_map(|m| m.split(':').next()).collect();
+        assert!(files.len() >= 5, "sample came from only {} files", files.len());
+        
+        // A result no larger than the sample is returned whole
+        let small = searcher.parse_only(&options, lines[..5].join("\n").as_bytes()).unwrap();
+        assert_eq!(small.matches, lines[..5].to_vec());
+    }
+    
+    #[test]
+    fn test_parse_search_stats() {
+        let searcher = RipgrepSearcher::new(PathBuf::from("/root"));
+        let stdout = concat!(
+            "/root/a.rs:1:let x = 1;\n",
+            "/root/b.rs:3:let y = 2;\n",
+            "\n",
+            "2 matches\n",
+            "2 matched lines\n",
+            "2 files contained matches\n",
+            "1,234 files searched\n",
+            "40 bytes printed\n",
+            "0.000100 seconds spent searching\n",
+        );
+        
+        // The stats block is split off the matches; grouping separators are dropped
+        let result = searcher.parse_only(&search_options("let"), stdout.as_bytes()).unwrap();
+        assert_eq!(result.matches, vec!["/root/a.rs:1:let x = 1;", "/root/b.rs:3:let y = 2;"]);
+        assert_eq!(result.stats.matched_lines, 2);
+        assert_eq!(result.stats.files_searched, Some(1234));
+        assert_eq!(result.stats.files_with_matches, 2);
+        
+        let options = SearchOptions {
+            match_ratio: true,
+            ..search_options("let")
+        };
+        let ratio = searcher.parse_only(&options, stdout.as_bytes()).unwrap().stats.match_ratio.unwrap();
+        assert!((ratio - 2.0 / 1234.0).abs() < f64::EPSILON);
+        
+        // JSON output carries them in its summary event
+        let stdout = concat!(
+            r#"{"type":"match","data":{"path":{"text":"/root/a.rs"},"lines":{"text":"let x = 1;\n"},"line_number":1,"absolute_offset":0,"submatches":[{"match":{"text":"let"},"start":0,"end":3}]}}"#, "\n",
+            r#"{"type":"summary","data":{"elapsed_total":{"human":"0.01s","nanos":1,"secs":0},"stats":{"searches":7,"searches_with_match":1}}}"#, "\n",
+        );
+        let options = SearchOptions {
+            output_format: OutputFormat::Json,
+            ..search_options("let")
+        };
+        let result = searcher.parse_only(&options, stdout.as_bytes()).unwrap();
+        assert_eq!(result.stats.files_searched, Some(7));
+        assert_eq!(result.stats.files_with_matches, 1);
+        
+        // Output without stats, e.g. from a search stopped early, leaves the
+        // searched files unknown and counts the matched ones it holds
+        let options = SearchOptions {
+            match_ratio: true,
+            ..search_options("let")
+        };
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "a.rs", "let x = 1;\n");
+        let stdout = format!("{}:1:let x = 1;\n", temp_dir.path().join("a.rs").display());
+        let result = searcher.parse_only(&options, stdout.as_bytes()).unwrap();
+        assert_eq!((result.stats.files_searched, result.stats.files_with_matches), (None, 1));
+        assert_eq!(result.stats.match_ratio, None);
+    }
+    
+    #[test]
+    fn test_count_text_matches() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "a-1-b.rs", "let x = 1;\ny:3:\n");
         let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+        let options = SearchOptions {
+            context_lines: Some(1),
+            ..search_options("let")
+        };
+        
+        // Without a stats block (a stopped search) the separators decide,
+        // even for paths and text that hold dashes and digits
+        let stdout = "{0}:1:let x = 1;\n{0}-2-y:3:\n--\n{0}:9:let z;\n"
+            .replace("{0}", &temp_dir.path().join("a-1-b.rs").to_string_lossy());
+        let result = searcher.parse_only(&options, stdout.as_bytes()).unwrap();
+        assert_eq!(result.matches.len(), 4);
+        assert_eq!(result.stats.matched_lines, 2);
         
         let options = SearchOptions {
-            pattern: "hello".into(),
-            path: "".into(),
-            fixed_strings: true,
-            case_sensitive: false,
-            line_numbers: true,
-            context_lines: None,
-            file_types: vec![],
-            max_depth: None,
+            null_output: true,
+            ..options
+        };
+        let stdout = "/root/a.rs\x001:let x = 1;\n/root/a.rs\x002-y:3:\n--\n";
+        assert_eq!(searcher.parse_only(&options, stdout.as_bytes()).unwrap().stats.matched_lines, 1);
+    }
+    
+    #[test]
+    fn test_filter_output() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(temp_dir.path(), "a.rs", "");
+        write_file(temp_dir.path(), "b.txt", "");
+        write_file(temp_dir.path(), "c-1-d.rs", "");
+        let path = |name: &str| temp_dir.path().join(name).to_string_lossy().into_owned();
+        let filter = FileFilter {
+            allowed_extensions: Some(vec!["rs".to_string()]),
+            ..FileFilter::default()
         };
         
-        let result = searcher.search(&options).await.unwrap();
-        assert!(result.matches.len() >= 2); // Should find "hello" in both files
+        // The rejected file's group goes, with a single separator left
+        // between the kept ones and the stats that no longer add up
+        let options = SearchOptions {
+            context_lines: Some(1),
+            ..search_options("x")
+        };
+        let stdout = format!(
+            "{a}:1:x\n{a}-2-y\n--\n{b}:5:x\n{b}-6-y:1:\n--\n{c}-3-y\n{c}:4:x\n\n4 matched lines\n3 files searched\n",
+            a = path("a.rs"),
+            b = path("b.txt"),
+            c = path("c-1-d.rs"),
+        );
+        assert_eq!(
+            filter_output(stdout, &filter, &options),
+            (format!("{a}:1:x\n{a}-2-y\n--\n{c}-3-y\n{c}:4:x\n", a = path("a.rs"), c = path("c-1-d.rs")), true)
+        );
         
-        // Test with file type filter
+        // Without anything to drop the output is untouched
+        let stdout = format!("{}:1:x\n\n1 matched lines\n", path("a.rs"));
+        assert_eq!(filter_output(stdout.clone(), &filter, &options), (stdout, false));
+        
+        // The mtime window applies the same way
+        let now = SystemTime::now();
+        let window = FileFilter {
+            newer_than: Some(now - Duration::from_secs(3600)),
+            older_than: Some(now + Duration::from_secs(3600)),
+            ..FileFilter::default()
+        };
+        assert!(window.keeps(Path::new(&path("b.txt"))));
+        let stale = FileFilter {
+            older_than: Some(now - Duration::from_secs(3600)),
+            ..FileFilter::default()
+        };
+        assert!(!stale.keeps(Path::new(&path("b.txt"))));
+        assert!(!stale.keeps(Path::new(&path("missing.txt"))));
+        
+        // JSON events are filtered by their path
         let options = SearchOptions {
-            pattern: "hello".into(),
-            path: "".into(),
-            fixed_strings: true,
-            case_sensitive: false,
-            line_numbers: true,
+            output_format: OutputFormat::Json,
+            ..search_options("x")
+        };
+        let event = |kind: &str, name: &str| format!(r#"{{"type":"{}","data":{{"path":{{"text":"{}"}}}}}}"#, kind, path(name));
+        let summary = r#"{"type":"summary","data":{"stats":{"matched_lines":2}}}"#;
+        let stdout = [event("begin", "a.rs"), event("begin", "b.txt"), event("end", "b.txt"), summary.to_string()].join("\n");
+        assert_eq!(filter_output(stdout, &filter, &options), (event("begin", "a.rs") + "\n", true));
+    }
+    
+    #[test]
+    fn test_text_lines_from_events() {
+        let searcher = RipgrepSearcher::new(PathBuf::from("/root"));
+        let stdout = concat!(
+            r#"{"type":"begin","data":{"path":{"text":"/root/a.rs"}}}"#, "\n",
+            r#"{"type":"match","data":{"path":{"text":"/root/a.rs"},"lines":{"text":"needle\n"},"line_number":1,"absolute_offset":0,"submatches":[{"match":{"text":"needle"},"start":0,"end":6}]}}"#, "\n",
+            r#"{"type":"context","data":{"path":{"text":"/root/a.rs"},"lines":{"text":"after\n"},"line_number":2,"absolute_offset":7,"submatches":[]}}"#, "\n",
+            r#"{"type":"context","data":{"path":{"text":"/root/a.rs"},"lines":{"text":"before\n"},"line_number":8,"absolute_offset":40,"submatches":[]}}"#, "\n",
+            r#"{"type":"match","data":{"path":{"text":"/root/a.rs"},"lines":{"text":"a needle\n"},"line_number":9,"absolute_offset":47,"submatches":[{"match":{"text":"needle"},"start":2,"end":8}]}}"#, "\n",
+            r#"{"type":"end","data":{"path":{"text":"/root/a.rs"}}}"#, "\n",
+        );
+        let options = SearchOptions {
+            first_result_fast: true,
+            context_lines: Some(1),
+            ..search_options("needle")
+        };
+        let result = searcher.parse_only(&options, stdout.as_bytes()).unwrap();
+        assert_eq!(
+            result.matches,
+            vec!["/root/a.rs:1:needle", "/root/a.rs-2-after", "--", "/root/a.rs-8-before", "/root/a.rs:9:a needle"]
+        );
+        assert_eq!(result.stats.matched_lines, 2);
+        
+        // Columns and byte offsets follow the line number, as ripgrep prints
+        // them, and only output with context separates groups
+        let options = SearchOptions {
+            columns: true,
+            byte_offset: true,
             context_lines: None,
-            file_types: vec!["rs".into()],
-            max_depth: None,
+            ..options
+        };
+        let result = searcher.parse_only(&options, stdout.as_bytes()).unwrap();
+        assert_eq!(result.matches[3], "/root/a.rs:9:3:47:a needle");
+        assert_eq!(result.matches[1], "/root/a.rs-2-7-after");
+    }
+    
+    #[test]
+    fn test_allowlist_args() {
+        assert_eq!(
+            allowlist_args(&["rs".to_string(), "h5".to_string()]),
+            vec!["--type-add", "allowlisted:*.[rR][sS]", "--type-add", "allowlisted:*.[hH]5", "-t", "allowlisted"]
+        );
+    }
+    
+    #[test]
+    fn test_merge_context_same_path_twice() {
+        let searcher = RipgrepSearcher::new(PathBuf::from("/root"));
+        let pass = concat!(
+            r#"{"type":"begin","data":{"path":{"text":"/root/a.txt"}}}"#, "\n",
+            r#"{"type":"match","data":{"path":{"text":"/root/a.txt"},"lines":{"text":"needle\n"},"line_number":1,"absolute_offset":0,"submatches":[{"match":{"text":"needle"},"start":0,"end":6}]}}"#, "\n",
+            r#"{"type":"context","data":{"path":{"text":"/root/a.txt"},"lines":{"text":"after\n"},"line_number":2,"absolute_offset":7,"submatches":[]}}"#, "\n",
+            r#"{"type":"end","data":{"path":{"text":"/root/a.txt"},"binary_offset":null,"stats":{}}}"#, "\n",
+        );
+        let options = SearchOptions {
+            merge_context: true,
+            ..search_options("needle")
         };
         
-        let result = searcher.search(&options).await.unwrap();
-        assert_eq!(result.matches.len(), 1); // Should only find in Rust file
+        // A file listed twice is searched twice; each pass is its own block
+        let stdout = format!("{}{}", pass, pass);
+        let blocks = searcher.parse_only(&options, stdout.as_bytes()).unwrap().context_blocks.unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks.iter().all(|b| b.lines == vec!["needle", "after"]));
+    }
+    
+    #[test]
+    fn test_case_mode_options() {
+        let parse = |value: serde_json::Value| serde_json::from_value::<SearchOptions>(value).unwrap().case();
+        
+        // The existing flag keeps working, insensitive by default
+        assert_eq!(parse(serde_json::json!({ "pattern": "x" })), CaseMode::Insensitive);
+        assert_eq!(parse(serde_json::json!({ "pattern": "x", "case_sensitive": true })), CaseMode::Sensitive);
+        
+        // An explicit mode wins over the flag
+        assert_eq!(parse(serde_json::json!({ "pattern": "x", "case_mode": "smart" })), CaseMode::Smart);
+        assert_eq!(
+            parse(serde_json::json!({ "pattern": "x", "case_sensitive": true, "case_mode": "insensitive" })),
+            CaseMode::Insensitive
+        );
+        assert!(serde_json::from_value::<SearchOptions>(serde_json::json!({ "pattern": "x", "case_mode": "loud" })).is_err());
+    }
+    
+    #[test]
+    fn test_pcre2_unavailable() {
+        assert!(pcre2_unavailable("PCRE2 is not available in this build of ripgrep.\n"));
+        assert!(!pcre2_unavailable("rg: regex parse error:\n    look-around, including look-ahead and look-behind, is not supported\n"));
     }
     
     #[tokio::test]
-    async fn test_path_traversal_prevention() {
-        let temp_dir = setup_test_files();
-        let searcher = RipgrepSearcher::new(temp_dir.path().to_path_buf());
+    async fn test_replacement_hunks() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        write_file(root, "a.txt", "one\ntwo\nthree\nfour\nfive\nsix\nseven");
+        let path = root.join("a.txt").to_string_lossy().into_owned();
         
+        // Distant changes get separate hunks; unchanged replacements are dropped
+        let stdout = format!("{0}:1:ONE\n{0}:4:four\n{0}:7:SEVEN\n", path);
         let options = SearchOptions {
-            pattern: "hello".into(),
-            path: "../../../etc/passwd".into(), // Attempt path traversal
-            fixed_strings: true,
-            case_sensitive: false,
-            line_numbers: true,
-            context_lines: None,
-            file_types: vec![],
-            max_depth: None,
+            context_lines: Some(1),
+            ..search_options("")
         };
+        let hunks = replacement_hunks(&stdout, root, &options).await;
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(hunks[0].diff, "--- a/a.txt\n+++ b/a.txt\n@@ -1,2 +1,2 @@\n-one\n+ONE\n two\n");
         
-        let result = searcher.search(&options).await;
-        assert!(result.is_err());
-        match result {
-            Err(AppError::PathTraversal(_)) => {}
-            _ => panic!("Expected PathTraversal error"),
-        }
+        // The last line has no terminator on either side
+        assert_eq!(
+            hunks[1].diff,
+            "--- a/a.txt\n+++ b/a.txt\n@@ -6,2 +6,2 @@\n six\n-seven\n\\ No newline at end of file\n\
+             +SEVEN\n\\ No newline at end of file\n"
+        );
+        
+        // CRLF lines keep their `\r` on every side of the diff
+        write_file(root, "crlf.txt", "one\r\ntwo\r\nthree\r\n");
+        let path = root.join("crlf.txt").to_string_lossy().into_owned();
+        let hunks = replacement_hunks(&format!("{}:2:TWO\r\n", path), root, &options).await;
+        assert_eq!(
+            hunks[0].diff,
+            "--- a/crlf.txt\n+++ b/crlf.txt\n@@ -1,3 +1,3 @@\n one\r\n-two\r\n+TWO\r\n three\r\n"
+        );
+    }
+    
+    #[test]
+    fn test_permission_denied_path() {
+        let stderr = "rg: ./secret: Permission denied (os error 13)\nrg: ./other: Permission denied (os error 13)\n";
+        assert_eq!(permission_denied_path(stderr), Some("./secret".to_string()));
+        assert_eq!(permission_denied_path("rg: regex parse error\n"), None);
+    }
+    
+    #[test]
+    fn test_match_window_boundaries() {
+        // Window is clipped at both ends of the line
+        assert_eq!(match_window("foo bar\n", 0, 3, 10), "foo bar");
+        assert_eq!(match_window("a needle b", 2, 8, 1), " needle ");
+        assert_eq!(match_window("a needle b", 2, 8, 0), "needle");
+        
+        // Multi-byte characters count as one character each
+        let line = "héé needle ñññ";
+        let start = line.find("needle").unwrap();
+        assert_eq!(match_window(line, start, start + 6, 2), "é needle ñ");
+    }
+    
+    #[test]
+    fn test_smart_truncate() {
+        let line = "the quick brown fox jumps over the lazy dog near the riverbank today";
+        let start = line.find("lazy").unwrap();
+        
+        // A plain window cuts words in half
+        assert_eq!(match_window(line, start, start + 4, 12), "ps over the lazy dog near th");
+        
+        // The smart window snaps both cuts to whole words
+        let window = smart_match_window(line, start, start + 4, 12);
+        assert_eq!(window, "…over the lazy dog near…");
+        let inner = window.trim_matches('…');
+        let words: Vec<&str> = line.split(' ').collect();
+        assert!(inner.split(' ').all(|word| words.contains(&word)));
+        
+        // Untruncated sides get no marker
+        assert_eq!(smart_match_window("lazy dog", 0, 4, 20), "lazy dog");
+        
+        // A single long token keeps its plain cut
+        let token = "x_aaaaaaaaaaaa_needle_bbbbbbbbbbbb";
+        let start = token.find("needle").unwrap();
+        assert_eq!(smart_match_window(token, start, start + 6, 3), "…aa_needle_bb…");
     }
 }
\ No newline at end of file
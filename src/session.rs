@@ -0,0 +1,53 @@
+use std::sync::Mutex;
+use std::time::Duration;
+use crate::error::AppError;
+
+/// Wall time spent searching on behalf of one client session. The stdio
+/// transport serves a single session, so the searcher holds one. The HTTP
+/// transport has no sessions of its own, so all its clients share the
+/// searcher's budget: it caps the server's total search time until restart.
+#[derive(Debug, Default)]
+pub struct SessionBudget {
+    used: Mutex<Duration>,
+}
+
+impl SessionBudget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Fail once the session has used up `budget`; `None` means unlimited
+    pub fn check(&self, budget: Option<Duration>) -> Result<(), AppError> {
+        match budget {
+            Some(budget) if *self.used.lock().unwrap() >= budget => {
+                Err(AppError::SessionBudgetExhausted(budget.as_secs_f64()))
+            }
+            _ => Ok(()),
+        }
+    }
+    
+    /// Add the wall time of a finished search
+    pub fn charge(&self, elapsed: Duration) {
+        *self.used.lock().unwrap() += elapsed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_session_budget() {
+        let session = SessionBudget::new();
+        let budget = Some(Duration::from_millis(100));
+        
+        // Searches may start until the total reaches the budget
+        session.charge(Duration::from_millis(60));
+        assert!(session.check(budget).is_ok());
+        session.charge(Duration::from_millis(40));
+        assert!(matches!(session.check(budget), Err(AppError::SessionBudgetExhausted(_))));
+        
+        // Without a budget nothing is ever rejected
+        assert!(session.check(None).is_ok());
+    }
+}
\ No newline at end of file